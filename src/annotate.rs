@@ -0,0 +1,130 @@
+extern crate colored;
+
+use std::path::PathBuf;
+
+use journaling::journal::Journal;
+use journaling::managed_file_journal::{ManagedFileJournal, ManagedFileJournalError};
+use journaling::managed_file::{ManagedFileError, SnapshotLabels};
+
+use self::colored::*;
+
+pub struct AnnotateError;
+
+impl From<ManagedFileJournalError> for AnnotateError {
+    fn from(_: ManagedFileJournalError) -> Self { AnnotateError }
+}
+
+impl From<ManagedFileError> for AnnotateError {
+    fn from(_: ManagedFileError) -> Self { AnnotateError }
+}
+
+/// Changes to make to the details of a snapshot
+pub struct Annotation {
+    pub comment: Option<String>,
+    pub added_labels: SnapshotLabels,
+    pub removed_tags: Vec<String>,
+    pub removed_meta_keys: Vec<String>
+}
+
+impl Annotation {
+    fn is_empty(&self) -> bool {
+        self.comment.is_none() && self.added_labels.is_empty() && self.removed_tags.is_empty() &&
+            self.removed_meta_keys.is_empty()
+    }
+}
+
+/// Changes the comment, tags or metadata of a snapshot
+pub fn annotate(
+    journal: &mut Journal, snapshot_name: String, annotation: Annotation, file_path: PathBuf
+) -> Result<(), AnnotateError> {
+    let managed_file_journal = ManagedFileJournal::for_journal(journal);
+    let file_path_string = file_path.to_owned().into_os_string().into_string()
+        .expect("Could not read the file path, it might contain invalid characters");
+
+    if annotation.is_empty() {
+        println!("{} Nothing to change, give a --comment or tags and metadata to add or remove", "ERROR".red());
+        return Err(AnnotateError);
+    }
+
+    let mut managed_file = match managed_file_journal.get_managed_file(&file_path)? {
+        Some(managed_file) => managed_file,
+        None => {
+            println!("{} {}, file not managed by {}", "ERROR".red(), file_path_string, "hippo".magenta());
+            return Err(AnnotateError);
+        }
+    };
+
+    if managed_file.get_snapshot(&snapshot_name).is_none() {
+        println!("{} {}, no snapshot named {}", "ERROR".red(), file_path_string, snapshot_name.cyan());
+        return Err(AnnotateError);
+    }
+
+    managed_file.update_snapshot(&snapshot_name, |snapshot_entry| {
+        if let Some(comment) = annotation.comment {
+            snapshot_entry.comment = comment;
+        }
+
+        for tag in &annotation.removed_tags {
+            snapshot_entry.tags.remove(tag);
+        }
+
+        for key in &annotation.removed_meta_keys {
+            snapshot_entry.meta.remove(key);
+        }
+
+        snapshot_entry.tags.extend(annotation.added_labels.tags);
+        snapshot_entry.meta.extend(annotation.added_labels.meta);
+    })?;
+
+    println!("{} Updated snapshot {} of {}", "OK".green().bold(), snapshot_name.cyan(), file_path_string.bold());
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::write;
+
+    use journaling::managed_file::SnapshotLabels;
+    use journaling::managed_file_journal::ManagedFileJournal;
+    use snap::{snap, SnapOptions};
+    use snap::tests::snap_options;
+    use utils::test_dir::TestDir;
+
+    use super::{annotate, Annotation};
+
+    #[test]
+    fn comment_and_labels_are_changed_in_place() {
+        let test_dir = TestDir::new("annotate");
+        let file_path = test_dir.join("app.conf");
+
+        write(&file_path, "contents\n").unwrap();
+        let mut journal = test_dir.open_store();
+        let options = snap_options("v1");
+        let options = SnapOptions {
+            labels: SnapshotLabels::parse(vec![String::from("draft")], vec![String::from("ticket=OPS-1")]).unwrap(),
+            ..options
+        };
+        assert!(snap(&mut journal, options, vec![file_path.to_owned()]).is_ok());
+
+        let annotation = Annotation {
+            comment: Some(String::from("Known good")),
+            added_labels: SnapshotLabels::parse(vec![String::from("stable")], vec![String::from("owner=ops")]).unwrap(),
+            removed_tags: vec![String::from("draft")],
+            removed_meta_keys: vec![String::from("ticket")]
+        };
+        assert!(annotate(&mut journal, String::from("v1"), annotation, file_path.to_owned()).is_ok());
+
+        let nothing = Annotation {
+            comment: None, added_labels: SnapshotLabels::default(), removed_tags: Vec::new(), removed_meta_keys: Vec::new()
+        };
+        assert!(annotate(&mut journal, String::from("v1"), nothing, file_path.to_owned()).is_err());
+
+        let managed_file_journal = ManagedFileJournal::for_journal(&mut journal);
+        let managed_file = managed_file_journal.get_managed_file(&file_path).ok().unwrap().unwrap();
+        let snapshot_entry = managed_file.get_snapshot("v1").unwrap();
+
+        assert_eq!(snapshot_entry.comment, "Known good");
+        assert_eq!(snapshot_entry.labels(), vec!["stable", "owner=ops"]);
+    }
+}
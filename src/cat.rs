@@ -0,0 +1,64 @@
+extern crate colored;
+extern crate pretty_bytes;
+
+use std::path::PathBuf;
+use std::io;
+use std::io::Write;
+
+use journaling::journal::Journal;
+use journaling::managed_file_journal::{ManagedFileJournal, ManagedFileJournalError};
+use journaling::managed_file::ManagedFileError;
+
+use self::colored::*;
+use self::pretty_bytes::converter::convert;
+
+pub struct CatError;
+
+impl From<ManagedFileJournalError> for CatError {
+    fn from(_: ManagedFileJournalError) -> Self { CatError }
+}
+
+impl From<ManagedFileError> for CatError {
+    fn from(_: ManagedFileError) -> Self { CatError }
+}
+
+impl From<io::Error> for CatError {
+    fn from(_: io::Error) -> Self { CatError }
+}
+
+/// Writes the contents of a snapshot to standard output.
+pub fn cat(journal: &mut Journal, snapshot_name: String, file_path: PathBuf) -> Result<(), CatError> {
+    let managed_file_journal = ManagedFileJournal::for_journal(journal);
+    let file_path_string = file_path.to_owned().into_os_string().into_string()
+        .expect("Could not read the file path, it might contain invalid characters");
+
+    let managed_file = match managed_file_journal.get_managed_file(&file_path)? {
+        Some(managed_file) => managed_file,
+        None => {
+            eprintln!("{} {}, file not managed by {}", "ERROR".red(), file_path_string, "hippo".magenta());
+            return Err(CatError);
+        }
+    };
+
+    match managed_file.read_snapshot_files_by_name(&snapshot_name)? {
+        // A single file is written out as is, while a directory is listed with the size of
+        // every file in it
+        Some(files) => {
+            match files.get("") {
+                Some(content) if files.len() == 1 => io::stdout().write_all(content)?,
+                _ => {
+                    for (relative_path, content) in &files {
+                        println!("{}\t{}", convert(content.len() as f64), relative_path);
+                    }
+                }
+            }
+
+            Ok(())
+        }
+
+        None => {
+            eprintln!("{} {}, no snapshot named {}", "ERROR".red(), file_path_string, snapshot_name.cyan());
+            Err(CatError)
+        }
+    }
+}
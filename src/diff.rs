@@ -0,0 +1,130 @@
+extern crate colored;
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::PathBuf;
+
+use journaling::journal::Journal;
+use journaling::managed_file_journal::{ManagedFileJournal, ManagedFileJournalError};
+use journaling::managed_file::{ManagedFile, ManagedFileError, LIVE_SNAPSHOT_NAME};
+use utils::line_diff::{diff_lines, hunks, split_lines, DiffLine};
+
+use self::colored::*;
+
+static DIFF_CONTEXT_LINES: usize = 3;
+
+pub struct DiffError;
+
+impl From<ManagedFileJournalError> for DiffError {
+    fn from(_: ManagedFileJournalError) -> Self { DiffError }
+}
+
+impl From<ManagedFileError> for DiffError {
+    fn from(_: ManagedFileError) -> Self { DiffError }
+}
+
+pub fn diff(
+    journal: &mut Journal, from_snapshot: String, to_snapshot: Option<String>, file_path: PathBuf
+) -> Result<(), DiffError> {
+    let managed_file_journal = ManagedFileJournal::for_journal(journal);
+    let file_path_string = file_path.to_owned().into_os_string().into_string()
+        .expect("Could not read the file path, it might contain invalid characters");
+    let to_snapshot = to_snapshot.unwrap_or_else(|| LIVE_SNAPSHOT_NAME.to_owned());
+
+    info!("Computing diff between {} and {} for {:?}", from_snapshot, to_snapshot, file_path);
+
+    let managed_file = match managed_file_journal.get_managed_file(&file_path)? {
+        Some(managed_file) => managed_file,
+        None => {
+            println!("{} {}, file not managed by {}", "ERROR".red(), file_path_string, "hippo".magenta());
+            return Err(DiffError);
+        }
+    };
+
+    let from_files = read_files(&managed_file, &file_path_string, &from_snapshot)?;
+    let to_files = read_files(&managed_file, &file_path_string, &to_snapshot)?;
+    let from_metadata = managed_file.read_snapshot_metadata_by_name(&from_snapshot)?.unwrap_or_default();
+    let to_metadata = managed_file.read_snapshot_metadata_by_name(&to_snapshot)?.unwrap_or_default();
+
+    // For directories, every file in either snapshot is diffed on its own, with a missing file
+    // treated as an empty one
+    let relative_paths: BTreeSet<&String> = from_files.keys().chain(to_files.keys())
+        .chain(from_metadata.keys()).chain(to_metadata.keys())
+        .collect();
+    let mut differences = false;
+
+    for relative_path in relative_paths {
+        let diff_path_string = if relative_path.is_empty() {
+            file_path_string.to_owned()
+        } else {
+            format!("{}/{}", file_path_string, relative_path)
+        };
+
+        let from_content = from_files.get(relative_path).map(|content| String::from_utf8_lossy(content)).unwrap_or_default();
+        let to_content = to_files.get(relative_path).map(|content| String::from_utf8_lossy(content)).unwrap_or_default();
+
+        let from_lines = split_lines(&from_content);
+        let to_lines = split_lines(&to_content);
+        let diff_hunks = hunks(diff_lines(&from_lines, &to_lines), DIFF_CONTEXT_LINES);
+
+        // Only compared when both sides recorded metadata. The modification time is left out
+        // when the contents changed, since that is implied.
+        if let (Some(from_metadata), Some(to_metadata)) = (from_metadata.get(relative_path), to_metadata.get(relative_path)) {
+            let metadata_changes = from_metadata.changes_to(to_metadata, diff_hunks.is_empty());
+
+            if !metadata_changes.is_empty() {
+                differences = true;
+                println!("{} {}, {}", "METADATA".yellow(), diff_path_string, metadata_changes.join(", "));
+            }
+        }
+
+        if diff_hunks.is_empty() {
+            continue;
+        }
+
+        differences = true;
+
+        println!("{}", format!("--- {} {}", diff_path_string, from_snapshot).bold());
+        println!("{}", format!("+++ {} {}", diff_path_string, to_snapshot).bold());
+
+        for hunk in diff_hunks {
+            println!("{}", format!("@@ -{},{} +{},{} @@",
+                                   hunk.old_start, hunk.old_len, hunk.new_start, hunk.new_len).cyan());
+
+            for line in hunk.lines {
+                let (text, newline_at_end) = match line.line().strip_suffix('\n') {
+                    Some(text) => (text, true),
+                    None => (line.line(), false)
+                };
+
+                match line {
+                    DiffLine::Context(_) => println!(" {}", text),
+                    DiffLine::Removed(_) => println!("{}", format!("-{}", text).red()),
+                    DiffLine::Added(_) => println!("{}", format!("+{}", text).green())
+                }
+
+                if !newline_at_end {
+                    println!("\\ No newline at end of file");
+                }
+            }
+        }
+    }
+
+    if !differences {
+        println!("{} {}, no differences between {} and {}", "OK".green(), file_path_string,
+                 from_snapshot.cyan(), to_snapshot.cyan());
+    }
+
+    Ok(())
+}
+
+fn read_files(
+    managed_file: &ManagedFile, file_path_string: &str, snapshot_name: &str
+) -> Result<BTreeMap<String, Vec<u8>>, DiffError> {
+    match managed_file.read_snapshot_files_by_name(snapshot_name)? {
+        Some(files) => Ok(files),
+        None => {
+            println!("{} {}, no snapshot named {}", "ERROR".red(), file_path_string, snapshot_name.cyan());
+            Err(DiffError)
+        }
+    }
+}
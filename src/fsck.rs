@@ -0,0 +1,407 @@
+extern crate chrono;
+extern crate colored;
+
+use std::collections::HashSet;
+use std::fs::{create_dir_all, read_dir, rename, OpenOptions};
+use std::io;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use journaling::journal::Journal;
+use journaling::managed_file_journal::{ManagedFileJournal, ManagedFileJournalError};
+use journaling::managed_file::{ManagedFile, ManagedFileError, NULL_SNAPSHOT_NAME};
+use journaling::object_store::PayloadStatus;
+use journaling::snapshot_set::SnapshotSet;
+use utils::simple_file_records::{MapsToSimpleRecord, RejectedLine, RejectionReason, SimpleFileRecords};
+
+use self::chrono::prelude::*;
+use self::colored::*;
+
+static QUARANTINE_DIR_NAME: &str = "__quarantine";
+static QUARANTINE_TIME_FORMAT: &str = "%Y%m%d-%H%M%S";
+
+pub struct FsckError;
+
+impl From<ManagedFileJournalError> for FsckError {
+    fn from(_: ManagedFileJournalError) -> Self { FsckError }
+}
+
+impl From<ManagedFileError> for FsckError {
+    fn from(_: ManagedFileError) -> Self { FsckError }
+}
+
+impl From<io::Error> for FsckError {
+    fn from(_: io::Error) -> Self { FsckError }
+}
+
+/// Anything removed from the store while repairing it is moved here instead of being deleted,
+/// so that it can still be inspected (or restored by hand) later.
+struct Quarantine {
+    dir: PathBuf
+}
+
+impl Quarantine {
+    fn new(journal_root: &Path) -> Quarantine {
+        Quarantine {
+            dir: journal_root
+                .join(QUARANTINE_DIR_NAME)
+                .join(Local::now().format(QUARANTINE_TIME_FORMAT).to_string())
+        }
+    }
+
+    /// Appends to a file named after `label`, which may be a record set name (and therefore contain
+    /// path separators).
+    fn append(&self, label: &str, content: &str) -> io::Result<()> {
+        create_dir_all(&self.dir)?;
+
+        let file_path = self.dir.join(label.replace('/', "%"));
+        let mut f = OpenOptions::new().create(true).append(true).open(file_path)?;
+        f.write_all(content.as_bytes())?;
+        f.write_all(b"\n")
+    }
+
+    fn lines(&self, label: &str, lines: &[RejectedLine]) -> io::Result<()> {
+        for line in lines {
+            self.append(&format!("{}.rejected", label), &format!("{}: {}", line.line_number, line.content))?;
+        }
+
+        Ok(())
+    }
+
+    fn path(&self, path: &PathBuf) -> io::Result<PathBuf> {
+        create_dir_all(&self.dir)?;
+
+        let destination = self.dir.join(path.file_name().unwrap());
+        rename(path, &destination)?;
+
+        Ok(destination)
+    }
+}
+
+struct FsckReport {
+    repair: bool,
+    problems: usize,
+    repaired: usize
+}
+
+impl FsckReport {
+    fn problem(&mut self, kind: &str, description: String) {
+        self.problems += 1;
+        println!("{:8} {}", kind.red().bold(), description);
+    }
+
+    fn repaired(&mut self, description: String) {
+        self.repaired += 1;
+        println!("{:8} {}", "REPAIRED".green().bold(), description);
+    }
+}
+
+/// Checks the store for unreadable journal records, managed directories that are missing or
+/// not referenced by the root journal, and snapshot payloads that are missing or corrupt. With
+/// `repair`, payloads are rebuilt where possible and everything else that is broken is moved
+/// into a quarantine directory inside the store.
+pub fn fsck(journal: &mut Journal, repair: bool) -> Result<(), FsckError> {
+    let quarantine = Quarantine::new(&journal.root);
+    let mut report = FsckReport { repair, problems: 0, repaired: 0 };
+
+    check_root_journal(journal, &quarantine, &mut report)?;
+
+    // Files whose managed directory is missing have been reported already
+    let mut managed_paths: Vec<PathBuf> = journal.root_journal_config.records.values()
+        .filter(|entry| entry.root.is_dir())
+        .map(|entry| PathBuf::from(&entry.key))
+        .collect();
+    managed_paths.sort();
+
+    {
+        let managed_file_journal = ManagedFileJournal::for_journal(journal);
+
+        for file_path in &managed_paths {
+            if let Some(mut managed_file) = managed_file_journal.get_managed_file(file_path)? {
+                check_managed_file(&mut managed_file, file_path, &quarantine, &mut report)?;
+            }
+        }
+    }
+
+    check_sets(journal, &managed_paths, &quarantine, &mut report)?;
+
+    println!();
+
+    if report.problems == 0 {
+        println!("{} No problems found", "OK".green().bold());
+        Ok(())
+    } else if report.repaired == report.problems {
+        println!("{} Found {} problems, all of them were repaired. Anything removed from the store was \
+                  moved to {}", "OK".green().bold(), report.problems, quarantine.dir.to_string_lossy());
+        Ok(())
+    } else {
+        println!("{} Found {} problems, {} were repaired{}", "ERROR".red().bold(), report.problems,
+                 report.repaired, if repair { "" } else { ". Run with --repair to fix them" });
+        Err(FsckError)
+    }
+}
+
+fn check_root_journal(journal: &mut Journal, quarantine: &Quarantine, report: &mut FsckReport) -> Result<(), FsckError> {
+    check_records("root journal", &mut journal.root_journal_config, quarantine, report)?;
+    check_records("configuration", &mut journal.config, quarantine, report)?;
+    check_records("sets", &mut journal.sets, quarantine, report)?;
+
+    let mut referenced_dirs = HashSet::new();
+    let mut missing_dirs = Vec::new();
+
+    for entry in journal.root_journal_config.records.values() {
+        referenced_dirs.insert(entry.root.to_owned());
+
+        if !entry.root.is_dir() {
+            report.problem("MISSING", format!("{}, managed directory {} does not exist",
+                                              entry.key, entry.root.to_string_lossy()));
+            missing_dirs.push(entry.key.to_owned());
+        }
+    }
+
+    if report.repair {
+        for key in missing_dirs {
+            let entry = journal.remove_record(&key).unwrap();
+            quarantine.append(&format!("{}.removed", journal.root_journal_config.name), &entry.to().value)?;
+
+            report.repaired(format!("{}, removed from the root journal", key));
+        }
+    }
+
+    journal.commit()?;
+
+    for dir_entry in read_dir(&journal.root)? {
+        let path = dir_entry?.path();
+        let is_internal = path.file_name().unwrap().to_string_lossy().starts_with("__");
+
+        if !path.is_dir() || is_internal || referenced_dirs.contains(&path) {
+            continue;
+        }
+
+        let recoverable_path = ManagedFileJournal::recorded_managed_path(&path)
+            .filter(|managed_path| !journal.contains_record(&managed_path.to_string_lossy().into_owned()));
+
+        // Directories that know which file they belong to are left for `hippo recover`, unless
+        // that file is already managed by another directory
+        if let Some(managed_path) = recoverable_path {
+            report.problem("ORPHAN", format!("{} is not referenced by the root journal, it belongs to {} \
+                                              and can be restored with `hippo recover`", path.to_string_lossy(),
+                                             managed_path.to_string_lossy()));
+        } else {
+            report.problem("ORPHAN", format!("{} is not referenced by the root journal", path.to_string_lossy()));
+
+            if report.repair {
+                let destination = quarantine.path(&path)?;
+                report.repaired(format!("{}, moved to {}", path.to_string_lossy(), destination.to_string_lossy()));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn check_managed_file(
+    managed_file: &mut ManagedFile, file_path: &Path, quarantine: &Quarantine, report: &mut FsckReport
+) -> Result<(), FsckError> {
+    let file_path_string = file_path.to_string_lossy().into_owned();
+
+    for (journal_name, line) in managed_file.rejected_lines() {
+        report_rejected_lines(journal_name, ::std::slice::from_ref(line), report);
+    }
+
+    if report.repair {
+        for (journal_name, line) in managed_file.rejected_lines() {
+            quarantine.lines(journal_name, ::std::slice::from_ref(line))?;
+        }
+
+        for (journal_name, line) in managed_file.discard_rejected_lines() {
+            report.repaired(format!("{}, quarantined line {} of {}", file_path_string, line.line_number, journal_name));
+        }
+    }
+
+    let mut broken_snapshots = Vec::new();
+
+    for snapshot_entry in managed_file.snapshot_entries() {
+        let description = match managed_file.check_payload(snapshot_entry) {
+            PayloadStatus::Intact => continue,
+            PayloadStatus::Missing => ("MISSING", "is missing"),
+            PayloadStatus::Corrupt => ("CORRUPT", "does not match its checksum")
+        };
+
+        report.problem(description.0, format!("{}, payload of snapshot {} {}", file_path_string,
+                                              snapshot_entry.snapshot_name.cyan(), description.1));
+        broken_snapshots.push(snapshot_entry.clone());
+    }
+
+    if report.repair {
+        for snapshot_entry in broken_snapshots {
+            if managed_file.rebuild_payload(&snapshot_entry)? {
+                report.repaired(format!("{}, rebuilt payload of snapshot {}", file_path_string,
+                                        snapshot_entry.snapshot_name.cyan()));
+            } else {
+                managed_file.remove_snapshot(&snapshot_entry.snapshot_name);
+                quarantine.append(&format!("{}.removed", managed_file.snapshot_journal_name()),
+                                  &snapshot_entry.to().value)?;
+
+                report.repaired(format!("{}, snapshot {} could not be rebuilt and was removed from the journal",
+                                        file_path_string, snapshot_entry.snapshot_name.cyan()));
+            }
+        }
+    }
+
+    if let Some(current_snapshot_name) = managed_file.get_current_snapshot_name().map(String::from) {
+        if current_snapshot_name != NULL_SNAPSHOT_NAME && managed_file.get_snapshot(&current_snapshot_name).is_none() {
+            report.problem("DANGLING", format!("{}, current snapshot {} does not exist", file_path_string,
+                                               current_snapshot_name.cyan()));
+
+            if report.repair {
+                managed_file.remove_snapshot(&current_snapshot_name);
+                report.repaired(format!("{}, cleared current snapshot", file_path_string));
+            }
+        }
+    }
+
+    managed_file.commit()?;
+
+    Ok(())
+}
+
+/// Checks that every snapshot of a set refers to snapshots of its members that exist. Snapshots
+/// of sets that do not are removed when repairing, since they can no longer be loaded.
+fn check_sets(
+    journal: &mut Journal, managed_paths: &[PathBuf], quarantine: &Quarantine, report: &mut FsckReport
+) -> Result<(), FsckError> {
+    let mut snapshot_sets: Vec<SnapshotSet> = journal.sets.records.values().cloned().collect();
+    snapshot_sets.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let mut dangling_set_snapshots = Vec::new();
+
+    {
+        let managed_file_journal = ManagedFileJournal::for_journal(journal);
+
+        for snapshot_set in &snapshot_sets {
+            for (set_snapshot_name, set_snapshot) in &snapshot_set.snapshots {
+                let mut missing_snapshots = Vec::new();
+
+                for (member, snapshot_name) in &set_snapshot.members {
+                    let exists = snapshot_name == NULL_SNAPSHOT_NAME || managed_paths.contains(member)
+                        && managed_file_journal.get_managed_file(member)?
+                            .is_some_and(|managed_file| managed_file.get_snapshot(snapshot_name).is_some());
+
+                    if !exists {
+                        report.problem("SET", format!("Set {}, snapshot {} refers to snapshot {} of {}, which does \
+                                                       not exist", snapshot_set.name.cyan(), set_snapshot_name.cyan(),
+                                                      snapshot_name.cyan(), member.to_string_lossy()));
+                        missing_snapshots.push((member, snapshot_name));
+                    }
+                }
+
+                if !missing_snapshots.is_empty() {
+                    dangling_set_snapshots.push((snapshot_set, set_snapshot_name, missing_snapshots));
+                }
+            }
+        }
+    }
+
+    if report.repair && !dangling_set_snapshots.is_empty() {
+        for (snapshot_set, set_snapshot_name, missing_snapshots) in dangling_set_snapshots {
+            quarantine.append(&format!("{}.removed", journal.sets.name), &snapshot_set.to().value)?;
+            journal.remove_set_snapshot(&snapshot_set.name, set_snapshot_name);
+
+            for (member, snapshot_name) in missing_snapshots {
+                report.repaired(format!("Set {}, removed snapshot {} which referred to snapshot {} of {}",
+                                        snapshot_set.name.cyan(), set_snapshot_name.cyan(), snapshot_name.cyan(),
+                                        member.to_string_lossy()));
+            }
+        }
+
+        journal.commit()?;
+    }
+
+    Ok(())
+}
+
+fn check_records<T: MapsToSimpleRecord>(
+    label: &str, records: &mut SimpleFileRecords<T>, quarantine: &Quarantine, report: &mut FsckReport
+) -> Result<(), FsckError> {
+    report_rejected_lines(label, &records.rejected_lines, report);
+
+    if report.repair && !records.rejected_lines.is_empty() {
+        quarantine.lines(&records.name, &records.rejected_lines)?;
+
+        for line in records.discard_rejected_lines() {
+            report.repaired(format!("{}, quarantined line {}", label, line.line_number));
+        }
+    }
+
+    Ok(())
+}
+
+fn report_rejected_lines(journal_name: &str, rejected_lines: &[RejectedLine], report: &mut FsckReport) {
+    for line in rejected_lines {
+        match line.reason {
+            RejectionReason::Corrupt => report.problem("CORRUPT", format!(
+                "{}, line {} could not be read", journal_name, line.line_number
+            )),
+
+            RejectionReason::DuplicateKey(ref key) => report.problem("DUPLICATE", format!(
+                "{}, line {} is superseded by a later record for the same key `{}`", journal_name,
+                line.line_number, key
+            )),
+
+            RejectionReason::UnsupportedFormat(ref reason) => report.problem("FORMAT", format!(
+                "{}, line {} cannot be read since the journal is in an unsupported format: {}", journal_name,
+                line.line_number, reason
+            ))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{create_dir, read_dir, read_to_string, write};
+
+    use journaling::managed_file_journal::ManagedFileJournal;
+    use snap::snap;
+    use snap::tests::snap_options;
+    use utils::test_dir::TestDir;
+
+    use super::{fsck, QUARANTINE_DIR_NAME};
+
+    #[test]
+    fn corrupt_records_and_stray_directories_are_quarantined() {
+        let test_dir = TestDir::new("fsck");
+        let file_path = test_dir.join("app.conf");
+
+        write(&file_path, "contents\n").unwrap();
+        let mut journal = test_dir.open_store();
+        assert!(snap(&mut journal, snap_options("first"), vec![file_path.to_owned()]).is_ok());
+        assert!(snap(&mut journal, snap_options("second"), vec![file_path.to_owned()]).is_ok());
+        assert!(fsck(&mut journal, false).is_ok());
+
+        let managed_root = journal.get_record(&file_path.to_string_lossy().into_owned()).root.to_owned();
+        let stray_dir = journal.root.join("stray");
+        drop(journal);
+
+        // Breaks the checksum of the line of the first snapshot
+        let snapshot_journal_path = managed_root.join("__snaps_journal");
+        let snapshot_journal = read_to_string(&snapshot_journal_path).unwrap();
+        write(&snapshot_journal_path, snapshot_journal.replacen("first", "frist", 1)).unwrap();
+        create_dir(&stray_dir).unwrap();
+
+        let mut journal = test_dir.open_store();
+        assert!(fsck(&mut journal, false).is_err());
+        assert!(stray_dir.is_dir());
+
+        assert!(fsck(&mut journal, true).is_ok());
+        assert!(!stray_dir.exists());
+        assert_eq!(read_dir(journal.root.join(QUARANTINE_DIR_NAME)).unwrap().count(), 1);
+        assert!(fsck(&mut journal, false).is_ok());
+
+        let managed_file_journal = ManagedFileJournal::for_journal(&mut journal);
+        let managed_file = managed_file_journal.get_managed_file(&file_path).ok().unwrap().unwrap();
+        assert!(managed_file.rejected_lines().is_empty());
+        assert!(managed_file.get_snapshot("first").is_none());
+        assert!(managed_file.get_snapshot("second").is_some());
+    }
+}
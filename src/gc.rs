@@ -0,0 +1,215 @@
+extern crate colored;
+extern crate pretty_bytes;
+
+use std::collections::HashSet;
+use std::fs::{read_dir, remove_dir_all, remove_file};
+use std::io;
+use std::path::{Path, PathBuf};
+
+use journaling::journal::Journal;
+use journaling::managed_file_journal::{ManagedFileJournal, ManagedFileJournalError};
+
+use self::colored::*;
+use self::pretty_bytes::converter::convert;
+
+pub struct GcError;
+
+impl From<ManagedFileJournalError> for GcError {
+    fn from(_: ManagedFileJournalError) -> Self { GcError }
+}
+
+impl From<io::Error> for GcError {
+    fn from(_: io::Error) -> Self { GcError }
+}
+
+/// Removes payloads that no snapshot refers to anymore, and the managed directories of files
+/// that no longer have any snapshots, settings or sets they belong to. With `dry_run`, only
+/// reports what would be removed. Refuses to run while any journal has lines that could not be
+/// read, since the snapshots on them may still refer to payloads.
+pub fn gc(journal: &mut Journal, dry_run: bool) -> Result<(), GcError> {
+    let object_store = journal.object_store();
+
+    let mut managed_entries: Vec<(String, PathBuf)> = journal.root_journal_config.records.values()
+        .filter(|entry| entry.root.is_dir())
+        .map(|entry| (entry.key.to_owned(), entry.root.to_owned()))
+        .collect();
+    managed_entries.sort();
+
+    let set_members: HashSet<PathBuf> = managed_entries.iter()
+        .map(|(key, _)| PathBuf::from(key))
+        .filter(|file_path| !journal.sets_containing(file_path).is_empty())
+        .collect();
+
+    let mut unreferenced_payloads = Vec::new();
+    let mut empty_managed_dirs = Vec::new();
+
+    {
+        let managed_file_journal = ManagedFileJournal::for_journal(journal);
+        let unreadable_journals = managed_file_journal.unreadable_journals()?;
+
+        if !unreadable_journals.is_empty() {
+            println!("{} {} could not be fully read, so it is not known which payloads are still referenced. \
+                      Run `hippo fsck` first", "ERROR".red(), unreadable_journals.join(", "));
+            return Err(GcError);
+        }
+
+        let referenced_payloads = managed_file_journal.referenced_payloads()?;
+
+        for (path, size) in object_store.files()? {
+            if !referenced_payloads.contains(&path) {
+                unreferenced_payloads.push((path, size));
+            }
+        }
+
+        for (key, root) in &managed_entries {
+            let file_path = PathBuf::from(key);
+            let managed_file = match managed_file_journal.get_managed_file(&file_path)? {
+                Some(managed_file) => managed_file,
+                None => continue
+            };
+
+            if managed_file.snapshot_entries().is_empty() {
+                if managed_file.has_settings() || set_members.contains(&file_path) {
+                    info!("Keeping the managed directory of {}, which has no snapshots but still has settings \
+                           or belongs to a set", key);
+                } else {
+                    empty_managed_dirs.push((key.to_owned(), root.to_owned()));
+                }
+
+                continue;
+            }
+
+            // Payloads of snapshots taken before the object store existed live in the managed
+            // directory itself, next to its journals
+            for (path, size) in managed_dir_payloads(root)? {
+                if !referenced_payloads.contains(&path) {
+                    unreferenced_payloads.push((path, size));
+                }
+            }
+        }
+    }
+
+    let mut reclaimed = 0;
+
+    for (path, size) in &unreferenced_payloads {
+        print_removal(dry_run, format!("{} ({})", path.to_string_lossy(), convert(*size as f64)));
+
+        if !dry_run {
+            remove_file(path)?;
+        }
+
+        reclaimed += size;
+    }
+
+    for (key, root) in &empty_managed_dirs {
+        let size: u64 = managed_dir_size(root)?;
+        print_removal(dry_run, format!("{}, managed directory of {} which has no snapshots ({})",
+                                       root.to_string_lossy(), key, convert(size as f64)));
+
+        if !dry_run {
+            journal.remove_record(key);
+            journal.commit().map_err(|e| {
+                println!("{} Could not write the root journal: {}", "ERROR".red(), e);
+                e
+            })?;
+
+            remove_dir_all(root)?;
+        }
+
+        reclaimed += size;
+    }
+
+    if !dry_run {
+        object_store.remove_empty_dirs()?;
+    }
+
+    if dry_run {
+        println!("{} Would remove {} unreferenced payloads and {} empty managed directories, reclaiming {} \
+                  (dry run, nothing was removed)", "OK".green().bold(), unreferenced_payloads.len(),
+                 empty_managed_dirs.len(), convert(reclaimed as f64));
+    } else {
+        println!("{} Removed {} unreferenced payloads and {} empty managed directories (Reclaimed: {})",
+                 "OK".green().bold(), unreferenced_payloads.len(), empty_managed_dirs.len(),
+                 convert(reclaimed as f64));
+    }
+
+    Ok(())
+}
+
+fn print_removal(dry_run: bool, description: String) {
+    if dry_run {
+        println!("{} {}", "WOULD REMOVE".yellow(), description);
+    } else {
+        println!("{} {}", "REMOVED".green(), description);
+    }
+}
+
+fn managed_dir_payloads(root: &Path) -> io::Result<Vec<(PathBuf, u64)>> {
+    let mut payloads = Vec::new();
+
+    for dir_entry in read_dir(root)? {
+        let dir_entry = dir_entry?;
+        let is_internal = dir_entry.file_name().to_string_lossy().starts_with("__");
+
+        if !is_internal && dir_entry.file_type()?.is_file() {
+            payloads.push((dir_entry.path(), dir_entry.metadata()?.len()));
+        }
+    }
+
+    payloads.sort();
+    Ok(payloads)
+}
+
+fn managed_dir_size(root: &Path) -> io::Result<u64> {
+    let mut size = 0;
+
+    for dir_entry in read_dir(root)? {
+        let dir_entry = dir_entry?;
+
+        if dir_entry.file_type()?.is_file() {
+            size += dir_entry.metadata()?.len();
+        }
+    }
+
+    Ok(size)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{read_to_string, write};
+    use std::path::PathBuf;
+
+    use snap::snap;
+    use snap::tests::snap_options;
+    use utils::test_dir::TestDir;
+
+    use super::gc;
+
+    #[test]
+    fn gc_refuses_to_run_while_a_snapshot_line_is_rejected() {
+        let test_dir = TestDir::new("gc");
+        let file_path = test_dir.join("file.txt");
+
+        write(&file_path, "contents\n").unwrap();
+
+        let managed_root = {
+            let mut journal = test_dir.open_store();
+
+            assert!(snap(&mut journal, snap_options("first"), vec![file_path.to_owned()]).is_ok());
+            journal.get_record(&file_path.to_string_lossy().into_owned()).root.to_owned()
+        };
+
+        // Corrupt the line of the only snapshot, so that its checksum no longer matches
+        let snapshot_journal_path = managed_root.join("__snaps_journal");
+        let snapshot_journal = read_to_string(&snapshot_journal_path).unwrap();
+        write(&snapshot_journal_path, snapshot_journal.replacen("first", "frist", 1)).unwrap();
+
+        let mut journal = test_dir.open_store();
+        let payloads: Vec<PathBuf> = journal.object_store().files().unwrap().into_iter().map(|(path, _)| path).collect();
+        assert!(!payloads.is_empty());
+
+        assert!(gc(&mut journal, false).is_err());
+        assert!(payloads.iter().all(|payload| payload.is_file()));
+        assert!(managed_root.is_dir());
+    }
+}
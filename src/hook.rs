@@ -0,0 +1,107 @@
+extern crate colored;
+
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+use journaling::hooks::{Hook, HOOKS};
+use journaling::journal::Journal;
+use journaling::managed_file_journal::{ManagedFileJournal, ManagedFileJournalError};
+use journaling::managed_file::ManagedFileError;
+
+use self::colored::*;
+
+pub struct HookError;
+
+impl From<ManagedFileJournalError> for HookError {
+    fn from(_: ManagedFileJournalError) -> Self { HookError }
+}
+
+impl From<ManagedFileError> for HookError {
+    fn from(_: ManagedFileError) -> Self { HookError }
+}
+
+/// What the hooks are configured for
+pub enum HookTarget {
+    File(PathBuf),
+    Set(String)
+}
+
+/// Shows the hooks of a managed file or a set, after making the given changes to them. Each
+/// change either sets the command of a hook, or clears it if no command is given.
+pub fn hook(journal: &mut Journal, target: HookTarget, changes: Vec<(Hook, Option<String>)>) -> Result<(), HookError> {
+    let (subject, hooks) = match target {
+        HookTarget::File(file_path) => {
+            let managed_file_journal = ManagedFileJournal::for_journal(journal);
+            let file_path_string = file_path.to_string_lossy().into_owned();
+
+            let mut managed_file = match managed_file_journal.get_managed_file(&file_path)? {
+                Some(managed_file) => managed_file,
+                None => {
+                    println!("{} {}, file not managed by {}", "ERROR".red(), file_path_string, "hippo".magenta());
+                    return Err(HookError);
+                }
+            };
+
+            for (hook, command) in changes {
+                managed_file.set_hook(hook, command.as_deref());
+            }
+
+            managed_file.commit()?;
+
+            let hooks: BTreeMap<String, String> = HOOKS.iter()
+                .filter_map(|&hook| managed_file.hook(hook).map(|command| (hook.name().to_owned(), command.to_owned())))
+                .collect();
+
+            (file_path_string, hooks)
+        }
+
+        HookTarget::Set(set_name) => {
+            let mut snapshot_set = match journal.get_set(&set_name) {
+                Some(snapshot_set) => snapshot_set.clone(),
+                None => {
+                    println!("{} No set named {}", "ERROR".red(), set_name.cyan());
+                    return Err(HookError);
+                }
+            };
+
+            let changes_given = !changes.is_empty();
+
+            for (hook, command) in changes {
+                match command {
+                    Some(command) => { snapshot_set.hooks.insert(hook.name().to_owned(), command); }
+                    None => { snapshot_set.hooks.remove(hook.name()); }
+                }
+            }
+
+            let hooks = snapshot_set.hooks.clone();
+
+            // Only showing the hooks leaves the sets alone
+            if changes_given {
+                journal.add_set(snapshot_set);
+
+                journal.commit().map_err(|e| {
+                    println!("{} Could not write the sets: {}", "ERROR".red(), e);
+                    HookError
+                })?;
+            }
+
+            (format!("Set {}", set_name.cyan()), hooks)
+        }
+    };
+
+    if hooks.is_empty() {
+        println!("{} {} has no hooks", "OK".green(), subject);
+        return Ok(());
+    }
+
+    println!("{} {}, hooks:", "OK".green(), subject);
+
+    // In the order they run in, rather than by name
+    for hook in HOOKS.iter() {
+        if let Some(command) = hooks.get(hook.name()) {
+            println!("  {} {}", format!("{:<10}", hook.name()).cyan(), command);
+        }
+    }
+
+    Ok(())
+}
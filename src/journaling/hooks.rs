@@ -0,0 +1,73 @@
+extern crate colored;
+
+use std::process::Command;
+
+use self::colored::*;
+
+/// Commands that run around snapping and loading a managed file (or a set of them), configured
+/// per file in its metadata under `hook.<name>`, or per set
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Hook {
+    /// Runs before a snapshot is taken. Failing keeps the snapshot from being taken.
+    PreSnap,
+    /// Runs before a snapshot is loaded, before anything has been changed. Failing aborts the load.
+    PreLoad,
+    /// Runs against the candidate contents of a load once they have been staged, before they
+    /// replace the target. Failing aborts the load.
+    Validate,
+    /// Runs once a snapshot has been loaded
+    PostLoad
+}
+
+pub static HOOKS: [Hook; 4] = [Hook::PreSnap, Hook::PreLoad, Hook::Validate, Hook::PostLoad];
+
+impl Hook {
+    pub fn parse(hook: &str) -> Option<Hook> {
+        HOOKS.iter().cloned().find(|candidate| candidate.name() == hook)
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Hook::PreSnap => "pre-snap",
+            Hook::PreLoad => "pre-load",
+            Hook::Validate => "validate",
+            Hook::PostLoad => "post-load"
+        }
+    }
+
+    /// Key under which the command of the hook is stored in the metadata of a managed file
+    pub fn meta_key(&self) -> String {
+        format!("hook.{}", self.name())
+    }
+}
+
+pub struct HookFailure;
+
+/// Runs the command of a hook through `sh -c`, with the given variables added to its environment
+/// (along with `HIPPO_HOOK`, the name of the hook). Its output goes straight to hippo's own. Any
+/// exit status other than zero counts as a failure, which is reported.
+pub fn run_hook(hook: Hook, command: &str, subject: &str, env: &[(&str, String)]) -> Result<(), HookFailure> {
+    info!("Running {} hook for {}: {}", hook.name(), subject, command);
+
+    let status = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .env("HIPPO_HOOK", hook.name())
+        .envs(env.iter().map(|&(key, ref value)| (key, value)))
+        .status();
+
+    match status {
+        Ok(ref status) if status.success() => Ok(()),
+
+        Ok(status) => {
+            println!("{} The {} hook for {} failed ({}): {}", "ERROR".red(), hook.name().cyan(), subject, status,
+                     command);
+            Err(HookFailure)
+        }
+
+        Err(e) => {
+            println!("{} Could not run the {} hook for {}: {}", "ERROR".red(), hook.name().cyan(), subject, e);
+            Err(HookFailure)
+        }
+    }
+}
@@ -1,10 +1,21 @@
 extern crate chrono;
+extern crate colored;
 
 use std::path::{Path, PathBuf};
+use std::fs::create_dir_all;
+use std::io;
 
 use super::super::utils::simple_file_records::{SimpleRecord, SimpleFileRecords, MapsToSimpleRecord};
+use super::object_store::ObjectStore;
+use super::snapshot_set::{SetReference, SnapshotSet};
+use super::store_lock::{LockMode, StoreLock, StoreLockError};
 
-static ROOT_JOURNAL_FILE_NAME: &'static str = "__hippo_journal";
+use self::colored::*;
+
+static ROOT_JOURNAL_FILE_NAME: &str = "__hippo_journal";
+static ROOT_LOCK_FILE_NAME: &str = "__lock";
+static CONFIG_FILE_NAME: &str = "__config";
+static SETS_FILE_NAME: &str = "__sets";
 
 #[derive(Debug)]
 pub struct RootJournalEntry {
@@ -13,11 +24,11 @@ pub struct RootJournalEntry {
 }
 
 impl MapsToSimpleRecord for RootJournalEntry {
-    fn from(simple_record: SimpleRecord) -> Self {
-        RootJournalEntry {
+    fn from(simple_record: SimpleRecord) -> Option<Self> {
+        Some(RootJournalEntry {
             key: simple_record.key.to_owned(),
             root: Path::new(&simple_record.value).to_path_buf()
-        }
+        })
     }
 
     fn to(&self) -> SimpleRecord {
@@ -28,28 +39,79 @@ impl MapsToSimpleRecord for RootJournalEntry {
     }
 }
 
+/// A setting that applies to the whole store, such as the default retention policy
+pub struct ConfigEntry {
+    pub key: String,
+    pub value: String
+}
+
+impl MapsToSimpleRecord for ConfigEntry {
+    fn from(simple_record: SimpleRecord) -> Option<Self> {
+        Some(ConfigEntry {
+            key: simple_record.key,
+            value: simple_record.value
+        })
+    }
+
+    fn to(&self) -> SimpleRecord {
+        SimpleRecord {
+            key: self.key.to_owned(),
+            value: self.value.to_owned()
+        }
+    }
+}
+
 pub struct Journal {
     pub root: PathBuf,
-    pub root_journal_config: SimpleFileRecords<RootJournalEntry>
+    pub root_journal_config: SimpleFileRecords<RootJournalEntry>,
+    pub config: SimpleFileRecords<ConfigEntry>,
+    pub sets: SimpleFileRecords<SnapshotSet>,
+    pub lock_mode: LockMode,
+    pub wait_for_locks: bool,
+    // Declared last so that the lock is only released after the journal has been written
+    _lock: StoreLock
+}
+
+pub struct JournalError;
+
+impl From<io::Error> for JournalError {
+    fn from(_: io::Error) -> Self { JournalError }
+}
+
+impl From<StoreLockError> for JournalError {
+    fn from(_: StoreLockError) -> Self { JournalError }
 }
 
 impl Journal {
-    pub fn initialize(root: String) -> Journal {
+    /// Opens the journal at the given root, first locking the store in the given mode. The lock
+    /// is held for as long as the journal is alive.
+    pub fn initialize(root: String, lock_mode: LockMode, wait_for_locks: bool) -> Result<Journal, JournalError> {
         let root_str = root.as_str().to_owned();
 
+        create_dir_all(&root)?;
+        let lock = StoreLock::acquire(Path::new(&root).join(ROOT_LOCK_FILE_NAME), lock_mode, wait_for_locks)?;
+
         info!("Loading journal into prefix {}", root);
 
-        let root_journal : SimpleFileRecords<RootJournalEntry> = SimpleFileRecords::new(
-            String::from("root_journal"),
+        let root_journal : SimpleFileRecords<RootJournalEntry> = open_records(
+            "root_journal",
             Journal::get_root_journal_path(&root_str)
-        );
+        )?;
 
         info!("Found entries in root journal {:?}", root_journal.records);
 
-        Journal {
+        let config = open_records("config", Path::new(&root).join(CONFIG_FILE_NAME))?;
+        let sets = open_records("sets", Path::new(&root).join(SETS_FILE_NAME))?;
+
+        Ok(Journal {
             root: Path::new(&root).to_path_buf(),
-            root_journal_config: root_journal
-        }
+            root_journal_config: root_journal,
+            config,
+            sets,
+            lock_mode,
+            wait_for_locks,
+            _lock: lock
+        })
     }
 
     pub fn contains_record(&self, key: &String) -> bool {
@@ -60,6 +122,127 @@ impl Journal {
         self.root_journal_config.get_record(key)
     }
 
+    pub fn remove_record(&mut self, key: &str) -> Option<RootJournalEntry> {
+        self.root_journal_config.remove(key)
+    }
+
+    pub fn get_config(&self, key: &str) -> Option<&str> {
+        self.config.records.get(key).map(|entry| entry.value.as_str())
+    }
+
+    pub fn set_config(&mut self, key: &str, value: &str) {
+        self.config.add(ConfigEntry {
+            key: key.to_owned(),
+            value: value.to_owned()
+        });
+    }
+
+    pub fn remove_config(&mut self, key: &str) {
+        self.config.remove(key);
+    }
+
+    pub fn get_set(&self, set_name: &str) -> Option<&SnapshotSet> {
+        self.sets.records.get(set_name)
+    }
+
+    pub fn add_set(&mut self, snapshot_set: SnapshotSet) {
+        self.sets.add(snapshot_set);
+    }
+
+    pub fn remove_set(&mut self, set_name: &str) -> Option<SnapshotSet> {
+        self.sets.remove(set_name)
+    }
+
+    /// Names of the sets that have the given file as a member
+    pub fn sets_containing(&self, file_path: &Path) -> Vec<&str> {
+        let mut set_names: Vec<&str> = self.sets.records.values()
+            .filter(|snapshot_set| snapshot_set.members.iter().any(|member| member == file_path))
+            .map(|snapshot_set| snapshot_set.name.as_str())
+            .collect();
+        set_names.sort();
+        set_names
+    }
+
+    /// The snapshots of sets that refer to a snapshot of the given file
+    pub fn set_references(&self, file_path: &Path) -> Vec<SetReference> {
+        let mut set_references: Vec<SetReference> = self.sets.records.values()
+            .flat_map(|snapshot_set| snapshot_set.snapshots.iter().filter_map(move |(set_snapshot_name, set_snapshot)| {
+                set_snapshot.members.get(file_path).map(|snapshot_name| SetReference {
+                    set_name: snapshot_set.name.to_owned(),
+                    set_snapshot_name: set_snapshot_name.to_owned(),
+                    snapshot_name: snapshot_name.to_owned()
+                })
+            }))
+            .collect();
+        set_references.sort_by(|a, b| (&a.set_name, &a.set_snapshot_name).cmp(&(&b.set_name, &b.set_snapshot_name)));
+        set_references
+    }
+
+    /// Makes the snapshots of sets that refer to a snapshot of the given file refer to its new name
+    pub fn rename_set_references(&mut self, file_path: &Path, snapshot_name: &str, new_snapshot_name: &str) {
+        let mut renamed_sets = Vec::new();
+
+        for snapshot_set in self.sets.records.values() {
+            let mut snapshot_set = snapshot_set.clone();
+            let mut renamed = false;
+
+            for set_snapshot in snapshot_set.snapshots.values_mut() {
+                if let Some(member_snapshot_name) = set_snapshot.members.get_mut(file_path) {
+                    if member_snapshot_name == snapshot_name {
+                        *member_snapshot_name = new_snapshot_name.to_owned();
+                        renamed = true;
+                    }
+                }
+            }
+
+            if renamed {
+                renamed_sets.push(snapshot_set);
+            }
+        }
+
+        for snapshot_set in renamed_sets {
+            self.add_set(snapshot_set);
+        }
+    }
+
+    /// Removes a snapshot of a set, returning whether it existed
+    pub fn remove_set_snapshot(&mut self, set_name: &str, set_snapshot_name: &str) -> bool {
+        let mut snapshot_set = match self.get_set(set_name) {
+            Some(snapshot_set) => snapshot_set.clone(),
+            None => return false
+        };
+
+        let removed = snapshot_set.snapshots.remove(set_snapshot_name).is_some();
+
+        if removed {
+            self.add_set(snapshot_set);
+        }
+
+        removed
+    }
+
+    /// Number of lines of the root journal, the configuration and the sets that could not be read
+    pub fn rejected_line_count(&self) -> usize {
+        self.root_journal_config.rejected_lines.len() + self.config.rejected_lines.len() + self.sets.rejected_lines.len()
+    }
+
+    /// Persists any changes made to the root journal, the configuration and the sets
+    pub fn commit(&mut self) -> io::Result<()> {
+        self.root_journal_config.write()
+            .and_then(|_| self.config.write())
+            .and_then(|_| self.sets.write())
+    }
+
+    /// Rewrites the root journal in the current format if it is in an older one, returning
+    /// whether it had to be rewritten
+    pub fn upgrade(&mut self) -> io::Result<bool> {
+        self.root_journal_config.upgrade()
+    }
+
+    pub fn object_store(&self) -> ObjectStore {
+        ObjectStore::for_journal_root(&self.root)
+    }
+
     pub fn add_record(&mut self, file_path: PathBuf, managed_root: PathBuf) {
         self.root_journal_config.add(RootJournalEntry {
             key: file_path.into_os_string().into_string().unwrap(),
@@ -86,3 +269,10 @@ impl Journal {
         Path::new(root).join(ROOT_JOURNAL_FILE_NAME)
     }
 }
+
+fn open_records<T: MapsToSimpleRecord>(name: &str, file_path: PathBuf) -> Result<SimpleFileRecords<T>, JournalError> {
+    SimpleFileRecords::new(String::from(name), file_path.to_owned()).map_err(|e| {
+        println!("{} Could not open {}: {}", "ERROR".red(), file_path.to_string_lossy(), e);
+        JournalError
+    })
+}
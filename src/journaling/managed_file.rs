@@ -6,37 +6,199 @@ extern crate users;
 
 extern crate colored;
 extern crate pretty_bytes;
+extern crate uuid;
 
-use std::path::PathBuf;
-use std::ops::Add;
-use std::fs::copy;
-use std::fs::metadata;
+use std::collections::{BTreeMap, BTreeSet};
+use std::env;
+use std::path::{Path, PathBuf};
+use std::fs::{copy, create_dir_all, remove_dir_all, metadata, read_link, remove_file, symlink_metadata, File};
+use std::io::Read;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::symlink;
 use std::{ffi, io};
 
 use self::colored::*;
 use self::pretty_bytes::converter::convert;
 
 use self::users::{get_user_by_uid, get_current_uid};
+use self::uuid::Uuid;
 
 use self::chrono::prelude::*;
-use super::super::utils::simple_file_records::{SimpleRecord, SimpleFileRecords, MapsToSimpleRecord};
+use super::super::utils::simple_file_records::{SimpleRecord, SimpleFileRecords, MapsToSimpleRecord, RejectedLine};
+use super::super::utils::compression::Codec;
+use super::super::utils::staged_file::{RollbackOutcome, StagedChanges};
+use super::super::utils::paths::follow_symlinks;
+use super::hooks::{run_hook, Hook, HookFailure};
+use super::object_store::{ObjectStore, PayloadStatus};
+use super::snapshot_set::SnapshotSet;
+use super::store_lock::{LockMode, StoreLock};
+use super::tree::{copy_tree, remove_empty_dirs, stage_tree_removal, ScannedTree, TreeFilter, TreeManifest};
+use super::super::utils::file_metadata::{running_as_root, FileMetadata};
 
-static AUTO_SNAPSHOT_NAME_FORMAT: &'static str = "%Y%m.%d.%H%M.%S";
-static AUTO_SNAPSHOT_COMMENT_FORMAT: &'static str = "%a %b %e %T %Y";
+pub static AUTO_SNAPSHOT_NAME_FORMAT: &str = "%Y%m.%d.%H%M.%S";
+static AUTO_SNAPSHOT_COMMENT_FORMAT: &str = "%a %b %e %T %Y";
 
-#[derive(Serialize, Deserialize)]
+static CURRENT_SNAPSHOT_META_KEY: &str = "current";
+/// The current snapshot as it was before a commit that changes both the snapshot journal and the
+/// current snapshot, kept until both have been written (an empty value stands for none)
+static PREVIOUS_CURRENT_SNAPSHOT_META_KEY: &str = "current.previous";
+/// Glob patterns (as JSON arrays) deciding which files of a managed directory are snapshotted
+static TREE_INCLUDE_META_KEY: &str = "tree.include";
+static TREE_EXCLUDE_META_KEY: &str = "tree.exclude";
+/// Whether a managed path that is a symlink is managed as a link, or followed to what it points to
+static SYMLINK_POLICY_META_KEY: &str = "symlinks";
+/// The absolute path of the managed file, kept so that the root journal can be rebuilt from the
+/// managed directories if it is ever lost
+pub static MANAGED_PATH_META_KEY: &str = "path";
+
+pub static NULL_SNAPSHOT_NAME: &str = "(null)";
+pub static LIVE_SNAPSHOT_NAME: &str = "(live)";
+
+/// What a snapshot holds: the contents of a single file, or (for managed directories) a
+/// manifest of every file in the tree. Snapshots of symlinks that are managed as links hold the
+/// path the link points to. Snapshots taken while nothing existed at the path are absent, and
+/// have no payload at all.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug, Default)]
+pub enum SnapshotKind {
+    #[default]
+    File,
+    Tree,
+    Absent,
+    Symlink
+}
+
+/// How a managed path that is a symlink is treated
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub enum SymlinkPolicy {
+    /// The link itself is snapshotted (as the path it points to) and replaced on load
+    Manage,
+    /// The link is followed, and whatever it points to is snapshotted and replaced on load
+    #[default]
+    Follow
+}
+
+impl SymlinkPolicy {
+    pub fn parse(policy: &str) -> Option<SymlinkPolicy> {
+        match policy {
+            "manage" => Some(SymlinkPolicy::Manage),
+            "follow" => Some(SymlinkPolicy::Follow),
+            _ => None
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            SymlinkPolicy::Manage => "manage",
+            SymlinkPolicy::Follow => "follow"
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
 pub struct SnapshotEntry {
     pub snapshot_name: String,
     pub comment: String,
     pub created_time: DateTime<Utc>,
+    /// Location of the payload relative to the managed directory, only used by snapshots taken
+    /// before payloads were kept in the object store
     pub relative_file_path: String,
-    pub author: String
+    pub author: String,
+    #[serde(default)]
+    pub codec: Codec,
+    /// Hash of the payload in the journal's object store
+    #[serde(default)]
+    pub object: Option<String>,
+    /// Whether the name was generated rather than given by hand. Not recorded by older
+    /// versions of hippo, see `is_auto_named`.
+    #[serde(default)]
+    pub auto_named: Option<bool>,
+    #[serde(default)]
+    pub tags: BTreeSet<String>,
+    #[serde(default)]
+    pub meta: BTreeMap<String, String>,
+    #[serde(default)]
+    pub kind: SnapshotKind,
+    /// Permissions, ownership and modification time of the target when it was snapped. For
+    /// directories, these are of the directory itself, and the manifest holds those of its
+    /// contents.
+    #[serde(default)]
+    pub metadata: Option<FileMetadata>
+}
+
+/// Tags and key/value pairs to attach to a snapshot, so that it can be told apart (and
+/// selected) by more than its name
+#[derive(Default)]
+pub struct SnapshotLabels {
+    pub tags: BTreeSet<String>,
+    pub meta: BTreeMap<String, String>
+}
+
+impl SnapshotLabels {
+    /// Builds labels from tags and `key=value` pairs as given on the command line
+    pub fn parse(tags: Vec<String>, meta_pairs: Vec<String>) -> Result<SnapshotLabels, String> {
+        let mut labels = SnapshotLabels::default();
+
+        for tag in tags {
+            validate_label(&tag, "Tags")?;
+            labels.tags.insert(tag);
+        }
+
+        for meta_pair in meta_pairs {
+            let (key, value) = meta_pair.split_once('=')
+                .ok_or_else(|| format!("`{}` is not a key=value pair", meta_pair))?;
+
+            validate_label(key, "Metadata keys")?;
+
+            if value.chars().any(char::is_control) {
+                return Err(format!("The value of `{}` contains control characters, which are not allowed", key));
+            }
+
+            labels.meta.insert(key.to_owned(), value.to_owned());
+        }
+
+        Ok(labels)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tags.is_empty() && self.meta.is_empty()
+    }
+}
+
+fn validate_label(label: &str, kind: &str) -> Result<(), String> {
+    if label.is_empty() {
+        Err(format!("{} cannot be empty", kind))
+    } else if label.chars().any(|c| c.is_whitespace() || c.is_control() || c == ',' || c == '=') {
+        Err(format!("`{}` is not allowed, {} cannot contain whitespace, commas or `=`", label.escape_default(),
+                    kind.to_lowercase()))
+    } else {
+        Ok(())
+    }
+}
+
+impl SnapshotEntry {
+    pub fn has_tags(&self, tags: &[String]) -> bool {
+        tags.iter().all(|tag| self.tags.contains(tag))
+    }
+
+    /// Tags followed by metadata pairs, for display
+    pub fn labels(&self) -> Vec<String> {
+        self.tags.iter().cloned()
+            .chain(self.meta.iter().map(|(key, value)| format!("{}={}", key, value)))
+            .collect()
+    }
+
+    /// Snapshots taken before this was recorded are considered auto-named if their name is in the
+    /// generated format
+    pub fn is_auto_named(&self) -> bool {
+        self.auto_named.unwrap_or_else(|| {
+            NaiveDateTime::parse_from_str(&self.snapshot_name, AUTO_SNAPSHOT_NAME_FORMAT).is_ok()
+        })
+    }
 }
 
 impl MapsToSimpleRecord for SnapshotEntry {
-    fn from(simple_record: SimpleRecord) -> Self {
-        serde_json::from_str(simple_record.value.as_str())
-            .expect("Invalid format while trying to read snapshot entry from journal")
+    fn from(simple_record: SimpleRecord) -> Option<Self> {
+        serde_json::from_str(simple_record.value.as_str()).ok()
     }
 
     fn to(&self) -> SimpleRecord {
@@ -48,8 +210,53 @@ impl MapsToSimpleRecord for SnapshotEntry {
     }
 }
 
+/// A free-form key/value pair stored alongside a managed file's snapshot journal, used for
+/// book-keeping that does not belong to any single snapshot (such as the snapshot the file is
+/// currently at).
+pub struct ManagedFileMetaEntry {
+    pub key: String,
+    pub value: String
+}
+
+impl MapsToSimpleRecord for ManagedFileMetaEntry {
+    fn from(simple_record: SimpleRecord) -> Option<Self> {
+        Some(ManagedFileMetaEntry {
+            key: simple_record.key,
+            value: simple_record.value
+        })
+    }
+
+    fn to(&self) -> SimpleRecord {
+        SimpleRecord {
+            key: self.key.to_owned(),
+            value: self.value.to_owned()
+        }
+    }
+}
+
+/// Checks that a name can be given to a snapshot by hand. Names wrapped in parentheses are
+/// reserved for synthetic snapshots, and names are kept free of path separators and control
+/// characters so that they can be passed around safely.
+pub fn validate_snapshot_name(snapshot_name: &str) -> Result<(), String> {
+    if snapshot_name.trim().is_empty() {
+        Err(String::from("Snapshot names cannot be empty"))
+    } else if snapshot_name.starts_with('(') && snapshot_name.ends_with(')') {
+        Err(format!("`{}` is reserved, names wrapped in parentheses are used for synthetic snapshots", snapshot_name))
+    } else if snapshot_name.contains('/') || snapshot_name.contains('\\') {
+        Err(format!("`{}` contains a path separator, which is not allowed in snapshot names", snapshot_name))
+    } else if snapshot_name.chars().any(char::is_control) {
+        Err(format!("`{}` contains control characters, which are not allowed in snapshot names",
+                    snapshot_name.escape_default()))
+    } else {
+        Ok(())
+    }
+}
+
 pub enum SyntheticSnapshot {
-    Null
+    Null,
+    /// The file on disk has been modified since it was last snapped or loaded. Carries the
+    /// modification time of the file.
+    Live(DateTime<Utc>)
 }
 
 pub enum Snapshot<'a> {
@@ -57,20 +264,77 @@ pub enum Snapshot<'a> {
     Tangible(&'a SnapshotEntry)
 }
 
+impl <'a> Snapshot<'a> {
+    pub fn name(&self) -> &str {
+        match *self {
+            Snapshot::Synthetic(SyntheticSnapshot::Null) => NULL_SNAPSHOT_NAME,
+            Snapshot::Synthetic(SyntheticSnapshot::Live(_)) => LIVE_SNAPSHOT_NAME,
+            Snapshot::Tangible(entry) => entry.snapshot_name.as_str()
+        }
+    }
+}
+
 pub struct ManagedFile<'a> {
     tangible_snapshot_journal: SimpleFileRecords<SnapshotEntry>,
+    meta: SimpleFileRecords<ManagedFileMetaEntry>,
+    object_store: ObjectStore,
     snapshot_storage: &'a PathBuf,
-    target_file: &'a PathBuf
+    target_file: &'a PathBuf,
+    /// The current snapshot as last written to the metadata
+    committed_current_snapshot_name: Option<String>,
+    // Declared last so that the lock is only released after the journals have been written
+    _lock: StoreLock
 }
 
 pub struct SnapshotsListing<'a> {
     pub snapshots: Vec<Snapshot<'a>>,
-    pub synthetic_count: u8,
-    pub tangible_count: u8
+    /// Name of the snapshot (tangible or synthetic) the file on disk is currently at
+    pub current_snapshot_name: String,
+    pub synthetic_count: usize,
+    pub tangible_count: usize
 }
 
 pub struct ManagedFileError;
 
+/// A payload stored for a new snapshot, along with its (uncompressed) size and a summary of
+/// how it was stored
+struct StoredPayload {
+    object: String,
+    size: u64,
+    summary: String,
+    metadata: Option<FileMetadata>
+}
+
+/// A snapshot whose changes to the target have been staged, waiting for them to be applied
+pub struct StagedLoad {
+    snapshot_name: String,
+    summary: String,
+    write_path: PathBuf,
+    manifest: Option<TreeManifest>,
+    /// The filter of a directory that is being removed, whose empty directories are removed once
+    /// its files are
+    removed_tree: Option<TreeFilter>,
+    root_metadata: Option<FileMetadata>,
+    /// The staged file (or symlink) that replaces the target, which validate hooks run against
+    candidate: Option<PathBuf>,
+    unowned_paths: Vec<PathBuf>
+}
+
+/// The contents a load would put in place of the target, for validate hooks to check. Copies
+/// made for this are removed once it is dropped.
+struct LoadCandidate {
+    path: Option<PathBuf>,
+    copy_dir: Option<PathBuf>
+}
+
+impl Drop for LoadCandidate {
+    fn drop(&mut self) {
+        if let Some(ref copy_dir) = self.copy_dir {
+            let _ = remove_dir_all(copy_dir);
+        }
+    }
+}
+
 impl From<ffi::OsString> for ManagedFileError {
     fn from(_: ffi::OsString) -> Self { ManagedFileError {} }
 }
@@ -79,21 +343,53 @@ impl From<io::Error> for ManagedFileError {
     fn from(_: io::Error) -> Self { ManagedFileError {} }
 }
 
+impl From<HookFailure> for ManagedFileError {
+    fn from(_: HookFailure) -> Self { ManagedFileError {} }
+}
+
 impl <'a> ManagedFile<'a> {
-    pub fn new(file_key: &'a PathBuf, snapshot_journal_file: PathBuf, snapshot_storage: &'a PathBuf) -> ManagedFile<'a> {
-        ManagedFile {
+    pub fn new(
+        file_key: &'a PathBuf, snapshot_journal_file: PathBuf, meta_file: PathBuf, object_store: ObjectStore,
+        snapshot_storage: &'a PathBuf, lock: StoreLock
+    ) -> io::Result<ManagedFile<'a>> {
+        let lock_mode = lock.mode();
+
+        let mut managed_file = ManagedFile {
             tangible_snapshot_journal: SimpleFileRecords::new(
                 format!("snapshot_journal({})", file_key.to_str().unwrap()),
                 snapshot_journal_file
-            ),
+            )?,
+            meta: SimpleFileRecords::new(
+                format!("meta({})", file_key.to_str().unwrap()),
+                meta_file
+            )?,
+            object_store,
             snapshot_storage,
-            target_file: file_key
+            target_file: file_key,
+            committed_current_snapshot_name: None,
+            _lock: lock
+        };
+
+        // Directories created before the path was recorded are backfilled whenever they are
+        // opened for writing
+        if lock_mode == LockMode::Exclusive {
+            managed_file.record_managed_path();
         }
+
+        managed_file.settle_interrupted_commit(lock_mode);
+
+        managed_file.committed_current_snapshot_name = managed_file.get_current_snapshot_name().map(String::from);
+
+        Ok(managed_file)
     }
 
+    /// Takes a snapshot of the target file as it currently is. An existing snapshot with the same
+    /// name is only replaced if `force` is set, in which case it is returned. Generated names are
+    /// made unique by adding a counter if a snapshot was already taken in the same second.
     pub fn snap_current_state(
-        &mut self, snapshot_name: Option<String>, comment: Option<String>, author: Option<String>
-    ) -> Result<(), ManagedFileError> {
+        &mut self, snapshot_name: Option<String>, comment: Option<String>, author: Option<String>, codec: Codec,
+        labels: &SnapshotLabels, force: bool
+    ) -> Result<Option<SnapshotEntry>, ManagedFileError> {
         let created_time  = Utc::now();
         let created_local_time = created_time.with_timezone(&Local);
         let author  = author.unwrap_or(get_user_by_uid(get_current_uid()).unwrap().name().to_string()).to_owned();
@@ -103,71 +399,1375 @@ impl <'a> ManagedFile<'a> {
             created_local_time.format(AUTO_SNAPSHOT_COMMENT_FORMAT)
         )).to_owned();
 
-        let snapshot_name = snapshot_name.unwrap_or(
-                created_local_time.format(AUTO_SNAPSHOT_NAME_FORMAT
-            ).to_string())
-            .to_owned();
+        let auto_named = snapshot_name.is_none();
+        let snapshot_name = match snapshot_name {
+            Some(snapshot_name) => snapshot_name,
+            None => self.unique_snapshot_name(created_local_time.format(AUTO_SNAPSHOT_NAME_FORMAT).to_string())
+        };
 
-        let snapped_file_name = self.target_file.file_name()
-            .unwrap()
-            .to_os_string()
-            .into_string()?
-            .add("-")
-            .add(snapshot_name.as_str());
+        if !force && self.get_snapshot(&snapshot_name).is_some() {
+            println!("{} {} already has a snapshot named {}, use --force to replace it", "ERROR".red(),
+                     self.target_file.to_string_lossy(), snapshot_name.cyan());
+            return Err(ManagedFileError);
+        }
 
-        info!("Copying target file {:?} into directory", self.target_file);
+        self.run_hook(Hook::PreSnap, &snapshot_name, None)?;
 
-        copy(
-            &self.target_file,
-            self.snapshot_storage.join(&snapped_file_name)
-        )?;
+        let (kind, stored_payload) = if self.is_managed_link() {
+            (SnapshotKind::Symlink, Some(self.store_link(codec)?))
+        } else if self.target_file.is_dir() {
+            (SnapshotKind::Tree, Some(self.store_tree(codec)?))
+        } else if self.target_file.exists() {
+            (SnapshotKind::File, Some(self.store_file(codec)?))
+        } else {
+            (SnapshotKind::Absent, None)
+        };
 
         info!("Updating managed file journal");
 
-        println!("{} Created snapshot {} for {} (Size: {})",
-           "OK".green().bold(),
-            snapshot_name.as_str().cyan(),
-            self.target_file.to_owned().into_os_string().into_string().unwrap().as_str().bold(),
-            convert(metadata(&self.target_file).unwrap().len() as f64)
-        );
+        self.set_current_snapshot_name(&snapshot_name);
+
+        let replaced_entry = self.tangible_snapshot_journal.remove(&snapshot_name);
 
         self.tangible_snapshot_journal.add(SnapshotEntry {
-            snapshot_name,
-            relative_file_path: snapped_file_name,
+            snapshot_name: snapshot_name.to_owned(),
+            relative_file_path: String::new(),
+            object: stored_payload.as_ref().map(|stored_payload| stored_payload.object.to_owned()),
             comment,
             created_time,
-            author
+            author,
+            codec,
+            auto_named: Some(auto_named),
+            tags: labels.tags.clone(),
+            meta: labels.meta.clone(),
+            kind,
+            metadata: stored_payload.as_ref().and_then(|stored_payload| stored_payload.metadata)
         });
 
+        self.commit()?;
+
+        println!("{} {} snapshot {} for {} ({})",
+           "OK".green().bold(),
+            if replaced_entry.is_some() { "Replaced" } else { "Created" },
+            snapshot_name.as_str().cyan(),
+            self.target_file.to_owned().into_os_string().into_string().unwrap().as_str().bold(),
+            match stored_payload {
+                Some(stored_payload) => format!("Size: {}{}", convert(stored_payload.size as f64), stored_payload.summary),
+                None => String::from("Absent")
+            }
+        );
+
+        Ok(replaced_entry)
+    }
+
+    fn store_file(&self, codec: Codec) -> Result<StoredPayload, ManagedFileError> {
+        info!("Storing target file {:?} in the object store (codec: {:?})", self.target_file, codec);
+
+        let mut content = Vec::new();
+        File::open(self.target_file)?.read_to_end(&mut content)?;
+
+        let stored_object = self.object_store.put(&content, codec)?;
+
+        let summary = if !stored_object.created {
+            String::from(", Deduplicated")
+        } else if codec != Codec::None {
+            format!(", Stored: {}", convert(stored_object.stored_size as f64))
+        } else {
+            String::new()
+        };
+
+        Ok(StoredPayload {
+            object: stored_object.hash,
+            size: content.len() as u64,
+            summary,
+            metadata: Some(FileMetadata::read(self.target_file)?)
+        })
+    }
+
+    fn store_link(&self, codec: Codec) -> Result<StoredPayload, ManagedFileError> {
+        info!("Storing the target of symlink {:?} in the object store (codec: {:?})", self.target_file, codec);
+
+        let link_target = read_link(self.target_file)?;
+        let stored_object = self.object_store.put(link_target.as_os_str().as_bytes(), codec)?;
+
+        Ok(StoredPayload {
+            object: stored_object.hash,
+            size: link_target.as_os_str().len() as u64,
+            summary: format!(", Symlink to {}", link_target.to_string_lossy()),
+            metadata: None
+        })
+    }
+
+    /// Stores every file of the target directory, and then the manifest of the tree itself
+    fn store_tree(&self, codec: Codec) -> Result<StoredPayload, ManagedFileError> {
+        info!("Storing target directory {:?} in the object store (codec: {:?})", self.target_file, codec);
+
+        let (include, exclude) = self.tree_filter_globs();
+        let captured_tree = TreeManifest::capture(self.target_file, &include, &exclude, &self.object_store, codec)?;
+
+        for skipped in &captured_tree.skipped {
+            println!("{} {}, not a regular file or directory, leaving it out of the snapshot", "WARN".yellow(),
+                     skipped.to_string_lossy());
+        }
+
+        let manifest = serde_json::to_vec(&captured_tree.manifest)
+            .expect("Failed to serialize tree manifest");
+        let stored_manifest = self.object_store.put(&manifest, codec)?;
+
+        let stored_size = captured_tree.stored_size + if stored_manifest.created { stored_manifest.stored_size } else { 0 };
+        let summary = if captured_tree.stored_size == 0 {
+            format!(", {} files, Deduplicated", captured_tree.manifest.files.len())
+        } else {
+            format!(", {} files, Stored: {}", captured_tree.manifest.files.len(), convert(stored_size as f64))
+        };
+
+        Ok(StoredPayload {
+            object: stored_manifest.hash,
+            size: captured_tree.size,
+            summary,
+            metadata: Some(FileMetadata::read(self.target_file)?)
+        })
+    }
+
+    /// Sets the glob patterns deciding which files of a managed directory are part of its
+    /// snapshots, for this and later snapshots
+    pub fn set_tree_filter(&mut self, include: &[String], exclude: &[String]) -> Result<(), String> {
+        TreeFilter::new(include, exclude)?;
+
+        for &(key, globs) in &[(TREE_INCLUDE_META_KEY, include), (TREE_EXCLUDE_META_KEY, exclude)] {
+            if globs.is_empty() {
+                self.remove_meta(key);
+            } else {
+                self.set_meta(key, &serde_json::to_string(globs).expect("Failed to serialize patterns"));
+            }
+        }
+
         Ok(())
     }
 
-    pub fn get_snapshots(&self) -> SnapshotsListing {
-        let mut snapshots = Vec::<Snapshot>::new();
-        let mut synthetic_count : u8 = 0;
-        let mut tangible_count : u8 = 0;
+    fn tree_filter_globs(&self) -> (Vec<String>, Vec<String>) {
+        let globs = |key| self.get_meta(key)
+            .and_then(|value| serde_json::from_str(value).ok())
+            .unwrap_or_default();
 
-        snapshots.push(Snapshot::Synthetic(SyntheticSnapshot::Null));
-        synthetic_count = synthetic_count + 1;
+        (globs(TREE_INCLUDE_META_KEY), globs(TREE_EXCLUDE_META_KEY))
+    }
 
-        let mut tangible_entries : Vec<&SnapshotEntry> = self.tangible_snapshot_journal.records
-            .values()
-            .collect();
+    fn tree_filter(&self) -> Result<TreeFilter, ManagedFileError> {
+        let (include, exclude) = self.tree_filter_globs();
+
+        TreeFilter::new(&include, &exclude).map_err(|e| {
+            println!("{} {}, {}", "ERROR".red(), self.target_file.to_string_lossy(), e);
+            ManagedFileError
+        })
+    }
+
+    pub fn symlink_policy(&self) -> SymlinkPolicy {
+        self.get_meta(SYMLINK_POLICY_META_KEY)
+            .and_then(SymlinkPolicy::parse)
+            .unwrap_or_default()
+    }
+
+    pub fn set_symlink_policy(&mut self, symlink_policy: SymlinkPolicy) {
+        self.set_meta(SYMLINK_POLICY_META_KEY, symlink_policy.name());
+    }
+
+    /// Whether the target is a symlink that is managed as a link, rather than followed
+    fn is_managed_link(&self) -> bool {
+        self.symlink_policy() == SymlinkPolicy::Manage && symlink_metadata(self.target_file)
+            .map(|target_metadata| target_metadata.file_type().is_symlink())
+            .unwrap_or(false)
+    }
+
+    /// The path that loading a snapshot writes to: the target itself, or for symlinks that are
+    /// followed, whatever they (eventually) point to
+    fn write_path(&self) -> Result<PathBuf, ManagedFileError> {
+        match self.symlink_policy() {
+            SymlinkPolicy::Manage => Ok(self.target_file.to_owned()),
+            SymlinkPolicy::Follow => Ok(follow_symlinks(self.target_file)?)
+        }
+    }
+
+    /// Adds a counter to the given name if a snapshot by that name already exists
+    fn unique_snapshot_name(&self, snapshot_name: String) -> String {
+        if self.get_snapshot(&snapshot_name).is_none() {
+            return snapshot_name;
+        }
+
+        (1..)
+            .map(|counter| format!("{}.{}", snapshot_name, counter))
+            .find(|candidate| self.get_snapshot(candidate).is_none())
+            .unwrap()
+    }
+
+    pub fn get_snapshot(&self, snapshot_name: &str) -> Option<&SnapshotEntry> {
+        self.tangible_snapshot_journal.records.get(snapshot_name)
+    }
+
+    /// Reads the contents of a snapshot, decoding the stored payload if it was compressed.
+    pub fn read_snapshot(&self, snapshot_entry: &SnapshotEntry) -> io::Result<Vec<u8>> {
+        if snapshot_entry.kind == SnapshotKind::Absent {
+            return Ok(Vec::new());
+        }
+
+        if let Some(ref hash) = snapshot_entry.object {
+            return self.object_store.get(hash, snapshot_entry.codec);
+        }
+
+        let mut payload = Vec::new();
+
+        File::open(self.snapshot_storage.join(&snapshot_entry.relative_file_path))?
+            .read_to_end(&mut payload)?;
+
+        snapshot_entry.codec.decode(&payload)
+    }
+
+    pub fn read_tree_manifest(&self, snapshot_entry: &SnapshotEntry) -> io::Result<TreeManifest> {
+        let manifest = self.read_snapshot(snapshot_entry)?;
+
+        serde_json::from_slice(&manifest).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    /// Reads the contents of a snapshot by its name, which could also be one of the synthetic
+    /// snapshots: `(null)` has no content and `(live)` is the file as it currently exists on disk.
+    /// Contents are keyed by the path of each file relative to the target, which for a single
+    /// file is just the empty path. Returns `None` if no such snapshot exists.
+    pub fn read_snapshot_files_by_name(&self, snapshot_name: &str) -> Result<Option<BTreeMap<String, Vec<u8>>>, ManagedFileError> {
+        let mut files = BTreeMap::new();
+
+        if snapshot_name == NULL_SNAPSHOT_NAME {
+            Ok(Some(files))
+        } else if snapshot_name == LIVE_SNAPSHOT_NAME {
+            if self.is_managed_link() {
+                files.insert(String::new(), read_link(self.target_file)?.as_os_str().as_bytes().to_vec());
+            } else if self.target_file.is_dir() {
+                files = ScannedTree::scan(self.target_file, &self.tree_filter()?)?.read_files()?;
+            } else if self.target_file.exists() {
+                let mut content = Vec::new();
+                File::open(self.target_file)?.read_to_end(&mut content)?;
+                files.insert(String::new(), content);
+            }
+
+            Ok(Some(files))
+        } else {
+            let snapshot_entry = match self.get_snapshot(snapshot_name) {
+                Some(snapshot_entry) => snapshot_entry,
+                None => return Ok(None)
+            };
+
+            let read = match snapshot_entry.kind {
+                SnapshotKind::File | SnapshotKind::Symlink => self.read_snapshot(snapshot_entry)
+                    .map(|content| { files.insert(String::new(), content); }),
+                SnapshotKind::Tree => self.read_tree_manifest(snapshot_entry)
+                    .and_then(|manifest| manifest.read_files(&self.object_store))
+                    .map(|tree_files| { files = tree_files; }),
+                SnapshotKind::Absent => Ok(())
+            };
+
+            // Standard error, since `hippo cat` writes the contents itself to standard output
+            if let Err(ref e) = read {
+                eprintln!("{} {}, could not read snapshot {} (see `hippo fsck`): {}", "ERROR".red(),
+                          self.target_file.to_string_lossy(), snapshot_name.cyan(), e);
+            }
+
+            Ok(read.map(|_| Some(files))?)
+        }
+    }
+
+    /// Reads the recorded metadata of a snapshot by its name, keyed like the contents returned
+    /// by `read_snapshot_files_by_name`, with the target itself at the empty path. Snapshots
+    /// taken by older versions of hippo have no metadata. Returns `None` if no such snapshot
+    /// exists.
+    pub fn read_snapshot_metadata_by_name(&self, snapshot_name: &str) -> Result<Option<BTreeMap<String, FileMetadata>>, ManagedFileError> {
+        let mut recorded_metadata = BTreeMap::new();
+
+        if snapshot_name == NULL_SNAPSHOT_NAME {
+            Ok(Some(recorded_metadata))
+        } else if snapshot_name == LIVE_SNAPSHOT_NAME {
+            if self.is_managed_link() {
+                return Ok(Some(recorded_metadata));
+            }
+
+            if self.target_file.is_dir() {
+                recorded_metadata = ScannedTree::scan(self.target_file, &self.tree_filter()?)?
+                    .read_metadata(self.target_file)?;
+            }
+
+            if self.target_file.exists() {
+                recorded_metadata.insert(String::new(), FileMetadata::read(self.target_file)?);
+            }
+
+            Ok(Some(recorded_metadata))
+        } else {
+            let snapshot_entry = match self.get_snapshot(snapshot_name) {
+                Some(snapshot_entry) => snapshot_entry,
+                None => return Ok(None)
+            };
+
+            if snapshot_entry.kind == SnapshotKind::Tree {
+                recorded_metadata = self.read_tree_manifest(snapshot_entry)?.read_metadata();
+            }
+
+            if let Some(file_metadata) = snapshot_entry.metadata {
+                recorded_metadata.insert(String::new(), file_metadata);
+            }
+
+            Ok(Some(recorded_metadata))
+        }
+    }
+
+    /// Replaces the target file with the contents of the given snapshot. The contents are first
+    /// staged next to the target and then renamed over it, so that the target is never left
+    /// half-written. For directories, every file in the tree is replaced this way, and files that
+    /// are not part of the snapshot are removed; if any of them cannot be replaced, the ones
+    /// that already were are restored (see `load_snapshots`). The recorded permissions,
+    /// ownership and modification times are restored as well. Loading `(null)`, or a snapshot
+    /// taken while the target did not exist, removes the target. If the target has modifications
+    /// that were never snapped, `handle_live` is called on it before anything is changed (see
+    /// `load_snapshots`).
+    pub fn load_snapshot<F>(&mut self, snapshot_name: &str, mut handle_live: F) -> Result<(), ManagedFileError>
+        where F: FnMut(&mut ManagedFile) -> Result<(), ManagedFileError>
+    {
+        load_snapshots(vec![(self, snapshot_name)], None, |_, managed_file| handle_live(managed_file))
+    }
+
+    /// Stages the changes that loading the given snapshot makes to the target, without changing
+    /// the target itself yet
+    pub fn stage_load(&self, snapshot_name: &str, staged_changes: &mut StagedChanges) -> Result<StagedLoad, ManagedFileError> {
+        let snapshot_entry = match self.get_snapshot(snapshot_name) {
+            Some(snapshot_entry) => Some(snapshot_entry.clone()),
+            None if snapshot_name == NULL_SNAPSHOT_NAME => None,
+            None => return Err(ManagedFileError)
+        };
+
+        let write_path = self.write_path()?;
+        let mut staged_load = StagedLoad {
+            snapshot_name: snapshot_name.to_owned(),
+            summary: String::from("Absent, removed it"),
+            write_path: write_path.to_owned(),
+            manifest: None,
+            removed_tree: None,
+            root_metadata: None,
+            candidate: None,
+            unowned_paths: Vec::new()
+        };
+
+        let snapshot_entry = match snapshot_entry {
+            Some(snapshot_entry) if snapshot_entry.kind != SnapshotKind::Absent => snapshot_entry,
+            _ => {
+                info!("Staging snapshot {} for target {:?}, removing it", snapshot_name, self.target_file);
+
+                let staged = if self.is_managed_link() {
+                    staged_changes.stage_removal(self.target_file)
+                } else if write_path.is_dir() {
+                    let filter = self.tree_filter()?;
+                    let staged = stage_tree_removal(&write_path, &filter, staged_changes);
+
+                    staged_load.removed_tree = Some(filter);
+                    staged
+                } else if write_path.exists() {
+                    staged_changes.stage_removal(&write_path)
+                } else {
+                    Ok(())
+                };
+
+                staged.map_err(|e| self.staging_failed(snapshot_name, e))?;
+                return Ok(staged_load);
+            }
+        };
+
+        info!("Staging snapshot {} for target {:?}", snapshot_name, self.target_file);
+
+        match snapshot_entry.kind {
+            SnapshotKind::File => {
+                if !self.is_managed_link() && write_path.is_dir() {
+                    println!("{} {} is a directory, but snapshot {} is of a single file", "ERROR".red(),
+                             self.target_file.to_string_lossy(), snapshot_name.cyan());
+                    return Err(ManagedFileError);
+                }
+
+                let content = self.read_snapshot(&snapshot_entry)?;
+                let ownership_applied = staged_changes.stage_file(&write_path, &content, snapshot_entry.metadata.as_ref())
+                    .map_err(|e| self.staging_failed(snapshot_name, e))?;
+
+                if !ownership_applied {
+                    staged_load.unowned_paths.push(write_path.to_owned());
+                }
+
+                staged_load.candidate = staged_changes.staging_file(&write_path).map(Path::to_owned);
+
+                staged_load.summary = format!("Size: {}", convert(content.len() as f64));
+            }
+
+            SnapshotKind::Tree => {
+                if write_path.exists() && !write_path.is_dir() {
+                    println!("{} {} is a file, but snapshot {} is of a directory", "ERROR".red(),
+                             self.target_file.to_string_lossy(), snapshot_name.cyan());
+                    return Err(ManagedFileError);
+                }
 
-        tangible_entries.sort_by(|a, b|
-            a.created_time.cmp(&b.created_time)
+                let manifest = self.read_tree_manifest(&snapshot_entry)?;
+
+                staged_load.unowned_paths = manifest.stage_restore(&write_path, &self.object_store, staged_changes)
+                    .map_err(|e| self.staging_failed(snapshot_name, e))?;
+                staged_load.summary = format!("Size: {}, {} files", convert(manifest.size() as f64), manifest.files.len());
+                staged_load.manifest = Some(manifest);
+                staged_load.root_metadata = snapshot_entry.metadata;
+            }
+
+            // Links are always replaced as links, whatever the policy is now
+            SnapshotKind::Symlink => {
+                if !self.is_managed_link() && self.target_file.is_dir() {
+                    println!("{} {} is a directory, but snapshot {} is of a symlink", "ERROR".red(),
+                             self.target_file.to_string_lossy(), snapshot_name.cyan());
+                    return Err(ManagedFileError);
+                }
+
+                let link_target = PathBuf::from(ffi::OsStr::from_bytes(&self.read_snapshot(&snapshot_entry)?));
+
+                staged_changes.stage_symlink(self.target_file, &link_target)
+                    .map_err(|e| self.staging_failed(snapshot_name, e))?;
+                staged_load.candidate = staged_changes.staging_file(self.target_file).map(Path::to_owned);
+                staged_load.summary = format!("Symlink to {}", link_target.to_string_lossy());
+            }
+
+            SnapshotKind::Absent => unreachable!()
+        }
+
+        Ok(staged_load)
+    }
+
+    /// Finishes loading a snapshot once its staged changes have been applied: for directories,
+    /// directories that are not part of the snapshot are removed and the metadata of the rest is
+    /// restored. The target is then recorded as being at the loaded snapshot.
+    pub fn complete_load(&mut self, mut staged_load: StagedLoad) -> Result<(), ManagedFileError> {
+        let settled = self.settle_load(&mut staged_load);
+
+        if let Err(ref e) = settled {
+            println!("{} {}, could not restore its directories as they were in snapshot {}: {}", "ERROR".red(),
+                     self.target_file.to_string_lossy(), staged_load.snapshot_name.cyan(), e);
+        }
+
+        if !staged_load.unowned_paths.is_empty() {
+            println!("{} {}, could not restore the ownership of {} files{}", "WARN".yellow(),
+                     self.target_file.to_string_lossy(), staged_load.unowned_paths.len(),
+                     if running_as_root() { "" } else { " since hippo is not running as root" });
+        }
+
+        // The files have been replaced either way, so the target is at the snapshot now
+        self.set_current_snapshot_name(&staged_load.snapshot_name);
+        self.commit()?;
+        settled?;
+
+        println!("{} Loaded snapshot {} for {} ({})",
+            "OK".green().bold(),
+            staged_load.snapshot_name.cyan(),
+            self.target_file.to_owned().into_os_string().into_string().unwrap().as_str().bold(),
+            staged_load.summary
         );
 
-        for snapshot_entry in tangible_entries {
+        Ok(())
+    }
+
+    fn settle_load(&self, staged_load: &mut StagedLoad) -> io::Result<()> {
+        if let Some(ref manifest) = staged_load.manifest {
+            let unowned_dirs = manifest.settle(&staged_load.write_path)?;
+            staged_load.unowned_paths.extend(unowned_dirs);
+        }
+
+        if let Some(ref filter) = staged_load.removed_tree {
+            remove_empty_dirs(&staged_load.write_path, filter)?;
+        }
+
+        if let Some(root_metadata) = staged_load.root_metadata {
+            if !root_metadata.apply(&staged_load.write_path)? {
+                staged_load.unowned_paths.push(staged_load.write_path.to_owned());
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The candidate contents of a load, which validate hooks run against: the staged file or
+    /// symlink next to the target, or for directories, a copy of the whole tree in the temporary
+    /// directory. Loads that remove the target have no candidate.
+    fn load_candidate(&self, staged_load: &StagedLoad) -> io::Result<LoadCandidate> {
+        match staged_load.manifest {
+            Some(ref manifest) => {
+                let copy_dir = env::temp_dir().join(Uuid::new_v4().simple().to_string());
+                let path = copy_dir.join(self.target_file.file_name().unwrap());
+                let candidate = LoadCandidate { path: Some(path), copy_dir: Some(copy_dir) };
+
+                manifest.write_copy(candidate.path.as_ref().unwrap(), &self.object_store)?;
+                Ok(candidate)
+            }
+
+            None => Ok(LoadCandidate { path: staged_load.candidate.to_owned(), copy_dir: None })
+        }
+    }
+
+    pub fn hook(&self, hook: Hook) -> Option<&str> {
+        self.get_meta(&hook.meta_key())
+    }
+
+    pub fn set_hook(&mut self, hook: Hook, command: Option<&str>) {
+        match command {
+            Some(command) => self.set_meta(&hook.meta_key(), command),
+            None => self.remove_meta(&hook.meta_key())
+        }
+    }
+
+    /// Runs a hook of the file, if it has one. Hooks of a file get its path and the name of the
+    /// snapshot in `HIPPO_FILE` and `HIPPO_SNAPSHOT`, and validate hooks the path of the candidate
+    /// contents in `HIPPO_CANDIDATE`.
+    fn run_hook(&self, hook: Hook, snapshot_name: &str, candidate: Option<&Path>) -> Result<(), ManagedFileError> {
+        let command = match self.hook(hook) {
+            Some(command) => command,
+            None => return Ok(())
+        };
+
+        let mut env = vec![
+            ("HIPPO_FILE", self.target_file.to_string_lossy().into_owned()),
+            ("HIPPO_SNAPSHOT", snapshot_name.to_owned())
+        ];
+        env.extend(candidate.map(|candidate| ("HIPPO_CANDIDATE", candidate.to_string_lossy().into_owned())));
+
+        Ok(run_hook(hook, command, &self.target_file.to_string_lossy(), &env)?)
+    }
+
+    fn staging_failed(&self, snapshot_name: &str, e: io::Error) -> ManagedFileError {
+        println!("{} Could not stage snapshot {} for {}: {}", "ERROR".red(), snapshot_name.cyan(),
+                 self.target_file.to_string_lossy(), e);
+        ManagedFileError::from(e)
+    }
+
+    /// Checks whether the target file has been modified since it was last snapped or loaded, by
+    /// comparing its contents, permissions and ownership against that snapshot (or the most
+    /// recent snapshot, if the file has never been snapped or loaded through this journal). A
+    /// target that does not exist has nothing to lose and is never considered live, while one
+    /// that exists even though it is at an absent snapshot is.
+    pub fn is_live(&self) -> io::Result<bool> {
+        let is_managed_link = self.is_managed_link();
+
+        if !self.target_file.exists() && !is_managed_link {
+            return Ok(false);
+        }
+
+        let reference_entry = match self.get_current_snapshot() {
+            Some(snapshot_entry) if snapshot_entry.kind != SnapshotKind::Absent => snapshot_entry,
+            // Directories only holding files the filter leaves out are as good as absent
+            _ if self.target_file.is_dir() && !is_managed_link => {
+                let (include, exclude) = self.tree_filter_globs();
+                let tree_filter = TreeFilter::new(&include, &exclude)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+                return Ok(!ScannedTree::scan(self.target_file, &tree_filter)?.files.is_empty());
+            }
+            _ => return Ok(true)
+        };
+
+        match (is_managed_link, reference_entry.kind == SnapshotKind::Symlink) {
+            (true, true) => {
+                let link_target = read_link(self.target_file)?;
+                return Ok(link_target.as_os_str().as_bytes() != self.read_snapshot(reference_entry)?.as_slice());
+            }
+
+            // A link where there was none, or the other way around
+            (true, false) | (false, true) => return Ok(true),
+            (false, false) => {}
+        }
+
+        if let Some(file_metadata) = reference_entry.metadata {
+            if !file_metadata.same_attributes(&FileMetadata::read(self.target_file)?) {
+                return Ok(true);
+            }
+        }
+
+        match (reference_entry.kind, self.target_file.is_dir()) {
+            (SnapshotKind::File, false) => {
+                let mut live_content = Vec::new();
+                File::open(self.target_file)?.read_to_end(&mut live_content)?;
+
+                Ok(live_content != self.read_snapshot(reference_entry)?)
+            }
+
+            (SnapshotKind::Tree, true) => Ok(!self.read_tree_manifest(reference_entry)?.matches(self.target_file)?),
+
+            // A file where a directory used to be, or the other way around
+            _ => Ok(true)
+        }
+    }
+
+    /// Like `is_live`, but reports why the target could not be checked
+    pub fn check_live(&self) -> Result<bool, ManagedFileError> {
+        self.is_live().map_err(|e| {
+            println!("{} {}, could not check for unsaved changes: {}", "ERROR".red(),
+                     self.target_file.to_string_lossy(), e);
+            ManagedFileError::from(e)
+        })
+    }
+
+    /// Copies the target file (or tree) into a freshly created directory under the system's
+    /// temporary directory, returning the path of the copy.
+    pub fn stash_live(&self) -> Result<PathBuf, ManagedFileError> {
+        let stash_dir = env::temp_dir().join(Uuid::new_v4().simple().to_string());
+        let stash_file = stash_dir.join(self.target_file.file_name().unwrap());
+
+        info!("Stashing live file {:?} at {:?}", self.target_file, stash_file);
+
+        create_dir_all(&stash_dir)?;
+
+        if self.is_managed_link() {
+            symlink(read_link(self.target_file)?, &stash_file)?;
+        } else if self.target_file.is_dir() {
+            copy_tree(self.target_file, &stash_file, &self.tree_filter()?)?;
+        } else {
+            copy(self.target_file, &stash_file)?;
+        }
+
+        Ok(stash_file)
+    }
+
+    /// The snapshot this file was last snapped from or loaded to. For journals that predate
+    /// tracking this, the most recent snapshot is assumed. A file at `(null)` is not at any
+    /// snapshot.
+    pub fn get_current_snapshot(&self) -> Option<&SnapshotEntry> {
+        if self.get_current_snapshot_name() == Some(NULL_SNAPSHOT_NAME) {
+            return None;
+        }
+
+        let current_snapshot = self.get_current_snapshot_name()
+            .and_then(|current| self.get_snapshot(current));
+
+        current_snapshot.or_else(||
+            self.tangible_snapshot_journal.records.values().max_by_key(|entry| entry.created_time)
+        )
+    }
+
+    /// All tangible snapshots of this file, oldest first
+    pub fn snapshot_entries(&self) -> Vec<&SnapshotEntry> {
+        let mut entries: Vec<&SnapshotEntry> = self.tangible_snapshot_journal.records.values().collect();
+        entries.sort_by_key(|entry| entry.created_time);
+
+        entries
+    }
+
+    /// Takes back a snapshot that was just taken, putting back the snapshot it replaced (if any)
+    /// and the snapshot the file was at before. Returns the entry that was taken back, whose
+    /// payloads may no longer be referenced.
+    pub fn undo_snap(
+        &mut self, snapshot_name: &str, replaced_entry: Option<SnapshotEntry>, previous_snapshot_name: Option<&str>
+    ) -> Result<Option<SnapshotEntry>, ManagedFileError> {
+        let undone_entry = self.tangible_snapshot_journal.remove(snapshot_name);
+
+        if let Some(replaced_entry) = replaced_entry {
+            self.tangible_snapshot_journal.add(replaced_entry);
+        }
+
+        match previous_snapshot_name {
+            Some(previous_snapshot_name) => self.set_current_snapshot_name(previous_snapshot_name),
+            None => { self.meta.remove(CURRENT_SNAPSHOT_META_KEY); }
+        }
+
+        self.commit()?;
+
+        Ok(undone_entry)
+    }
+
+    /// Removes a snapshot from the journal, without touching its payload. If the file was at
+    /// this snapshot, it is no longer considered to be at any snapshot.
+    pub fn remove_snapshot(&mut self, snapshot_name: &str) -> Option<SnapshotEntry> {
+        if self.get_current_snapshot_name() == Some(snapshot_name) {
+            self.meta.remove(CURRENT_SNAPSHOT_META_KEY);
+        }
+
+        self.tangible_snapshot_journal.remove(snapshot_name)
+    }
+
+    /// Gives a snapshot a new name, moving the current pointer along with it. A snapshot renamed
+    /// by hand counts as named, and is no longer pruned by retention policies. The payload is
+    /// stored by content, so it does not need to move.
+    pub fn rename_snapshot(&mut self, snapshot_name: &str, new_snapshot_name: &str) -> Result<(), ManagedFileError> {
+        let mut snapshot_entry = match self.tangible_snapshot_journal.remove(snapshot_name) {
+            Some(snapshot_entry) => snapshot_entry,
+            None => return Err(ManagedFileError)
+        };
+
+        let is_current = self.get_current_snapshot_name() == Some(snapshot_name);
+
+        snapshot_entry.snapshot_name = new_snapshot_name.to_owned();
+        snapshot_entry.auto_named = Some(false);
+        self.tangible_snapshot_journal.add(snapshot_entry);
+
+        if is_current {
+            self.set_current_snapshot_name(new_snapshot_name);
+        }
+
+        self.commit()
+    }
+
+    /// Changes the details of a snapshot (such as its comment or tags) and commits them. The name
+    /// cannot be changed this way, see `rename_snapshot`.
+    pub fn update_snapshot<F>(&mut self, snapshot_name: &str, update: F) -> Result<(), ManagedFileError>
+        where F: FnOnce(&mut SnapshotEntry) {
+        let mut snapshot_entry = match self.get_snapshot(snapshot_name) {
+            Some(snapshot_entry) => snapshot_entry.clone(),
+            None => return Err(ManagedFileError)
+        };
+
+        update(&mut snapshot_entry);
+        snapshot_entry.snapshot_name = snapshot_name.to_owned();
+        self.tangible_snapshot_journal.add(snapshot_entry);
+
+        self.commit()
+    }
+
+    /// Snapshots that have all of the given tags, oldest first
+    pub fn find_snapshots_by_tags(&self, tags: &[String]) -> Vec<&SnapshotEntry> {
+        self.snapshot_entries().into_iter()
+            .filter(|snapshot_entry| snapshot_entry.has_tags(tags))
+            .collect()
+    }
+
+    /// Locations of the stored payloads of a snapshot, whether in the object store or (for older
+    /// snapshots) in the managed directory. Directory snapshots have a payload for the manifest
+    /// and one for every file in it.
+    pub fn payload_paths(&self, snapshot_entry: &SnapshotEntry) -> Vec<PathBuf> {
+        if snapshot_entry.kind == SnapshotKind::Absent {
+            return Vec::new();
+        }
+
+        let mut payload_paths = vec![match snapshot_entry.object {
+            Some(ref hash) => self.object_store.path_for(hash, snapshot_entry.codec),
+            None => self.snapshot_storage.join(&snapshot_entry.relative_file_path)
+        }];
+
+        if snapshot_entry.kind == SnapshotKind::Tree {
+            if let Ok(manifest) = self.read_tree_manifest(snapshot_entry) {
+                payload_paths.extend(manifest.files.values()
+                    .map(|tree_file| self.object_store.path_for(&tree_file.object, tree_file.codec)));
+            }
+        }
+
+        payload_paths
+    }
+
+    pub fn check_payload(&self, snapshot_entry: &SnapshotEntry) -> PayloadStatus {
+        if snapshot_entry.kind == SnapshotKind::Absent {
+            return PayloadStatus::Intact;
+        }
+
+        let status = match snapshot_entry.object {
+            Some(ref hash) => self.object_store.verify(hash, snapshot_entry.codec),
+            None => {
+                if !self.snapshot_storage.join(&snapshot_entry.relative_file_path).exists() {
+                    PayloadStatus::Missing
+                } else if self.read_snapshot(snapshot_entry).is_err() {
+                    PayloadStatus::Corrupt
+                } else {
+                    PayloadStatus::Intact
+                }
+            }
+        };
+
+        if status != PayloadStatus::Intact || snapshot_entry.kind != SnapshotKind::Tree {
+            return status;
+        }
+
+        match self.read_tree_manifest(snapshot_entry) {
+            Ok(manifest) => manifest.files.values()
+                .map(|tree_file| self.object_store.verify(&tree_file.object, tree_file.codec))
+                .find(|status| *status != PayloadStatus::Intact)
+                .unwrap_or(PayloadStatus::Intact),
+            Err(_) => PayloadStatus::Corrupt
+        }
+    }
+
+    /// Tries to restore the missing or corrupt payloads of a snapshot in the object store, from
+    /// copies of the same content stored with a different codec, or from the target if it still
+    /// has the same content. Returns whether every payload could be restored.
+    pub fn rebuild_payload(&self, snapshot_entry: &SnapshotEntry) -> Result<bool, ManagedFileError> {
+        if snapshot_entry.kind == SnapshotKind::Absent {
+            return Ok(true);
+        }
+
+        let hash = match snapshot_entry.object {
+            Some(ref hash) => hash,
+            None => return Ok(false)
+        };
+
+        let live_file = match snapshot_entry.kind {
+            SnapshotKind::File if self.target_file.is_file() => Some(self.target_file.to_owned()),
+            _ => None
+        };
+
+        if !self.rebuild_object(hash, snapshot_entry.codec, live_file)? {
+            return Ok(false);
+        }
+
+        if snapshot_entry.kind == SnapshotKind::Tree {
+            let manifest = self.read_tree_manifest(snapshot_entry)?;
+
+            for (relative_path, tree_file) in &manifest.files {
+                let live_file = Some(self.target_file.join(relative_path)).filter(|path| path.is_file());
+
+                if !self.rebuild_object(&tree_file.object, tree_file.codec, live_file)? {
+                    return Ok(false);
+                }
+            }
+        }
+
+        Ok(true)
+    }
+
+    fn rebuild_object(&self, hash: &str, codec: Codec, live_file: Option<PathBuf>) -> Result<bool, ManagedFileError> {
+        if self.object_store.verify(hash, codec) == PayloadStatus::Intact {
+            return Ok(true);
+        }
+
+        let mut candidates: Vec<Vec<u8>> = Codec::all().into_iter()
+            .filter(|other_codec| *other_codec != codec)
+            .filter(|other_codec| self.object_store.verify(hash, *other_codec) == PayloadStatus::Intact)
+            .filter_map(|other_codec| self.object_store.get(hash, other_codec).ok())
+            .collect();
+
+        if let Some(live_file) = live_file {
+            let mut live_content = Vec::new();
+
+            if File::open(live_file).and_then(|mut f| f.read_to_end(&mut live_content)).is_ok() {
+                candidates.push(live_content);
+            }
+        }
+
+        for content in candidates {
+            if ObjectStore::hash(&content) == hash {
+                let _ = remove_file(self.object_store.path_for(hash, codec));
+                self.object_store.put(&content, codec)?;
+
+                return Ok(true);
+            }
+        }
+
+        Ok(false)
+    }
+
+    pub fn get_current_snapshot_name(&self) -> Option<&str> {
+        self.get_meta(CURRENT_SNAPSHOT_META_KEY)
+    }
+
+    pub fn get_meta(&self, key: &str) -> Option<&str> {
+        self.meta.records.get(key).map(|entry| entry.value.as_str())
+    }
+
+    pub fn set_meta(&mut self, key: &str, value: &str) {
+        self.meta.add(ManagedFileMetaEntry {
+            key: key.to_owned(),
+            value: value.to_owned()
+        });
+    }
+
+    pub fn remove_meta(&mut self, key: &str) {
+        self.meta.remove(key);
+    }
+
+    /// Whether the metadata holds more than the path and the current snapshot, like hooks, a
+    /// retention policy, tree filters or the symlink policy
+    pub fn has_settings(&self) -> bool {
+        self.meta.records.keys().any(|key| key != MANAGED_PATH_META_KEY && key != CURRENT_SNAPSHOT_META_KEY)
+    }
+
+    pub fn snapshot_journal_name(&self) -> &str {
+        &self.tangible_snapshot_journal.name
+    }
+
+    /// Lines in the journals of this file that could not be read, along with the name of the
+    /// journal they are in
+    pub fn rejected_lines(&self) -> Vec<(&str, &RejectedLine)> {
+        self.tangible_snapshot_journal.rejected_lines.iter()
+            .map(|line| (self.tangible_snapshot_journal.name.as_str(), line))
+            .chain(self.meta.rejected_lines.iter().map(|line| (self.meta.name.as_str(), line)))
+            .collect()
+    }
+
+    pub fn discard_rejected_lines(&mut self) -> Vec<(String, RejectedLine)> {
+        let snapshot_journal_name = self.tangible_snapshot_journal.name.to_owned();
+        let meta_name = self.meta.name.to_owned();
+
+        self.tangible_snapshot_journal.discard_rejected_lines().into_iter()
+            .map(|line| (snapshot_journal_name.to_owned(), line))
+            .chain(self.meta.discard_rejected_lines().into_iter().map(|line| (meta_name.to_owned(), line)))
+            .collect()
+    }
+
+    /// Brings the storage of this file up to date: journals are rewritten in the current format,
+    /// and payloads of snapshots taken before the object store existed are moved into it.
+    /// Returns the number of journals upgraded and the number of payloads moved.
+    pub fn migrate(&mut self) -> Result<(usize, usize), ManagedFileError> {
+        let mut upgraded_journals = 0;
+
+        if self.tangible_snapshot_journal.upgrade()? { upgraded_journals += 1; }
+        if self.meta.upgrade()? { upgraded_journals += 1; }
+
+        let legacy_entries: Vec<SnapshotEntry> = self.tangible_snapshot_journal.records.values()
+            .filter(|entry| entry.object.is_none() && entry.kind != SnapshotKind::Absent)
+            .cloned()
+            .collect();
+
+        let mut legacy_payloads = Vec::new();
+
+        for mut snapshot_entry in legacy_entries {
+            let content = self.read_snapshot(&snapshot_entry)?;
+            let stored_object = self.object_store.put(&content, snapshot_entry.codec)?;
+
+            info!("Moved payload of snapshot {} from {} to object {}", snapshot_entry.snapshot_name,
+                  snapshot_entry.relative_file_path, stored_object.hash);
+
+            legacy_payloads.push(self.snapshot_storage.join(&snapshot_entry.relative_file_path));
+
+            snapshot_entry.object = Some(stored_object.hash);
+            snapshot_entry.relative_file_path = String::new();
+            self.tangible_snapshot_journal.add(snapshot_entry);
+        }
+
+        // The legacy payloads are only removed once the journal no longer refers to them
+        self.commit()?;
+
+        for legacy_payload in &legacy_payloads {
+            remove_file(legacy_payload)?;
+        }
+
+        Ok((upgraded_journals, legacy_payloads.len()))
+    }
+
+    /// Writes the snapshot journal and the metadata. They are separate files, so when a commit
+    /// changes both the snapshots and the current snapshot, the metadata is written first with
+    /// the previous current snapshot recorded alongside the new one. If the snapshot journal is
+    /// then never written, the next time the file is opened can tell, and goes back to the
+    /// previous current snapshot (see `settle_interrupted_commit`).
+    pub fn commit(&mut self) -> Result<(), ManagedFileError> {
+        let current_snapshot_name = self.get_current_snapshot_name().map(String::from);
+        let moves_current = current_snapshot_name != self.committed_current_snapshot_name;
+
+        let written = if moves_current && self.tangible_snapshot_journal.is_dirty() {
+            let previous_snapshot_name = self.committed_current_snapshot_name.to_owned().unwrap_or_default();
+            self.set_meta(PREVIOUS_CURRENT_SNAPSHOT_META_KEY, &previous_snapshot_name);
+
+            self.meta.write()
+                .and_then(|_| self.tangible_snapshot_journal.write())
+                .and_then(|_| {
+                    self.meta.remove(PREVIOUS_CURRENT_SNAPSHOT_META_KEY);
+                    self.meta.write()
+                })
+        } else {
+            self.tangible_snapshot_journal.write().and_then(|_| self.meta.write())
+        };
+
+        match written {
+            Ok(_) => {
+                self.committed_current_snapshot_name = current_snapshot_name;
+                Ok(())
+            }
+
+            Err(e) => {
+                println!("{} Could not write the journal for {}: {}", "ERROR".red(),
+                         self.target_file.to_string_lossy(), e);
+                Err(ManagedFileError::from(e))
+            }
+        }
+    }
+
+    /// Finishes a commit that was interrupted after the metadata was written. If the snapshot
+    /// journal was not written either, the current snapshot points at a snapshot the journal
+    /// does not have (or, if it was cleared, the journal still has the previous one), and is set
+    /// back to what it was before. Under a shared lock this only happens in memory, so that
+    /// reads see the settled state, and the metadata is settled on disk once the file is next
+    /// opened for writing.
+    fn settle_interrupted_commit(&mut self, lock_mode: LockMode) {
+        let previous_snapshot_name = match self.get_meta(PREVIOUS_CURRENT_SNAPSHOT_META_KEY) {
+            Some(previous_snapshot_name) => previous_snapshot_name.to_owned(),
+            None => return
+        };
+
+        let journal_written = match self.get_current_snapshot_name() {
+            Some(snapshot_name) => snapshot_name == NULL_SNAPSHOT_NAME || self.get_snapshot(snapshot_name).is_some(),
+            None => previous_snapshot_name.is_empty() || self.get_snapshot(&previous_snapshot_name).is_none()
+        };
+
+        if !journal_written {
+            let shown_snapshot_name = if previous_snapshot_name.is_empty() { "none" } else { previous_snapshot_name.as_str() };
+
+            // Reads stay quiet, since some of them (such as `hippo cat`) write to standard output
+            if lock_mode == LockMode::Exclusive {
+                println!("{} {}, the last change to its journal was interrupted, its current snapshot was set back to {}",
+                         "WARN".yellow(), self.target_file.to_string_lossy(), shown_snapshot_name.cyan());
+            } else {
+                warn!("The last change to the journal of {:?} was interrupted, taking its current snapshot to be {}",
+                      self.target_file, shown_snapshot_name);
+            }
+
+            if previous_snapshot_name.is_empty() {
+                self.meta.remove(CURRENT_SNAPSHOT_META_KEY);
+            } else {
+                self.set_current_snapshot_name(&previous_snapshot_name);
+            }
+        }
+
+        self.meta.remove(PREVIOUS_CURRENT_SNAPSHOT_META_KEY);
+
+        if lock_mode == LockMode::Shared {
+            self.meta.keep_changes_in_memory();
+        }
+    }
+
+    fn record_managed_path(&mut self) {
+        let managed_path = self.target_file.to_str().unwrap();
+        let recorded = self.meta.records.get(MANAGED_PATH_META_KEY)
+            .is_some_and(|entry| entry.value == managed_path);
+
+        if !recorded {
+            self.meta.add(ManagedFileMetaEntry {
+                key: MANAGED_PATH_META_KEY.to_owned(),
+                value: managed_path.to_owned()
+            });
+        }
+    }
+
+    fn set_current_snapshot_name(&mut self, snapshot_name: &str) {
+        self.set_meta(CURRENT_SNAPSHOT_META_KEY, snapshot_name);
+    }
+
+    pub fn get_snapshots(&self) -> SnapshotsListing<'_> {
+        let mut snapshots = Vec::<Snapshot>::new();
+        let mut synthetic_count : usize = 0;
+        let mut tangible_count : usize = 0;
+
+        snapshots.push(Snapshot::Synthetic(SyntheticSnapshot::Null));
+        synthetic_count += 1;
+
+        for snapshot_entry in self.snapshot_entries() {
             snapshots.push(Snapshot::Tangible(snapshot_entry));
-            tangible_count = tangible_count + 1;;
+            tangible_count += 1;
+        }
+
+        if self.is_live().unwrap_or(false) {
+            if let Ok(modified_time) = metadata(self.target_file).and_then(|m| m.modified()) {
+                snapshots.push(Snapshot::Synthetic(SyntheticSnapshot::Live(DateTime::from(modified_time))));
+                synthetic_count += 1;
+            }
         }
 
-        return SnapshotsListing {
+        let current_snapshot_name = match snapshots.last() {
+            Some(&Snapshot::Synthetic(SyntheticSnapshot::Live(_))) => LIVE_SNAPSHOT_NAME.to_owned(),
+            _ => self.get_current_snapshot()
+                .map(|entry| entry.snapshot_name.to_owned())
+                .unwrap_or_else(|| NULL_SNAPSHOT_NAME.to_owned())
+        };
+
+        SnapshotsListing {
             snapshots,
+            current_snapshot_name,
             synthetic_count,
             tangible_count
         }
     }
 }
 
+/// Loads snapshots onto a number of managed files as a single change. The changes to every target
+/// are staged first, and only once all of them are staged (and validated by the validate hooks)
+/// are they applied. If applying any of them fails, every file that was already replaced is
+/// restored from the safety copy kept just before, and what happened to each of them is
+/// reported: the load goes through for all of the files, or for none of them. The pre-load and
+/// post-load hooks of each file run before anything is staged and after everything is loaded.
+/// Loads of a set snapshot are given the set and the name of its snapshot, for its hooks.
+///
+/// Targets with modifications that were never snapped are handed to `handle_live` (along with
+/// their index in `loads`) once the load has passed the pre-load and validate hooks, so that a
+/// load they stop does not leave anything behind. Since that may snap or stash the target, it
+/// is called while nothing is staged next to it, and the load is staged again afterwards.
+pub fn load_snapshots<F>(
+    mut loads: Vec<(&mut ManagedFile, &str)>, snapshot_set: Option<(&SnapshotSet, &str)>, mut handle_live: F
+) -> Result<(), ManagedFileError>
+    where F: FnMut(usize, &mut ManagedFile) -> Result<(), ManagedFileError>
+{
+    if let Some((snapshot_set, set_snapshot_name)) = snapshot_set {
+        if let Err(e) = snapshot_set.run_hook(Hook::PreLoad, set_snapshot_name, &[]) {
+            println!("{} Not loading snapshot {} of set {}, nothing was changed", "ERROR".red().bold(),
+                     set_snapshot_name.cyan(), snapshot_set.name.cyan());
+            return Err(ManagedFileError::from(e));
+        }
+    }
+
+    for &(ref managed_file, snapshot_name) in &loads {
+        if let Err(e) = managed_file.run_hook(Hook::PreLoad, snapshot_name, None) {
+            println!("{} Not loading snapshot {} for {}, nothing was changed", "ERROR".red().bold(),
+                     snapshot_name.cyan(), managed_file.target_file.to_string_lossy());
+            return Err(e);
+        }
+    }
+
+    // Checked before anything is staged, since staging files inside a directory would count as
+    // modifications
+    let mut live_files = Vec::new();
+
+    for (index, (managed_file, _)) in loads.iter().enumerate() {
+        if managed_file.check_live()? {
+            live_files.push(index);
+        }
+    }
+
+    let (mut staged_changes, mut staged_loads) = stage_loads(&loads)?;
+
+    if let Err(e) = validate_loads(&loads, &staged_loads, snapshot_set) {
+        staged_changes.roll_back();
+        println!("{} Not loading, nothing was changed", "ERROR".red().bold());
+        return Err(e);
+    }
+
+    if !live_files.is_empty() {
+        staged_changes.roll_back();
+
+        for index in live_files {
+            handle_live(index, loads[index].0)?;
+        }
+
+        let (restaged_changes, restaged_loads) = stage_loads(&loads)?;
+        staged_changes = restaged_changes;
+        staged_loads = restaged_loads;
+    }
+
+    if let Err(failed_change) = staged_changes.apply() {
+        println!("{} Could not replace {}: {}", "ERROR".red(), failed_change.target_file.to_string_lossy(),
+                 failed_change.error);
+        return Err(roll_back_load(staged_changes));
+    }
+
+    staged_changes.finish();
+
+    let mut completed = Ok(());
+
+    for ((managed_file, snapshot_name), staged_load) in loads.into_iter().zip(staged_loads) {
+        if let Err(e) = managed_file.complete_load(staged_load).and_then(|_| managed_file.run_hook(Hook::PostLoad, snapshot_name, None)) {
+            completed = Err(e);
+        }
+    }
+
+    completed
+}
+
+/// Stages the loads of every file as a single change
+fn stage_loads(loads: &[(&mut ManagedFile, &str)]) -> Result<(StagedChanges, Vec<StagedLoad>), ManagedFileError> {
+    let mut staged_changes = StagedChanges::new();
+    let mut staged_loads = Vec::new();
+
+    for &(ref managed_file, snapshot_name) in loads {
+        match managed_file.stage_load(snapshot_name, &mut staged_changes) {
+            Ok(staged_load) => staged_loads.push(staged_load),
+            Err(e) => {
+                staged_changes.roll_back();
+                println!("{} Not loading snapshot {} for {}, nothing was changed", "ERROR".red().bold(),
+                         snapshot_name.cyan(), managed_file.target_file.to_string_lossy());
+                return Err(e);
+            }
+        }
+    }
+
+    Ok((staged_changes, staged_loads))
+}
+
+/// Runs the validate hook of every file against the candidate contents of its load. The validate
+/// hook of the set the files are loaded through (if any) then runs once, with the files being
+/// loaded in `HIPPO_FILES` and their candidates in `HIPPO_CANDIDATES`, one per line in the same
+/// order (with an empty line for loads that remove the file).
+fn validate_loads(
+    loads: &[(&mut ManagedFile, &str)], staged_loads: &[StagedLoad], snapshot_set: Option<(&SnapshotSet, &str)>
+) -> Result<(), ManagedFileError> {
+    let set_validates = snapshot_set.is_some_and(|(snapshot_set, _)| snapshot_set.hook(Hook::Validate).is_some());
+    let mut candidates = Vec::new();
+
+    for (&(ref managed_file, snapshot_name), staged_load) in loads.iter().zip(staged_loads) {
+        if managed_file.hook(Hook::Validate).is_none() && !set_validates {
+            continue;
+        }
+
+        let candidate = managed_file.load_candidate(staged_load).map_err(|e| {
+            println!("{} Could not copy the contents of snapshot {} for {} to validate them: {}", "ERROR".red(),
+                     snapshot_name.cyan(), managed_file.target_file.to_string_lossy(), e);
+            e
+        })?;
+        managed_file.run_hook(Hook::Validate, snapshot_name, candidate.path.as_deref())?;
+        candidates.push((managed_file.target_file.to_string_lossy().into_owned(), candidate));
+    }
+
+    if let Some((snapshot_set, set_snapshot_name)) = snapshot_set.filter(|_| set_validates) {
+        let files: Vec<&str> = candidates.iter().map(|(file, _)| file.as_str()).collect();
+        let candidate_paths: Vec<String> = candidates.iter()
+            .map(|(_, candidate)| candidate.path.as_ref().map(|path| path.to_string_lossy().into_owned()).unwrap_or_default())
+            .collect();
+
+        snapshot_set.run_hook(Hook::Validate, set_snapshot_name, &[
+            ("HIPPO_FILES", files.join("\n")),
+            ("HIPPO_CANDIDATES", candidate_paths.join("\n"))
+        ])?;
+    }
+
+    Ok(())
+}
+
+/// Restores the files changed by a load that failed halfway, reporting what happened to each
+fn roll_back_load(staged_changes: StagedChanges) -> ManagedFileError {
+    let outcomes = staged_changes.roll_back();
+    let mut unrestored = 0;
+
+    for (path, outcome) in &outcomes {
+        match *outcome {
+            RollbackOutcome::Restored => {
+                println!("{} Restored {} to what it was before the load", "WARN".yellow(), path.to_string_lossy());
+            }
+
+            RollbackOutcome::Removed => {
+                println!("{} Removed {} again, it did not exist before the load", "WARN".yellow(), path.to_string_lossy());
+            }
+
+            RollbackOutcome::Failed { ref error, safety_copy: Some(ref safety_copy) } => {
+                unrestored += 1;
+                println!("{} Could not restore {}: {}. What it was before the load is kept at {}", "ERROR".red(),
+                         path.to_string_lossy(), error, safety_copy.to_string_lossy());
+            }
+
+            RollbackOutcome::Failed { ref error, safety_copy: None } => {
+                unrestored += 1;
+                println!("{} Could not remove {}, which did not exist before the load: {}", "ERROR".red(),
+                         path.to_string_lossy(), error);
+            }
+        }
+    }
+
+    if unrestored > 0 {
+        println!("{} Load failed, and {} of the {} files it had changed could not be restored", "ERROR".red().bold(),
+                 unrestored, outcomes.len());
+    } else if outcomes.is_empty() {
+        println!("{} Load failed, nothing was changed", "ERROR".red().bold());
+    } else {
+        println!("{} Load failed, every file it had changed was restored", "ERROR".red().bold());
+    }
+
+    ManagedFileError
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{read_to_string, write};
+
+    use journaling::journal::Journal;
+    use journaling::managed_file_journal::ManagedFileJournal;
+    use journaling::store_lock::LockMode;
+    use snap::snap;
+    use snap::tests::snap_options;
+    use utils::test_dir::TestDir;
+
+    use super::{validate_snapshot_name, Snapshot, SnapshotLabels, SyntheticSnapshot, CURRENT_SNAPSHOT_META_KEY,
+                LIVE_SNAPSHOT_NAME, PREVIOUS_CURRENT_SNAPSHOT_META_KEY};
+
+    fn strings(values: &[&str]) -> Vec<String> {
+        values.iter().map(|value| value.to_string()).collect()
+    }
+
+    #[test]
+    fn labels_are_parsed_from_tags_and_pairs() {
+        let labels = SnapshotLabels::parse(strings(&["stable", "prod", "stable"]),
+                                           strings(&["ticket=OPS-12", "query=a=b", "empty=", "ticket=OPS-13"]))
+            .unwrap();
+
+        assert_eq!(labels.tags.iter().collect::<Vec<_>>(), vec!["prod", "stable"]);
+        assert_eq!(labels.meta["ticket"], "OPS-13");
+        assert_eq!(labels.meta["query"], "a=b");
+        assert_eq!(labels.meta["empty"], "");
+        assert!(SnapshotLabels::parse(Vec::new(), Vec::new()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn malformed_labels_are_rejected() {
+        for tag in &["", "two words", "a,b", "a=b", "tab\tbed"] {
+            assert!(SnapshotLabels::parse(strings(&[tag]), Vec::new()).is_err(), "tag `{}` was accepted", tag);
+        }
+
+        for pair in &["no-equals", "=value", "two words=value", "a,b=value", "key=line\nbreak"] {
+            assert!(SnapshotLabels::parse(Vec::new(), strings(&[pair])).is_err(), "pair `{}` was accepted", pair);
+        }
+    }
+
+    #[test]
+    fn snapshot_names_are_validated() {
+        for name in &["v1", "before upgrade", "2026-01-14 10:00:00", "(draft", "draft)", "naïve", "a.b-c_d"] {
+            assert!(validate_snapshot_name(name).is_ok(), "`{}` was rejected", name);
+        }
+
+        for name in &["", "   ", "(null)", "(live)", "(anything)", "a/b", "a\\b", "tab\there", "line\nbreak"] {
+            assert!(validate_snapshot_name(name).is_err(), "`{}` was accepted", name.escape_default());
+        }
+    }
+
+    #[test]
+    fn edits_since_the_last_snap_show_up_as_a_live_snapshot() {
+        let test_dir = TestDir::new("managed-file");
+        let target_file = test_dir.join("app.conf");
+
+        write(&target_file, "first\n").unwrap();
+        let mut journal = test_dir.open_store();
+        assert!(snap(&mut journal, snap_options("v1"), vec![target_file.to_owned()]).is_ok());
+
+        let listing = |journal: &mut Journal| {
+            let managed_file_journal = ManagedFileJournal::for_journal(journal);
+            let managed_file = managed_file_journal.get_managed_file(&target_file).ok().unwrap().unwrap();
+            let snapshots = managed_file.get_snapshots();
+            let live = matches!(snapshots.snapshots.last(), Some(&Snapshot::Synthetic(SyntheticSnapshot::Live(_))));
+
+            (snapshots.tangible_count, snapshots.synthetic_count, live, snapshots.current_snapshot_name)
+        };
+
+        assert_eq!(listing(&mut journal), (1, 1, false, String::from("v1")));
+
+        write(&target_file, "edited\n").unwrap();
+        assert_eq!(listing(&mut journal), (1, 2, true, String::from(LIVE_SNAPSHOT_NAME)));
+
+        assert!(snap(&mut journal, snap_options("v2"), vec![target_file.to_owned()]).is_ok());
+        assert_eq!(listing(&mut journal), (2, 1, false, String::from("v2")));
+    }
+
+    #[test]
+    fn current_snapshot_is_set_back_when_the_journal_was_not_written() {
+        let test_dir = TestDir::new("managed-file");
+        let target_file = test_dir.join("app.conf");
+
+        write(&target_file, "first\n").unwrap();
+
+        let meta_file = {
+            let mut journal = test_dir.open_store();
+            let mut managed_file_journal = ManagedFileJournal::for_journal(&mut journal);
+            let mut managed_file = managed_file_journal.create_or_get_managed_file(&target_file).ok().unwrap();
+
+            // As left behind by a commit that wrote the metadata, but not the snapshot journal
+            managed_file.set_meta(CURRENT_SNAPSHOT_META_KEY, "v1");
+            managed_file.set_meta(PREVIOUS_CURRENT_SNAPSHOT_META_KEY, "");
+            assert!(managed_file.meta.write().is_ok());
+
+            drop(managed_file);
+            journal.get_record(&target_file.to_string_lossy().into_owned()).root.join("__meta")
+        };
+
+        // Reads see the settled state, but leave the metadata as it is
+        for &lock_mode in &[LockMode::Shared, LockMode::Exclusive] {
+            {
+                let mut journal = test_dir.open_store_locked(lock_mode);
+                let managed_file_journal = ManagedFileJournal::for_journal(&mut journal);
+                let managed_file = managed_file_journal.get_managed_file(&target_file).ok().unwrap().unwrap();
+
+                assert_eq!(managed_file.get_current_snapshot_name(), None);
+                assert_eq!(managed_file.get_meta(PREVIOUS_CURRENT_SNAPSHOT_META_KEY), None);
+            }
+
+            let meta = read_to_string(&meta_file).unwrap();
+            assert_eq!(meta.contains(PREVIOUS_CURRENT_SNAPSHOT_META_KEY), lock_mode == LockMode::Shared);
+        }
+    }
+}
@@ -3,16 +3,22 @@ extern crate uuid;
 
 use std::path::PathBuf;
 use std::path::Path;
-use std::fs::{create_dir_all, OpenOptions};
+use std::fs::{create_dir_all, remove_file, OpenOptions};
+use std::collections::HashSet;
 use std::{ffi, io};
 
 use journaling::journal::*;
 use journaling::managed_file::*;
+use journaling::store_lock::{StoreLock, StoreLockError};
+use utils::simple_file_records::SimpleFileRecords;
 
 use self::uuid::Uuid;
+use self::colored::*;
 use commons::HIPPO_PRINTABLE;
 
-static MANAGED_FILE_SNAPSHOT_JOURNAL_FILE_NAME: &'static str = "__snaps_journal";
+static MANAGED_FILE_SNAPSHOT_JOURNAL_FILE_NAME: &str = "__snaps_journal";
+static MANAGED_FILE_META_FILE_NAME: &str = "__meta";
+static MANAGED_FILE_LOCK_FILE_NAME: &str = "__lock";
 
 pub struct ManagedFileJournal<'a> {
     root_journal: &'a mut Journal
@@ -28,6 +34,10 @@ impl From<io::Error> for ManagedFileJournalError {
     fn from(_: io::Error) -> Self { ManagedFileJournalError }
 }
 
+impl From<StoreLockError> for ManagedFileJournalError {
+    fn from(_: StoreLockError) -> Self { ManagedFileJournalError }
+}
+
 impl <'a> ManagedFileJournal<'a> {
     pub fn for_journal(root_journal: &'a mut Journal) -> ManagedFileJournal<'a> {
         ManagedFileJournal {
@@ -35,7 +45,7 @@ impl <'a> ManagedFileJournal<'a> {
         }
     }
 
-    pub fn create_or_get_managed_file(&'a mut self, file_path: &'a PathBuf) -> Result<ManagedFile<'a>, ManagedFileJournalError>  {
+    pub fn create_or_get_managed_file<'b>(&'b mut self, file_path: &'b PathBuf) -> Result<ManagedFile<'b>, ManagedFileJournalError>  {
         let file_key_in_record = file_path.to_owned().into_os_string().into_string()?;
 
         if !self.root_journal.contains_record(&file_key_in_record) {
@@ -43,33 +53,152 @@ impl <'a> ManagedFileJournal<'a> {
                  Creating a new managed directory", file_path);
 
             self.new_managed_file(file_path);
+            self.root_journal.commit().map_err(|e| {
+                println!("{} Could not write the root journal: {}", "ERROR".red(), e);
+                e
+            })?;
         }
 
-        return Ok(self.get_managed_file(file_path)
+        return Ok(self.get_managed_file(file_path)?
             .expect("Assertion failure. A new managed file was created after detecting an absence, but is still returning as not-present")
         )
     }
 
-    pub fn get_managed_file(&'a self, file_path: &'a PathBuf) -> Option<ManagedFile<'a>> {
+    /// Opens the managed file for the given path, if it is managed by the journal. The managed
+    /// directory is locked in the same mode as the root journal for as long as the returned
+    /// managed file is alive.
+    pub fn get_managed_file<'b>(&'b self, file_path: &'b PathBuf) -> Result<Option<ManagedFile<'b>>, ManagedFileJournalError> {
         let file_key_in_record = file_path.to_owned().into_os_string().into_string().unwrap();
 
         if !self.root_journal.contains_record(&file_key_in_record) {
-            None
+            Ok(None)
         } else {
             let managed_file_record = self.root_journal.get_record(&file_key_in_record);
-
-            Some(ManagedFile::new(
-                &file_path,
+            let lock = StoreLock::acquire(
+                managed_file_record.root.join(MANAGED_FILE_LOCK_FILE_NAME),
+                self.root_journal.lock_mode,
+                self.root_journal.wait_for_locks
+            )?;
+
+            Ok(Some(ManagedFile::new(
+                file_path,
                 managed_file_record.root.join(MANAGED_FILE_SNAPSHOT_JOURNAL_FILE_NAME),
-                &managed_file_record.root
-            ))
+                managed_file_record.root.join(MANAGED_FILE_META_FILE_NAME),
+                self.root_journal.object_store(),
+                &managed_file_record.root,
+                lock
+            )?))
         }
     }
 
+    /// Collects the payload paths of every snapshot of every managed file, which is everything
+    /// in the store that must not be garbage collected. Managed files that are currently open
+    /// (and locked) elsewhere cannot be read, so they should be closed before calling this.
+    pub fn referenced_payloads(&self) -> Result<HashSet<PathBuf>, ManagedFileJournalError> {
+        let mut referenced_payloads = HashSet::new();
+        let managed_paths: Vec<PathBuf> = self.root_journal.root_journal_config.records.values()
+            .filter(|entry| entry.root.is_dir())
+            .map(|entry| PathBuf::from(&entry.key))
+            .collect();
+
+        for file_path in managed_paths {
+            if let Some(managed_file) = self.get_managed_file(&file_path)? {
+                for snapshot_entry in managed_file.snapshot_entries() {
+                    referenced_payloads.extend(managed_file.payload_paths(snapshot_entry));
+                }
+            }
+        }
+
+        Ok(referenced_payloads)
+    }
+
+    /// Names of the journals with lines that could not be read. Those lines may hold snapshots
+    /// that still refer to payloads, so no payload can safely be considered unreferenced until
+    /// `hippo fsck` has dealt with them.
+    pub fn unreadable_journals(&self) -> Result<Vec<String>, ManagedFileJournalError> {
+        let mut unreadable_journals = Vec::new();
+
+        if !self.root_journal.root_journal_config.rejected_lines.is_empty() {
+            unreadable_journals.push(self.root_journal.root_journal_config.name.to_owned());
+        }
+
+        if !self.root_journal.sets.rejected_lines.is_empty() {
+            unreadable_journals.push(self.root_journal.sets.name.to_owned());
+        }
+
+        let managed_paths: Vec<PathBuf> = self.root_journal.root_journal_config.records.values()
+            .filter(|entry| entry.root.is_dir())
+            .map(|entry| PathBuf::from(&entry.key))
+            .collect();
+
+        for file_path in managed_paths {
+            if let Some(managed_file) = self.get_managed_file(&file_path)? {
+                let mut journal_names: Vec<String> = managed_file.rejected_lines().iter()
+                    .map(|&(journal_name, _)| journal_name.to_owned())
+                    .collect();
+                journal_names.dedup();
+                unreadable_journals.extend(journal_names);
+            }
+        }
+
+        unreadable_journals.sort();
+        Ok(unreadable_journals)
+    }
+
+    /// Removes the given payloads, unless a snapshot of some managed file still refers to them
+    /// (payloads are shared between snapshots with identical content). Nothing is removed while
+    /// any journal has lines that could not be read. Returns the number of bytes reclaimed.
+    pub fn remove_unreferenced_payloads(&self, mut payloads: Vec<PathBuf>) -> Result<u64, ManagedFileJournalError> {
+        let unreadable_journals = self.unreadable_journals()?;
+
+        if !unreadable_journals.is_empty() {
+            println!("{} Kept the payloads that are no longer referenced, since {} could not be fully read. \
+                      Run `hippo fsck`, then `hippo gc` to reclaim them", "WARN".yellow(), unreadable_journals.join(", "));
+            return Ok(0);
+        }
+
+        let referenced_payloads = self.referenced_payloads()?;
+        let mut reclaimed = 0;
+
+        payloads.sort();
+        payloads.dedup();
+
+        for payload in payloads.iter().filter(|payload| !referenced_payloads.contains(*payload)) {
+            if let Ok(payload_metadata) = payload.metadata() {
+                info!("Removing payload {:?}, which is no longer referenced", payload);
+
+                remove_file(payload)?;
+                reclaimed += payload_metadata.len();
+            }
+        }
+
+        self.root_journal.object_store().remove_empty_dirs()?;
+
+        Ok(reclaimed)
+    }
+
+    /// Reads the path of the file a managed directory belongs to from its metadata, without
+    /// opening (and locking) it as a managed file. Returns `None` if the directory has no
+    /// metadata, or predates the path being recorded.
+    pub fn recorded_managed_path(managed_dir: &Path) -> Option<PathBuf> {
+        let meta_file = managed_dir.join(MANAGED_FILE_META_FILE_NAME);
+
+        if !meta_file.is_file() {
+            return None;
+        }
+
+        let meta: SimpleFileRecords<ManagedFileMetaEntry> = SimpleFileRecords::new(
+            format!("meta({})", managed_dir.to_string_lossy()),
+            meta_file
+        ).ok()?;
+
+        meta.records.get(MANAGED_PATH_META_KEY).map(|entry| PathBuf::from(&entry.value))
+    }
+
     fn new_managed_file(&mut self, file_path: &PathBuf) -> PathBuf {
         let managed_file_actual_path = file_path.to_owned().into_os_string().into_string().unwrap();
 
-        println!("{} is not managing {}, creating new journal", HIPPO_PRINTABLE.to_string(), managed_file_actual_path);
+        println!("{} is not managing {}, creating new journal", *HIPPO_PRINTABLE, managed_file_actual_path);
 
         info!("The root hosted by this journal is at {:?}", self.root_journal.root);
 
@@ -90,6 +219,7 @@ impl <'a> ManagedFileJournal<'a> {
 
         OpenOptions::new()
             .create(true)
+            .truncate(false)
             .write(true)
             .read(true)
             .open(managed_file_snaps_journal)
@@ -100,6 +230,6 @@ impl <'a> ManagedFileJournal<'a> {
 
         self.root_journal.add_record(file_path.to_owned(), managed_file_journal_dir.to_owned());
 
-        return managed_file_journal_dir;
+        managed_file_journal_dir
     }
 }
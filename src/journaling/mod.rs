@@ -1,3 +1,9 @@
 pub mod journal;
 pub mod managed_file;
 pub mod managed_file_journal;
+pub mod object_store;
+pub mod store_lock;
+pub mod retention;
+pub mod tree;
+pub mod snapshot_set;
+pub mod hooks;
@@ -0,0 +1,225 @@
+extern crate sha2;
+extern crate uuid;
+
+use std::path::{Path, PathBuf};
+use std::fs::{create_dir_all, read_dir, remove_dir, rename, remove_file, File};
+use std::io;
+use std::io::{Read, Write};
+
+use self::sha2::{Digest, Sha256};
+use self::uuid::Uuid;
+
+use super::super::utils::compression::Codec;
+
+static OBJECT_STORE_DIR_NAME: &str = "__objects";
+
+/// Content-addressed storage for snapshot payloads, shared by every file managed under a
+/// journal. Objects are keyed by the SHA-256 of their (uncompressed) content, so identical
+/// content is only ever stored once per codec.
+pub struct ObjectStore {
+    root: PathBuf
+}
+
+#[derive(Debug, PartialEq)]
+pub enum PayloadStatus {
+    Intact,
+    Missing,
+    /// The payload could not be decoded, or its content does not match the hash it is stored under
+    Corrupt
+}
+
+pub struct StoredObject {
+    pub hash: String,
+    pub stored_size: u64,
+    /// Whether the object was written by this call, as opposed to already being present
+    pub created: bool
+}
+
+impl ObjectStore {
+    pub fn for_journal_root(journal_root: &Path) -> ObjectStore {
+        ObjectStore {
+            root: journal_root.join(OBJECT_STORE_DIR_NAME)
+        }
+    }
+
+    pub fn hash(content: &[u8]) -> String {
+        format!("{:x}", Sha256::digest(content))
+    }
+
+    pub fn path_for(&self, hash: &str, codec: Codec) -> PathBuf {
+        self.root
+            .join(&hash[..2])
+            .join(format!("{}{}", &hash[2..], codec.extension()))
+    }
+
+    pub fn put(&self, content: &[u8], codec: Codec) -> io::Result<StoredObject> {
+        let hash = ObjectStore::hash(content);
+        let object_path = self.path_for(&hash, codec);
+
+        if let Ok(existing_object) = object_path.metadata() {
+            info!("Object {} already exists at {:?}, not writing it again", hash, object_path);
+
+            return Ok(StoredObject { hash, stored_size: existing_object.len(), created: false });
+        }
+
+        let payload = codec.encode(content)?;
+        let object_dir = object_path.parent().unwrap();
+        let staging_path = object_dir.join(format!(".{}", Uuid::new_v4()));
+
+        info!("Writing object {} to {:?}", hash, object_path);
+
+        create_dir_all(object_dir)?;
+
+        let written = File::create(&staging_path)
+            .and_then(|mut f| {
+                f.write_all(&payload)?;
+                f.sync_all()
+            })
+            .and_then(|_| rename(&staging_path, &object_path));
+
+        if let Err(e) = written {
+            let _ = remove_file(&staging_path);
+            return Err(e);
+        }
+
+        Ok(StoredObject { hash, stored_size: payload.len() as u64, created: true })
+    }
+
+    /// Reads an object back. An object whose content no longer matches its hash is an error,
+    /// rather than being passed off as the content that was stored.
+    pub fn get(&self, hash: &str, codec: Codec) -> io::Result<Vec<u8>> {
+        let mut payload = Vec::new();
+        File::open(self.path_for(hash, codec))?.read_to_end(&mut payload)?;
+
+        let content = codec.decode(&payload)?;
+
+        if ObjectStore::hash(&content) != hash {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("Object {} is corrupt", hash)));
+        }
+
+        Ok(content)
+    }
+
+    /// Lists the files in the store along with their sizes. Besides objects, this includes any
+    /// staging files left behind by writes that were interrupted.
+    pub fn files(&self) -> io::Result<Vec<(PathBuf, u64)>> {
+        let mut files = Vec::new();
+
+        if !self.root.is_dir() {
+            return Ok(files);
+        }
+
+        for object_dir in read_dir(&self.root)? {
+            let object_dir = object_dir?.path();
+
+            if !object_dir.is_dir() {
+                continue;
+            }
+
+            for object in read_dir(&object_dir)? {
+                let object = object?;
+                files.push((object.path(), object.metadata()?.len()));
+            }
+        }
+
+        files.sort();
+        Ok(files)
+    }
+
+    /// Removes the directories objects are fanned out into (by the first two characters of their
+    /// hash) once they no longer hold any objects
+    pub fn remove_empty_dirs(&self) -> io::Result<()> {
+        if !self.root.is_dir() {
+            return Ok(());
+        }
+
+        for object_dir in read_dir(&self.root)? {
+            let object_dir = object_dir?.path();
+
+            if object_dir.is_dir() && read_dir(&object_dir)?.next().is_none() {
+                remove_dir(&object_dir)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Checks that an object exists, can be decoded and still hashes to its key.
+    pub fn verify(&self, hash: &str, codec: Codec) -> PayloadStatus {
+        if !self.path_for(hash, codec).exists() {
+            return PayloadStatus::Missing;
+        }
+
+        match self.get(hash, codec) {
+            Ok(_) => PayloadStatus::Intact,
+            Err(_) => PayloadStatus::Corrupt
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{read, write};
+
+    use utils::compression::Codec;
+    use utils::test_dir::TestDir;
+
+    use super::{ObjectStore, PayloadStatus};
+
+    #[test]
+    fn same_content_is_stored_once() {
+        let test_dir = TestDir::new("object-store");
+        let object_store = ObjectStore::for_journal_root(&test_dir);
+
+        let first = object_store.put(b"contents\n", Codec::None).unwrap();
+        let second = object_store.put(b"contents\n", Codec::None).unwrap();
+
+        assert!(first.created);
+        assert!(!second.created);
+        assert_eq!(first.hash, second.hash);
+        assert_eq!(object_store.files().unwrap().len(), 1);
+
+        // Each codec keeps its own copy
+        assert!(object_store.put(b"contents\n", Codec::Gzip).unwrap().created);
+        assert_eq!(object_store.files().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn stored_content_reads_back_unchanged() {
+        let test_dir = TestDir::new("object-store");
+        let object_store = ObjectStore::for_journal_root(&test_dir);
+        let content: Vec<u8> = (0..4096).map(|i| (i * 7 % 256) as u8).collect();
+
+        for codec in Codec::all() {
+            let stored_object = object_store.put(&content, codec).unwrap();
+
+            assert_eq!(stored_object.hash, ObjectStore::hash(&content));
+            assert_eq!(object_store.get(&stored_object.hash, codec).unwrap(), content);
+            assert_eq!(object_store.verify(&stored_object.hash, codec), PayloadStatus::Intact);
+        }
+    }
+
+    #[test]
+    fn corrupt_objects_are_reported_rather_than_read() {
+        let test_dir = TestDir::new("object-store");
+        let object_store = ObjectStore::for_journal_root(&test_dir);
+
+        for codec in Codec::all() {
+            let hash = object_store.put(b"contents\n", codec).unwrap().hash;
+            let object_path = object_store.path_for(&hash, codec);
+
+            // Still decodes fine, but is not the content that was stored
+            write(&object_path, codec.encode(b"altered\n").unwrap()).unwrap();
+            assert_eq!(object_store.verify(&hash, codec), PayloadStatus::Corrupt);
+            assert!(object_store.get(&hash, codec).is_err());
+
+            // Cannot be decoded at all
+            let mut truncated = read(&object_path).unwrap();
+            truncated.truncate(truncated.len() / 2);
+            write(&object_path, truncated).unwrap();
+            assert_eq!(object_store.verify(&hash, codec), PayloadStatus::Corrupt);
+        }
+
+        assert_eq!(object_store.verify(&ObjectStore::hash(b"never stored"), Codec::None), PayloadStatus::Missing);
+    }
+}
@@ -0,0 +1,245 @@
+extern crate chrono;
+
+use std::cmp::Reverse;
+use std::collections::HashSet;
+use std::fmt;
+use std::str::FromStr;
+
+use self::chrono::prelude::*;
+use self::chrono::Duration;
+
+use super::managed_file::SnapshotEntry;
+
+/// Key under which a retention policy is stored, both in the global configuration and in the
+/// metadata of a managed file (which takes precedence)
+pub static RETENTION_POLICY_KEY: &str = "retention";
+
+/// Rules deciding which snapshots of a file are kept when it is pruned. A snapshot is kept if
+/// any of the rules keeps it. Snapshots that were named by hand are never pruned unless the
+/// policy opts in with `prune-named`, and neither is the snapshot the file is currently at.
+///
+/// Policies are written as a comma separated list of rules, such as `last=10,daily=30,weekly=52`:
+///
+/// * `last=N` keeps the N most recent snapshots
+/// * `daily=DAYS` keeps the most recent snapshot of each day, for the last DAYS days
+/// * `weekly=WEEKS` keeps the most recent snapshot of each week, for the last WEEKS weeks
+/// * `prune-named` allows snapshots that were named by hand to be pruned as well
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RetentionPolicy {
+    pub keep_last: Option<usize>,
+    pub keep_daily: Option<i64>,
+    pub keep_weekly: Option<i64>,
+    pub prune_named: bool
+}
+
+impl RetentionPolicy {
+    /// Picks the snapshots that the policy does not keep, out of the given ones
+    pub fn select_for_pruning<'e>(
+        &self, snapshot_entries: &[&'e SnapshotEntry], current_snapshot_name: Option<&str>, now: DateTime<Utc>
+    ) -> Vec<&'e SnapshotEntry> {
+        let mut newest_first: Vec<&SnapshotEntry> = snapshot_entries.to_vec();
+        newest_first.sort_by_key(|entry| Reverse(entry.created_time));
+
+        let mut kept: HashSet<&str> = HashSet::new();
+
+        if let Some(keep_last) = self.keep_last {
+            kept.extend(newest_first.iter().take(keep_last).map(|entry| entry.snapshot_name.as_str()));
+        }
+
+        if let Some(days) = self.keep_daily {
+            kept.extend(newest_per_period(&newest_first, now - Duration::days(days), |time| {
+                (time.year(), time.ordinal())
+            }));
+        }
+
+        if let Some(weeks) = self.keep_weekly {
+            kept.extend(newest_per_period(&newest_first, now - Duration::weeks(weeks), |time| {
+                let week = time.iso_week();
+                (week.year(), week.week())
+            }));
+        }
+
+        newest_first.into_iter()
+            .filter(|entry| !kept.contains(entry.snapshot_name.as_str()))
+            .filter(|entry| Some(entry.snapshot_name.as_str()) != current_snapshot_name)
+            .filter(|entry| self.prune_named || entry.is_auto_named())
+            .collect()
+    }
+}
+
+/// Names of the newest snapshot in every period (as told apart by `period_of`, in local time)
+/// since the given cutoff. Expects the snapshots newest first.
+fn newest_per_period<'e, F>(newest_first: &[&'e SnapshotEntry], cutoff: DateTime<Utc>, period_of: F) -> Vec<&'e str>
+    where F: Fn(DateTime<Local>) -> (i32, u32) {
+    let mut seen_periods = HashSet::new();
+
+    newest_first.iter()
+        .take_while(|entry| entry.created_time >= cutoff)
+        .filter(|entry| seen_periods.insert(period_of(entry.created_time.with_timezone(&Local))))
+        .map(|entry| entry.snapshot_name.as_str())
+        .collect()
+}
+
+impl FromStr for RetentionPolicy {
+    type Err = String;
+
+    fn from_str(spec: &str) -> Result<Self, Self::Err> {
+        let mut policy = RetentionPolicy::default();
+
+        for rule in spec.split(',').map(str::trim).filter(|rule| !rule.is_empty()) {
+            let (name, value) = match rule.split_once('=') {
+                Some((name, value)) => (name.trim(), Some(value.trim())),
+                None => (rule, None)
+            };
+
+            let count = value.map(|value| value.parse::<u32>()
+                .map_err(|_| format!("`{}` is not a valid count for `{}`", value, name)));
+
+            match (name, count) {
+                ("last", Some(count)) => policy.keep_last = Some(count? as usize),
+                ("daily", Some(count)) => policy.keep_daily = Some(count? as i64),
+                ("weekly", Some(count)) => policy.keep_weekly = Some(count? as i64),
+                ("prune-named", None) => policy.prune_named = true,
+                _ => return Err(format!("`{}` is not a retention rule, expected one of `last=N`, `daily=DAYS`, \
+                                         `weekly=WEEKS` or `prune-named`", rule))
+            }
+        }
+
+        if policy.keep_last.is_none() && policy.keep_daily.is_none() && policy.keep_weekly.is_none() {
+            return Err(String::from("A retention policy needs at least one of `last`, `daily` or `weekly`"));
+        }
+
+        Ok(policy)
+    }
+}
+
+impl fmt::Display for RetentionPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut rules = Vec::new();
+
+        if let Some(keep_last) = self.keep_last { rules.push(format!("last={}", keep_last)); }
+        if let Some(days) = self.keep_daily { rules.push(format!("daily={}", days)); }
+        if let Some(weeks) = self.keep_weekly { rules.push(format!("weekly={}", weeks)); }
+        if self.prune_named { rules.push(String::from("prune-named")); }
+
+        write!(f, "{}", rules.join(","))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeMap, BTreeSet};
+
+    use journaling::managed_file::SnapshotEntry;
+
+    use super::RetentionPolicy;
+    use super::chrono::prelude::*;
+
+    /// A point in local time, since that is what snapshots are bucketed into days and weeks by
+    fn local(year: i32, month: u32, day: u32, hour: u32, minute: u32) -> DateTime<Utc> {
+        Local.with_ymd_and_hms(year, month, day, hour, minute, 0).unwrap().with_timezone(&Utc)
+    }
+
+    fn entry(snapshot_name: &str, created_time: DateTime<Utc>, auto_named: bool) -> SnapshotEntry {
+        SnapshotEntry {
+            snapshot_name: snapshot_name.to_owned(),
+            comment: String::new(),
+            created_time,
+            relative_file_path: String::new(),
+            author: String::new(),
+            codec: Default::default(),
+            object: None,
+            auto_named: Some(auto_named),
+            tags: BTreeSet::new(),
+            meta: BTreeMap::new(),
+            kind: Default::default(),
+            metadata: None
+        }
+    }
+
+    fn pruned(policy: &str, entries: &[SnapshotEntry], current_snapshot_name: Option<&str>) -> Vec<String> {
+        let policy: RetentionPolicy = policy.parse().unwrap();
+        let entries: Vec<&SnapshotEntry> = entries.iter().collect();
+        let mut pruned: Vec<String> = policy.select_for_pruning(&entries, current_snapshot_name, local(2026, 1, 14, 12, 0))
+            .into_iter()
+            .map(|entry| entry.snapshot_name.to_owned())
+            .collect();
+
+        pruned.sort();
+        pruned
+    }
+
+    #[test]
+    fn last_keeps_the_newest_snapshots() {
+        let entries: Vec<SnapshotEntry> = (1..6)
+            .map(|day| entry(&format!("s{}", day), local(2026, 1, day, 10, 0), true))
+            .collect();
+
+        assert_eq!(pruned("last=2", &entries, None), vec!["s1", "s2", "s3"]);
+        assert_eq!(pruned("last=10", &entries, None), Vec::<String>::new());
+    }
+
+    #[test]
+    fn daily_keeps_the_newest_snapshot_of_each_recent_day() {
+        let entries = vec![
+            entry("today-late", local(2026, 1, 14, 9, 0), true),
+            entry("today-early", local(2026, 1, 14, 0, 1), true),
+            entry("yesterday-late", local(2026, 1, 13, 23, 59), true),
+            entry("yesterday-early", local(2026, 1, 13, 0, 1), true),
+            entry("before-cutoff", local(2026, 1, 10, 12, 0), true)
+        ];
+
+        assert_eq!(pruned("daily=3", &entries, None), vec!["before-cutoff", "today-early", "yesterday-early"]);
+    }
+
+    #[test]
+    fn weekly_keeps_the_newest_snapshot_of_each_recent_iso_week() {
+        let entries = vec![
+            // Monday and the Sunday right before it are in different weeks
+            entry("monday", local(2026, 1, 12, 0, 30), true),
+            entry("sunday", local(2026, 1, 11, 23, 30), true),
+            entry("saturday", local(2026, 1, 10, 12, 0), true),
+            // The first ISO week of 2026 starts in 2025
+            entry("new-year", local(2026, 1, 2, 10, 0), true),
+            entry("old-year", local(2025, 12, 30, 10, 0), true),
+            entry("last-week-of-2025", local(2025, 12, 28, 10, 0), true),
+            entry("before-cutoff", local(2025, 12, 20, 10, 0), true)
+        ];
+
+        assert_eq!(pruned("weekly=3", &entries, None), vec!["before-cutoff", "old-year", "saturday"]);
+    }
+
+    #[test]
+    fn current_and_named_snapshots_are_never_pruned() {
+        let entries = vec![
+            entry("newest", local(2026, 1, 5, 10, 0), true),
+            entry("current", local(2026, 1, 4, 10, 0), true),
+            entry("release", local(2026, 1, 3, 10, 0), false),
+            entry("oldest", local(2026, 1, 2, 10, 0), true)
+        ];
+
+        assert_eq!(pruned("last=1", &entries, Some("current")), vec!["oldest"]);
+        assert_eq!(pruned("last=1,prune-named", &entries, Some("current")), vec!["oldest", "release"]);
+    }
+
+    #[test]
+    fn policies_round_trip_through_display() {
+        for spec in &["last=10,daily=30,weekly=52,prune-named", "last=3", "daily=7,prune-named", "weekly=0"] {
+            let policy: RetentionPolicy = spec.parse().unwrap();
+
+            assert_eq!(policy.to_string(), *spec);
+            assert_eq!(policy.to_string().parse::<RetentionPolicy>(), Ok(policy));
+        }
+
+        let policy: RetentionPolicy = " weekly = 4 , last=2 ,".parse().unwrap();
+        assert_eq!(policy.to_string(), "last=2,weekly=4");
+    }
+
+    #[test]
+    fn malformed_policies_are_rejected() {
+        for spec in &["", "prune-named", "last", "last=", "last=x", "last=-1", "daily=1.5", "monthly=3",
+                      "prune-named=1", "last=2;daily=3"] {
+            assert!(spec.parse::<RetentionPolicy>().is_err(), "`{}` was accepted", spec);
+        }
+    }
+}
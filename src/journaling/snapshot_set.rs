@@ -0,0 +1,92 @@
+extern crate chrono;
+extern crate serde_json;
+
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+use self::chrono::prelude::*;
+
+use super::super::utils::simple_file_records::{SimpleRecord, MapsToSimpleRecord};
+use super::hooks::{run_hook, Hook, HookFailure};
+
+/// A named group of managed files that are snapped and loaded together, such as all the files
+/// making up the configuration of a service
+#[derive(Serialize, Deserialize, Clone)]
+pub struct SnapshotSet {
+    pub name: String,
+    pub members: Vec<PathBuf>,
+    /// Snapshots taken of the whole set, by name
+    #[serde(default)]
+    pub snapshots: BTreeMap<String, SetSnapshot>,
+    /// Commands run around snapping and loading the whole set, by the name of the hook
+    #[serde(default)]
+    pub hooks: BTreeMap<String, String>
+}
+
+impl SnapshotSet {
+    pub fn hook(&self, hook: Hook) -> Option<&str> {
+        self.hooks.get(hook.name()).map(String::as_str)
+    }
+
+    /// Runs a hook of the set, if it has one. Hooks of a set get the name of the set and the
+    /// snapshot in `HIPPO_SET` and `HIPPO_SNAPSHOT`, and the paths of its members (one per line)
+    /// in `HIPPO_FILES`. Variables given in `env` take precedence over these.
+    pub fn run_hook(&self, hook: Hook, snapshot_name: &str, env: &[(&str, String)]) -> Result<(), HookFailure> {
+        let command = match self.hook(hook) {
+            Some(command) => command,
+            None => return Ok(())
+        };
+
+        let members: Vec<String> = self.members.iter().map(|member| member.to_string_lossy().into_owned()).collect();
+        let mut set_env = vec![
+            ("HIPPO_SET", self.name.to_owned()),
+            ("HIPPO_SNAPSHOT", snapshot_name.to_owned()),
+            ("HIPPO_FILES", members.join("\n"))
+        ];
+        set_env.extend(env.iter().cloned());
+
+        run_hook(hook, command, &format!("set {}", self.name), &set_env)
+    }
+}
+
+/// A snapshot of a set, which refers to the snapshot taken of each of its members at the time
+#[derive(Serialize, Deserialize, Clone)]
+pub struct SetSnapshot {
+    pub created_time: DateTime<Utc>,
+    /// The name of the snapshot of every member, by its path
+    pub members: BTreeMap<PathBuf, String>
+}
+
+/// A snapshot of a set referring to the snapshot of one of its members
+pub struct SetReference {
+    pub set_name: String,
+    pub set_snapshot_name: String,
+    pub snapshot_name: String
+}
+
+/// Checks that a set can be given this name. Set names follow the same rules as tags, since they
+/// are typed on the command line just as often.
+pub fn validate_set_name(set_name: &str) -> Result<(), String> {
+    if set_name.is_empty() {
+        Err(String::from("Set names cannot be empty"))
+    } else if set_name.chars().any(|c| c.is_whitespace() || c.is_control() || c == ',' || c == '=' || c == '/') {
+        Err(format!("`{}` is not allowed, set names cannot contain whitespace, commas, `=` or `/`",
+                    set_name.escape_default()))
+    } else {
+        Ok(())
+    }
+}
+
+impl MapsToSimpleRecord for SnapshotSet {
+    fn from(simple_record: SimpleRecord) -> Option<Self> {
+        serde_json::from_str(simple_record.value.as_str()).ok()
+    }
+
+    fn to(&self) -> SimpleRecord {
+        SimpleRecord {
+            key: self.name.to_owned(),
+            value: serde_json::to_string(&self)
+                .expect("Failed to serialize snapshot set (trying to write to journal)")
+        }
+    }
+}
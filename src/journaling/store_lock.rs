@@ -0,0 +1,151 @@
+extern crate colored;
+extern crate fs2;
+
+use std::path::PathBuf;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::process;
+
+use self::colored::*;
+use self::fs2::FileExt;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum LockMode {
+    /// For commands that only read from the store; any number of them can run together
+    Shared,
+    /// For commands that modify the store
+    Exclusive
+}
+
+/// An advisory lock on a lock file inside the store, held until dropped. Exclusive holders
+/// record their pid in the lock file so that other processes can report who holds the lock.
+#[derive(Debug)]
+pub struct StoreLock {
+    file: File,
+    mode: LockMode
+}
+
+#[derive(Debug)]
+pub struct StoreLockError {
+    /// The pid of the process holding the lock, if it was held by another one that recorded it
+    #[allow(dead_code)]
+    pub holder_pid: Option<u32>
+}
+
+impl From<io::Error> for StoreLockError {
+    fn from(_: io::Error) -> Self { StoreLockError { holder_pid: None } }
+}
+
+impl StoreLock {
+    pub fn acquire(lock_file_path: PathBuf, mode: LockMode, wait: bool) -> Result<StoreLock, StoreLockError> {
+        info!("Acquiring {:?} lock on {:?}", mode, lock_file_path);
+
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&lock_file_path)?;
+
+        let acquired = match mode {
+            LockMode::Shared => FileExt::try_lock_shared(&file),
+            LockMode::Exclusive => FileExt::try_lock_exclusive(&file)
+        };
+
+        if acquired.is_err() {
+            let holder_pid = StoreLock::read_holder_pid(&mut file);
+            let holder = holder_pid
+                .map(|pid| format!("pid {}", pid))
+                .unwrap_or_else(|| String::from("another hippo process"));
+
+            if !wait {
+                println!("{} The store is locked by {} (lock file: {}). Retry once it has finished, \
+                          or use --wait to wait for it.", "ERROR".red(), holder, lock_file_path.to_string_lossy());
+                return Err(StoreLockError { holder_pid });
+            }
+
+            println!("{} The store is locked by {}, waiting for it to be released", "WAIT".yellow(), holder);
+
+            match mode {
+                LockMode::Shared => FileExt::lock_shared(&file)?,
+                LockMode::Exclusive => FileExt::lock_exclusive(&file)?
+            }
+        }
+
+        if mode == LockMode::Exclusive {
+            file.set_len(0)?;
+            file.seek(SeekFrom::Start(0))?;
+            file.write_all(process::id().to_string().as_bytes())?;
+            file.sync_all()?;
+        }
+
+        Ok(StoreLock { file, mode })
+    }
+
+    pub fn mode(&self) -> LockMode {
+        self.mode
+    }
+
+    fn read_holder_pid(file: &mut File) -> Option<u32> {
+        let mut contents = String::new();
+
+        file.seek(SeekFrom::Start(0)).ok()?;
+        file.read_to_string(&mut contents).ok()?;
+
+        contents.trim().parse::<u32>().ok()
+    }
+}
+
+impl Drop for StoreLock {
+    fn drop(&mut self) {
+        if self.mode == LockMode::Exclusive {
+            let _ = self.file.set_len(0);
+        }
+
+        let _ = FileExt::unlock(&self.file);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process;
+
+    use utils::test_dir::TestDir;
+
+    use super::{LockMode, StoreLock};
+
+    #[test]
+    fn exclusive_lock_keeps_out_everyone_else() {
+        let test_dir = TestDir::new("store-lock");
+        let lock_file_path = test_dir.join("__lock");
+
+        let exclusive = StoreLock::acquire(lock_file_path.to_owned(), LockMode::Exclusive, false).unwrap();
+
+        // Locks are held per open file, so a second acquisition conflicts even within this process
+        for &mode in &[LockMode::Exclusive, LockMode::Shared] {
+            let error = StoreLock::acquire(lock_file_path.to_owned(), mode, false).unwrap_err();
+            assert_eq!(error.holder_pid, Some(process::id()));
+        }
+
+        drop(exclusive);
+        assert!(StoreLock::acquire(lock_file_path, LockMode::Exclusive, false).is_ok());
+    }
+
+    #[test]
+    fn shared_locks_coexist() {
+        let test_dir = TestDir::new("store-lock");
+        let lock_file_path = test_dir.join("__lock");
+
+        let first = StoreLock::acquire(lock_file_path.to_owned(), LockMode::Shared, false).unwrap();
+        let second = StoreLock::acquire(lock_file_path.to_owned(), LockMode::Shared, false).unwrap();
+
+        assert_eq!(first.mode(), LockMode::Shared);
+        assert_eq!(second.mode(), LockMode::Shared);
+        assert!(StoreLock::acquire(lock_file_path.to_owned(), LockMode::Exclusive, false).is_err());
+
+        drop(first);
+        drop(second);
+        assert!(StoreLock::acquire(lock_file_path, LockMode::Exclusive, false).is_ok());
+    }
+}
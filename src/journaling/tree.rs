@@ -0,0 +1,369 @@
+extern crate glob;
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::{copy, create_dir_all, read_dir, remove_dir, symlink_metadata, File};
+use std::io;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+use self::glob::Pattern;
+
+use super::object_store::ObjectStore;
+use super::super::utils::compression::Codec;
+use super::super::utils::file_metadata::FileMetadata;
+use super::super::utils::staged_file::StagedChanges;
+
+/// Decides which files under a managed directory are part of its snapshots. Paths are matched
+/// relative to the managed directory, with `/` as the separator. Excluded directories are not
+/// descended into, and if any include patterns are given, only files matching one of them are
+/// part of the snapshot.
+pub struct TreeFilter {
+    include: Vec<Pattern>,
+    exclude: Vec<Pattern>
+}
+
+impl TreeFilter {
+    pub fn new(include: &[String], exclude: &[String]) -> Result<TreeFilter, String> {
+        let compile = |globs: &[String]| globs.iter()
+            .map(|glob| Pattern::new(glob).map_err(|e| format!("`{}` is not a valid pattern: {}", glob, e)))
+            .collect::<Result<Vec<_>, _>>();
+
+        Ok(TreeFilter {
+            include: compile(include)?,
+            exclude: compile(exclude)?
+        })
+    }
+
+    fn excludes(&self, relative_path: &str) -> bool {
+        self.exclude.iter().any(|pattern| pattern.matches(relative_path))
+    }
+
+    fn includes_file(&self, relative_path: &str) -> bool {
+        !self.excludes(relative_path) &&
+            (self.include.is_empty() || self.include.iter().any(|pattern| pattern.matches(relative_path)))
+    }
+}
+
+/// The files and directories found under a managed directory
+#[derive(Default)]
+pub struct ScannedTree {
+    pub dirs: BTreeSet<String>,
+    pub files: BTreeMap<String, PathBuf>,
+    /// Entries that are neither regular files nor directories (or whose names are not valid
+    /// UTF-8), which are left out of snapshots
+    pub skipped: Vec<PathBuf>
+}
+
+impl ScannedTree {
+    pub fn scan(root: &Path, filter: &TreeFilter) -> io::Result<ScannedTree> {
+        let mut scanned_tree = ScannedTree::default();
+        scanned_tree.scan_dir(root, "", filter)?;
+
+        Ok(scanned_tree)
+    }
+
+    fn scan_dir(&mut self, dir: &Path, relative_dir: &str, filter: &TreeFilter) -> io::Result<()> {
+        let mut dir_entries = read_dir(dir)?.collect::<io::Result<Vec<_>>>()?;
+        dir_entries.sort_by_key(|dir_entry| dir_entry.file_name());
+
+        for dir_entry in dir_entries {
+            let path = dir_entry.path();
+
+            let file_name = match dir_entry.file_name().into_string() {
+                Ok(file_name) => file_name,
+                Err(_) => {
+                    self.skipped.push(path);
+                    continue;
+                }
+            };
+
+            let relative_path = if relative_dir.is_empty() { file_name } else { format!("{}/{}", relative_dir, file_name) };
+            let file_type = symlink_metadata(&path)?.file_type();
+
+            if file_type.is_dir() {
+                if !filter.excludes(&relative_path) {
+                    self.dirs.insert(relative_path.to_owned());
+                    self.scan_dir(&path, &relative_path, filter)?;
+                }
+            } else if file_type.is_file() {
+                if filter.includes_file(&relative_path) {
+                    self.files.insert(relative_path, path);
+                }
+            } else if !filter.excludes(&relative_path) {
+                self.skipped.push(path);
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn read_files(&self) -> io::Result<BTreeMap<String, Vec<u8>>> {
+        self.files.iter()
+            .map(|(relative_path, path)| read_file(path).map(|content| (relative_path.to_owned(), content)))
+            .collect()
+    }
+
+    /// Metadata of every directory and file in the tree, by relative path
+    pub fn read_metadata(&self, root: &Path) -> io::Result<BTreeMap<String, FileMetadata>> {
+        self.dirs.iter()
+            .map(|relative_dir| (relative_dir, root.join(relative_dir)))
+            .chain(self.files.iter().map(|(relative_path, path)| (relative_path, path.to_owned())))
+            .map(|(relative_path, path)| FileMetadata::read(&path).map(|file_metadata| (relative_path.to_owned(), file_metadata)))
+            .collect()
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct TreeFile {
+    pub object: String,
+    pub codec: Codec,
+    pub size: u64,
+    #[serde(default)]
+    pub metadata: Option<FileMetadata>
+}
+
+/// The payload of a directory snapshot: every file in it (by relative path) along with the
+/// object holding its contents, every directory (so that empty ones are restored too), and the
+/// filter the snapshot was taken with.
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct TreeManifest {
+    pub include: Vec<String>,
+    pub exclude: Vec<String>,
+    pub dirs: BTreeSet<String>,
+    pub files: BTreeMap<String, TreeFile>,
+    #[serde(default)]
+    pub dir_metadata: BTreeMap<String, FileMetadata>
+}
+
+/// What taking a snapshot of a tree amounted to
+pub struct CapturedTree {
+    pub manifest: TreeManifest,
+    pub size: u64,
+    /// Bytes written to the object store, for files whose contents were not stored already
+    pub stored_size: u64,
+    pub skipped: Vec<PathBuf>
+}
+
+impl TreeManifest {
+    /// Stores every file under the given directory in the object store, and returns the manifest
+    /// describing the tree.
+    pub fn capture(
+        root: &Path, include: &[String], exclude: &[String], object_store: &ObjectStore, codec: Codec
+    ) -> io::Result<CapturedTree> {
+        let filter = TreeFilter::new(include, exclude)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        let scanned_tree = ScannedTree::scan(root, &filter)?;
+
+        let mut manifest = TreeManifest {
+            include: include.to_vec(),
+            exclude: exclude.to_vec(),
+            dir_metadata: scanned_tree.dirs.iter()
+                .map(|relative_dir| FileMetadata::read(&root.join(relative_dir)).map(|m| (relative_dir.to_owned(), m)))
+                .collect::<io::Result<_>>()?,
+            dirs: scanned_tree.dirs,
+            files: BTreeMap::new()
+        };
+        let (mut size, mut stored_size) = (0, 0);
+
+        for (relative_path, path) in scanned_tree.files {
+            let content = read_file(&path)?;
+            let stored_object = object_store.put(&content, codec)?;
+
+            size += content.len() as u64;
+            if stored_object.created { stored_size += stored_object.stored_size; }
+
+            manifest.files.insert(relative_path, TreeFile {
+                object: stored_object.hash,
+                codec,
+                size: content.len() as u64,
+                metadata: Some(FileMetadata::read(&path)?)
+            });
+        }
+
+        Ok(CapturedTree { manifest, size, stored_size, skipped: scanned_tree.skipped })
+    }
+
+    pub fn filter(&self) -> io::Result<TreeFilter> {
+        TreeFilter::new(&self.include, &self.exclude)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    pub fn size(&self) -> u64 {
+        self.files.values().map(|tree_file| tree_file.size).sum()
+    }
+
+    /// Whether the tree on disk is still exactly what this manifest describes, going by the
+    /// contents, permissions and ownership of everything in it
+    pub fn matches(&self, root: &Path) -> io::Result<bool> {
+        let scanned_tree = ScannedTree::scan(root, &self.filter()?)?;
+
+        if scanned_tree.dirs != self.dirs || scanned_tree.files.len() != self.files.len() {
+            return Ok(false);
+        }
+
+        for (relative_path, path) in &scanned_tree.files {
+            match self.files.get(relative_path) {
+                Some(tree_file) if ObjectStore::hash(&read_file(path)?) == tree_file.object => {}
+                _ => return Ok(false)
+            }
+        }
+
+        let recorded_metadata = self.read_metadata();
+
+        for (relative_path, live_metadata) in scanned_tree.read_metadata(root)? {
+            match recorded_metadata.get(&relative_path) {
+                Some(file_metadata) if !file_metadata.same_attributes(&live_metadata) => return Ok(false),
+                _ => {}
+            }
+        }
+
+        Ok(true)
+    }
+
+    /// Metadata of every directory and file in the tree, by relative path. Trees snapped by older
+    /// versions of hippo have none.
+    pub fn read_metadata(&self) -> BTreeMap<String, FileMetadata> {
+        let mut recorded_metadata = self.dir_metadata.clone();
+
+        recorded_metadata.extend(self.files.iter()
+            .filter_map(|(relative_path, tree_file)| tree_file.metadata.map(|m| (relative_path.to_owned(), m))));
+
+        recorded_metadata
+    }
+
+    pub fn read_files(&self, object_store: &ObjectStore) -> io::Result<BTreeMap<String, Vec<u8>>> {
+        self.files.iter()
+            .map(|(relative_path, tree_file)| {
+                object_store.get(&tree_file.object, tree_file.codec).map(|content| (relative_path.to_owned(), content))
+            })
+            .collect()
+    }
+
+    /// Stages the files of the tree under the given directory, along with the removal of files
+    /// that are not part of it. Anything the snapshot's filter leaves out is not touched.
+    /// Directories that are missing are created right away, since files cannot be staged inside
+    /// them otherwise. Returns the paths whose recorded ownership could not
+    /// be applied.
+    pub fn stage_restore(&self, root: &Path, object_store: &ObjectStore, staged_changes: &mut StagedChanges) -> io::Result<Vec<PathBuf>> {
+        // Everything is read upfront, so that a missing object fails the restore before anything
+        // on disk has been changed
+        let contents = self.read_files(object_store)?;
+
+        staged_changes.create_dir_all(root)?;
+
+        for relative_dir in &self.dirs {
+            staged_changes.create_dir_all(&root.join(relative_dir))?;
+        }
+
+        // Scanned before anything is staged, so that the staging files are not mistaken for files
+        // that are not part of the snapshot
+        for (relative_path, path) in ScannedTree::scan(root, &self.filter()?)?.files {
+            if !self.files.contains_key(&relative_path) {
+                info!("Removing {:?}, which is not part of the snapshot", path);
+                staged_changes.stage_removal(&path)?;
+            }
+        }
+
+        let mut unowned_paths = Vec::new();
+
+        for (relative_path, content) in &contents {
+            let path = root.join(relative_path);
+            let recorded_metadata = self.files.get(relative_path).and_then(|tree_file| tree_file.metadata.as_ref());
+
+            if !staged_changes.stage_file(&path, content, recorded_metadata)? {
+                unowned_paths.push(path);
+            }
+        }
+
+        Ok(unowned_paths)
+    }
+
+    /// Writes the files of the tree under the given directory, leaving out their metadata
+    pub fn write_copy(&self, destination: &Path, object_store: &ObjectStore) -> io::Result<()> {
+        let contents = self.read_files(object_store)?;
+
+        create_dir_all(destination)?;
+
+        for relative_dir in &self.dirs {
+            create_dir_all(destination.join(relative_dir))?;
+        }
+
+        for (relative_path, content) in &contents {
+            File::create(destination.join(relative_path))?.write_all(content)?;
+        }
+
+        Ok(())
+    }
+
+    /// Finishes restoring the tree once the staged changes have been applied: directories that are
+    /// not part of it are removed, and the recorded metadata of the rest is applied. Returns the
+    /// directories whose ownership could not be restored.
+    pub fn settle(&self, root: &Path) -> io::Result<Vec<PathBuf>> {
+        // Deepest first, so that directories are empty by the time they are removed. Directories
+        // still holding files the filter leaves out are kept.
+        for relative_dir in ScannedTree::scan(root, &self.filter()?)?.dirs.iter().rev() {
+            if !self.dirs.contains(relative_dir) {
+                let _ = remove_dir(root.join(relative_dir));
+            }
+        }
+
+        let mut unowned_paths = Vec::new();
+
+        // Deepest first again, since changing anything in a directory would change the
+        // modification time of its parent
+        for (relative_dir, recorded_metadata) in self.dir_metadata.iter().rev() {
+            let path = root.join(relative_dir);
+
+            if !recorded_metadata.apply(&path)? {
+                unowned_paths.push(path);
+            }
+        }
+
+        Ok(unowned_paths)
+    }
+}
+
+/// Stages the removal of the files of a tree (as seen through the filter)
+pub fn stage_tree_removal(root: &Path, filter: &TreeFilter, staged_changes: &mut StagedChanges) -> io::Result<()> {
+    for path in ScannedTree::scan(root, filter)?.files.values() {
+        staged_changes.stage_removal(path)?;
+    }
+
+    Ok(())
+}
+
+/// Removes the directories of a tree (as seen through the filter) that are empty, including the
+/// root itself
+pub fn remove_empty_dirs(root: &Path, filter: &TreeFilter) -> io::Result<()> {
+    for relative_dir in ScannedTree::scan(root, filter)?.dirs.iter().rev() {
+        let _ = remove_dir(root.join(relative_dir));
+    }
+
+    let _ = remove_dir(root);
+
+    Ok(())
+}
+
+/// Copies the files of a tree (as seen through the filter) into another directory
+pub fn copy_tree(root: &Path, destination: &Path, filter: &TreeFilter) -> io::Result<()> {
+    let scanned_tree = ScannedTree::scan(root, filter)?;
+
+    create_dir_all(destination)?;
+
+    for relative_dir in &scanned_tree.dirs {
+        create_dir_all(destination.join(relative_dir))?;
+    }
+
+    for (relative_path, path) in &scanned_tree.files {
+        copy(path, destination.join(relative_path))?;
+    }
+
+    Ok(())
+}
+
+fn read_file(path: &Path) -> io::Result<Vec<u8>> {
+    let mut content = Vec::new();
+    File::open(path)?.read_to_end(&mut content)?;
+
+    Ok(content)
+}
@@ -14,7 +14,7 @@ use prettytable::format::{FormatBuilder, TableFormat};
 use self::colored::*;
 use self::chrono::prelude::*;
 
-static DEFAULT_SNAPSHOT_TIME_FORMAT: &'static str = "%a %b %e %T %Y";
+static DEFAULT_SNAPSHOT_TIME_FORMAT: &str = "%a %b %e %T %Y";
 
 //lazy_static! {
 // No clue why lazy_static! is not working, will fix it.
@@ -30,13 +30,34 @@ fn hippo_list_display_table_format() -> TableFormat {
 
 pub struct ListError;
 
+impl From<ManagedFileJournalError> for ListError {
+    fn from(_: ManagedFileJournalError) -> Self { ListError }
+}
+
 impl From<Error> for ListError {
     fn from(_: Error) -> Self {
         ListError {}
     }
 }
 
-pub fn list(journal: &mut Journal, file_paths: Vec<PathBuf>) -> Result<(), ListError> {
+/// Lists snapshots for the given files, or for every file managed by the journal if none are
+/// given. In summary mode, only a single line is printed for each file. If tags are given, only
+/// snapshots that have all of them are listed, and files without any such snapshots are skipped
+/// unless they were asked for.
+pub fn list(journal: &mut Journal, summary: bool, tags: Vec<String>, file_paths: Vec<PathBuf>) -> Result<(), ListError> {
+    let explicit_file_paths = !file_paths.is_empty();
+    let file_paths = if !explicit_file_paths {
+        let mut managed_paths: Vec<PathBuf> = journal.root_journal_config.records
+            .keys()
+            .map(PathBuf::from)
+            .collect();
+
+        managed_paths.sort();
+        managed_paths
+    } else {
+        file_paths
+    };
+
     let managed_file_journal = ManagedFileJournal::for_journal(journal);
     let mut start = true;
 
@@ -46,19 +67,50 @@ pub fn list(journal: &mut Journal, file_paths: Vec<PathBuf>) -> Result<(), ListE
 
         info!("Probing for snapshots for {:?}", file_path);
 
-        let managed_file = managed_file_journal.get_managed_file(&file_path);
+        let managed_file = managed_file_journal.get_managed_file(&file_path)?;
+
+        if !tags.is_empty() {
+            match managed_file {
+                Some(ref managed_file) if explicit_file_paths || !managed_file.find_snapshots_by_tags(&tags).is_empty() => {}
+                Some(_) => continue,
+                None => {}
+            }
+        }
 
-        if !start { println!(); } else { start = false; }
+        if !start && !summary { println!(); } else { start = false; }
 
         match managed_file {
+            Some(managed_file) if !tags.is_empty() => {
+                let tagged_entries = managed_file.find_snapshots_by_tags(&tags);
+                let current_snapshot_name = managed_file.get_snapshots().current_snapshot_name;
+
+                println!("{}{} has {} snapshots tagged {}, currently at {}",
+                         if summary { String::new() } else { format!("{} ", "OK".green()) }, file_path_string,
+                         tagged_entries.len(), tags.join(", ").cyan(), current_snapshot_name.cyan());
+
+                if !summary {
+                    println!();
+                    print_snapshot_table(tagged_entries.into_iter().map(Snapshot::Tangible).collect(),
+                                         &current_snapshot_name)?;
+                }
+            }
+
+            Some(managed_file) if summary => {
+                let snapshots_listing = managed_file.get_snapshots();
+
+                println!("{} has {} snapshots, currently at {}", file_path_string,
+                         snapshots_listing.tangible_count, snapshots_listing.current_snapshot_name.cyan());
+            }
+
             Some(managed_file) => {
                 let snapshots_listing = managed_file.get_snapshots();
 
-                println!("{} {}, {} snapshots (+{} synthetic)", "OK".green(), file_path_string,
-                         snapshots_listing.tangible_count, snapshots_listing.synthetic_count);
+                println!("{} {}, {} snapshots (+{} synthetic), currently at {}", "OK".green(), file_path_string,
+                         snapshots_listing.tangible_count, snapshots_listing.synthetic_count,
+                         snapshots_listing.current_snapshot_name.cyan());
                 println!();
 
-                print_snapshot_table(snapshots_listing.snapshots);
+                print_snapshot_table(snapshots_listing.snapshots, &snapshots_listing.current_snapshot_name)?;
             }
 
             None => {
@@ -70,33 +122,77 @@ pub fn list(journal: &mut Journal, file_paths: Vec<PathBuf>) -> Result<(), ListE
     Ok(())
 }
 
-fn print_snapshot_table(snapshots: Vec<Snapshot>) {
+fn print_snapshot_table(snapshots: Vec<Snapshot>, current_snapshot_name: &str) -> Result<(), ListError> {
     let mut table = Table::new();
-    table.add_row(row![b->"Name", b->"Comment", b->"Author", b->"Creation Time"]);
+    table.add_row(row![b->"", b->"Name", b->"Comment", b->"Author", b->"Creation Time", b->"Labels"]);
+
+    for (index, snapshot) in snapshots.into_iter().enumerate() {
+        let marker = if snapshot.name() == current_snapshot_name { "*" } else { "" };
+        let position = format!("{}{}", index + 1, marker);
 
-    for snapshot in snapshots {
         match snapshot {
-            Snapshot::Synthetic(some) => process_table_for_synthetic(&mut table, &some),
-            Snapshot::Tangible(entry) => process_table_for_snapshot_entry(&mut table, &entry)
+            Snapshot::Synthetic(some) => process_table_for_synthetic(&mut table, position, &some),
+            Snapshot::Tangible(entry) => process_table_for_snapshot_entry(&mut table, position, entry)
         }
     }
 
     table.set_format(hippo_list_display_table_format());
-    table.print_tty(true);
+    table.print_tty(true)?;
+
+    Ok(())
 }
 
-fn process_table_for_synthetic(table: &mut Table, synthetic_snapshot: &SyntheticSnapshot) {
-    match synthetic_snapshot {
-        &SyntheticSnapshot::Null => {
-            table.add_row(row!["(null)", "(synthetic snapshot; noent inode)", "", "Before all time"]);
+fn process_table_for_synthetic(table: &mut Table, position: String, synthetic_snapshot: &SyntheticSnapshot) {
+    match *synthetic_snapshot {
+        SyntheticSnapshot::Null => {
+            table.add_row(row![position, "(null)", "(synthetic snapshot; file absent)", "", "Before all time", ""]);
+        }
+
+        SyntheticSnapshot::Live(modified_time) => {
+            let local_modified_time = modified_time.with_timezone(&Local)
+                .format(DEFAULT_SNAPSHOT_TIME_FORMAT).to_string();
+
+            table.add_row(row![position, "(live)", "(synthetic snapshot; modified since last snap)", "",
+                local_modified_time, ""]);
         }
     }
 }
 
-fn process_table_for_snapshot_entry(table: &mut Table, snapshot_entry: &SnapshotEntry) {
+fn process_table_for_snapshot_entry(table: &mut Table, position: String, snapshot_entry: &SnapshotEntry) {
     let local_creation_date = snapshot_entry.created_time.with_timezone(&Local)
         .format(DEFAULT_SNAPSHOT_TIME_FORMAT).to_string();
 
-    table.add_row(row![snapshot_entry.snapshot_name, snapshot_entry.comment,
-        snapshot_entry.author, local_creation_date]);
+    table.add_row(row![position, snapshot_entry.snapshot_name, snapshot_entry.comment,
+        snapshot_entry.author, local_creation_date, snapshot_entry.labels().join(", ")]);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::write;
+
+    use snap::snap;
+    use snap::tests::snap_options;
+    use utils::test_dir::TestDir;
+
+    use super::list;
+
+    #[test]
+    fn every_managed_file_is_listed_in_full_or_in_summary() {
+        let test_dir = TestDir::new("list");
+        let file_paths = vec![test_dir.join("first.conf"), test_dir.join("second.conf")];
+
+        for file_path in &file_paths {
+            write(file_path, "contents\n").unwrap();
+        }
+
+        let mut journal = test_dir.open_store();
+        assert!(snap(&mut journal, snap_options("v1"), file_paths.to_owned()).is_ok());
+        write(&file_paths[0], "edited\n").unwrap();
+
+        for &summary in &[false, true] {
+            assert!(list(&mut journal, summary, Vec::new(), Vec::new()).is_ok());
+            assert!(list(&mut journal, summary, vec![String::from("missing-tag")], Vec::new()).is_ok());
+            assert!(list(&mut journal, summary, Vec::new(), vec![test_dir.join("unmanaged.conf")]).is_ok());
+        }
+    }
 }
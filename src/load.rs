@@ -0,0 +1,519 @@
+extern crate colored;
+
+use std::path::PathBuf;
+
+use journaling::hooks::Hook;
+use journaling::journal::Journal;
+use journaling::managed_file_journal::{ManagedFileJournal, ManagedFileJournalError};
+use journaling::object_store::PayloadStatus;
+use journaling::managed_file::{load_snapshots, ManagedFile, ManagedFileError, SnapshotKind, SnapshotLabels, NULL_SNAPSHOT_NAME};
+use utils::compression::Codec;
+
+use self::colored::*;
+
+pub struct LoadError;
+
+impl From<ManagedFileJournalError> for LoadError {
+    fn from(_: ManagedFileJournalError) -> Self { LoadError }
+}
+
+impl From<ManagedFileError> for LoadError {
+    fn from(_: ManagedFileError) -> Self { LoadError }
+}
+
+/// What to do when the file being loaded onto has modifications that were never snapped. Loads
+/// that remove the file snap it first unless told to discard it.
+#[derive(Clone, Copy)]
+pub enum LiveFilePolicy {
+    Refuse,
+    AutoSnap,
+    Discard
+}
+
+/// How the snapshot to load is picked
+pub enum SnapshotSelector {
+    Name(String),
+    /// The snapshot with all of the given tags. If several snapshots have them, `latest` picks
+    /// the most recent one, otherwise the selection is ambiguous.
+    Tags { tags: Vec<String>, latest: bool }
+}
+
+pub fn load(
+    journal: &mut Journal, selector: SnapshotSelector, live_file_policy: LiveFilePolicy, file_path: PathBuf
+) -> Result<(), LoadError> {
+    let managed_file_journal = ManagedFileJournal::for_journal(journal);
+    let file_path_string = file_path.to_owned().into_os_string().into_string()
+        .expect("Could not read the file path, it might contain invalid characters");
+
+    match managed_file_journal.get_managed_file(&file_path)? {
+        Some(mut managed_file) => {
+            let snapshot_name = select_snapshot(&managed_file, selector, &file_path_string)?;
+
+            info!("Loading snapshot {} for {:?}", snapshot_name, file_path);
+
+            let live_file_policy = effective_live_file_policy(&managed_file, &snapshot_name, live_file_policy);
+
+            if !check_live_file(&managed_file, live_file_policy, &file_path_string)? {
+                return Err(LoadError);
+            }
+
+            managed_file.load_snapshot(&snapshot_name, |managed_file| {
+                handle_live_file(managed_file, live_file_policy, &file_path_string)
+            })?;
+            Ok(())
+        }
+
+        None => {
+            println!("{} {}, file not managed by {}", "ERROR".red(), file_path_string, "hippo".magenta());
+            Err(LoadError)
+        }
+    }
+}
+
+/// Loads a snapshot of a set, which loads the snapshot it refers to for every member. Every
+/// member is checked before any of them is touched, so that a load that cannot go through for
+/// one of them does not change the others either. The members are then loaded as a single
+/// change, so that if replacing any of them fails, the ones already replaced are restored.
+/// Members with modifications that were never snapped are only snapped (or stashed) once the
+/// hooks of the set and of every member have let the load go ahead.
+pub fn load_set(
+    journal: &mut Journal, set_name: &str, snapshot_name: &str, live_file_policy: LiveFilePolicy
+) -> Result<(), LoadError> {
+    let snapshot_set = match journal.get_set(set_name) {
+        Some(snapshot_set) => snapshot_set.clone(),
+        None => {
+            println!("{} No set named {}", "ERROR".red(), set_name.cyan());
+            return Err(LoadError);
+        }
+    };
+
+    let set_snapshot = match snapshot_set.snapshots.get(snapshot_name) {
+        Some(set_snapshot) => set_snapshot.clone(),
+        None => {
+            println!("{} Set {} has no snapshot named {}", "ERROR".red(), set_name.cyan(), snapshot_name.cyan());
+            return Err(LoadError);
+        }
+    };
+
+    let managed_file_journal = ManagedFileJournal::for_journal(journal);
+    let mut members = Vec::new();
+    let mut problems = 0;
+
+    for (member, member_snapshot_name) in &set_snapshot.members {
+        let member_string = member.to_string_lossy().into_owned();
+
+        let managed_file = match managed_file_journal.get_managed_file(member)? {
+            Some(managed_file) => managed_file,
+            None => {
+                println!("{} {}, file not managed by {}", "ERROR".red(), member_string, "hippo".magenta());
+                problems += 1;
+                continue;
+            }
+        };
+
+        let usable = match managed_file.get_snapshot(member_snapshot_name) {
+            Some(snapshot_entry) if managed_file.check_payload(snapshot_entry) != PayloadStatus::Intact => {
+                println!("{} {}, the contents of snapshot {} are missing or corrupt, run `hippo fsck`", "ERROR".red(),
+                         member_string, member_snapshot_name.cyan());
+                false
+            }
+            Some(_) => true,
+            None if member_snapshot_name == NULL_SNAPSHOT_NAME => true,
+            None => {
+                println!("{} {}, no snapshot named {}", "ERROR".red(), member_string, member_snapshot_name.cyan());
+                false
+            }
+        };
+
+        let live_file_policy = effective_live_file_policy(&managed_file, member_snapshot_name, live_file_policy);
+
+        if !usable || !check_live_file(&managed_file, live_file_policy, &member_string)? {
+            problems += 1;
+            continue;
+        }
+
+        members.push((managed_file, member_string, member_snapshot_name, live_file_policy));
+    }
+
+    if problems > 0 {
+        println!("{} Not loading snapshot {} of set {}, {} of its {} members cannot be loaded", "ERROR".red().bold(),
+                 snapshot_name.cyan(), set_name.cyan(), problems, set_snapshot.members.len());
+        return Err(LoadError);
+    }
+
+    let member_count = members.len();
+    let live_file_policies: Vec<(String, LiveFilePolicy)> = members.iter()
+        .map(|&(_, ref member_string, _, live_file_policy)| (member_string.to_owned(), live_file_policy))
+        .collect();
+    let loads = members.iter_mut()
+        .map(|&mut (ref mut managed_file, _, member_snapshot_name, _)| (managed_file, member_snapshot_name.as_str()))
+        .collect();
+
+    let loaded = load_snapshots(loads, Some((&snapshot_set, snapshot_name)), |index, managed_file| {
+        let (ref member_string, live_file_policy) = live_file_policies[index];
+        handle_live_file(managed_file, live_file_policy, member_string)
+    });
+
+    if loaded.is_err() {
+        println!("{} Could not load snapshot {} of set {}", "ERROR".red().bold(), snapshot_name.cyan(), set_name.cyan());
+        return Err(LoadError);
+    }
+
+    println!("{} Loaded snapshot {} of set {} ({} members)", "OK".green().bold(), snapshot_name.cyan(),
+             set_name.cyan(), member_count);
+
+    snapshot_set.run_hook(Hook::PostLoad, snapshot_name, &[]).map_err(|_| LoadError)
+}
+
+/// Loads that remove the file snap it first if it has modifications, unless told to discard them
+fn effective_live_file_policy(managed_file: &ManagedFile, snapshot_name: &str, live_file_policy: LiveFilePolicy) -> LiveFilePolicy {
+    let removes_file = snapshot_name == NULL_SNAPSHOT_NAME || managed_file.get_snapshot(snapshot_name)
+        .is_some_and(|snapshot_entry| snapshot_entry.kind == SnapshotKind::Absent);
+
+    match live_file_policy {
+        LiveFilePolicy::Refuse if removes_file => LiveFilePolicy::AutoSnap,
+        live_file_policy => live_file_policy
+    }
+}
+
+/// Whether the file can be loaded onto under the given policy, without changing anything yet
+fn check_live_file(managed_file: &ManagedFile, live_file_policy: LiveFilePolicy, file_path_string: &str) -> Result<bool, LoadError> {
+    if let LiveFilePolicy::Refuse = live_file_policy {
+        if managed_file.check_live()? {
+            println!("{} The current snapshot for {} is {}. Create a snapshot for the current \
+                      file before continuing (or use --auto-snap or --discard-live).",
+                     "ERROR".red(), file_path_string, "(live)".cyan());
+            return Ok(false);
+        }
+    }
+
+    Ok(true)
+}
+
+fn handle_live_file(
+    managed_file: &mut ManagedFile, live_file_policy: LiveFilePolicy, file_path_string: &str
+) -> Result<(), ManagedFileError> {
+    if !managed_file.check_live()? {
+        return Ok(());
+    }
+
+    match live_file_policy {
+        LiveFilePolicy::Refuse => {}
+
+        LiveFilePolicy::AutoSnap => {
+            managed_file.snap_current_state(None, None, None, Codec::None, &SnapshotLabels::default(), false)?;
+        }
+
+        LiveFilePolicy::Discard => {
+            let stashed_file = managed_file.stash_live()?;
+
+            println!("{} The current snapshot for {} is {}. Discarding changes. The last edited \
+                      version is stored in {}", "WARN".yellow(), file_path_string, "(live)".cyan(),
+                     stashed_file.to_string_lossy());
+        }
+    }
+
+    Ok(())
+}
+
+fn select_snapshot(managed_file: &ManagedFile, selector: SnapshotSelector, file_path_string: &str) -> Result<String, LoadError> {
+    match selector {
+        SnapshotSelector::Name(snapshot_name) => {
+            if snapshot_name != NULL_SNAPSHOT_NAME && managed_file.get_snapshot(&snapshot_name).is_none() {
+                println!("{} {}, no snapshot named {}", "ERROR".red(), file_path_string, snapshot_name.cyan());
+                return Err(LoadError);
+            }
+
+            Ok(snapshot_name)
+        }
+
+        SnapshotSelector::Tags { tags, latest } => {
+            let candidates = managed_file.find_snapshots_by_tags(&tags);
+
+            match candidates.last() {
+                None => {
+                    println!("{} {}, no snapshot is tagged {}", "ERROR".red(), file_path_string, tags.join(", ").cyan());
+                    Err(LoadError)
+                }
+
+                Some(snapshot_entry) if latest || candidates.len() == 1 => Ok(snapshot_entry.snapshot_name.to_owned()),
+
+                Some(_) => {
+                    let candidate_names: Vec<&str> = candidates.iter()
+                        .map(|snapshot_entry| snapshot_entry.snapshot_name.as_str())
+                        .collect();
+
+                    println!("{} {}, {} snapshots are tagged {} ({}). Name one of them, or use --latest to load \
+                              the most recent", "ERROR".red(), file_path_string, candidates.len(),
+                             tags.join(", ").cyan(), candidate_names.join(", "));
+                    Err(LoadError)
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate chrono;
+
+    use std::collections::BTreeMap;
+    use std::fs::{create_dir_all, read_to_string, set_permissions, write, File, Permissions};
+    use std::os::unix::fs::PermissionsExt;
+    use std::path::PathBuf;
+    use std::time::{Duration, SystemTime};
+
+    use hook::{hook, HookTarget};
+    use journaling::hooks::Hook;
+    use journaling::journal::Journal;
+    use journaling::managed_file_journal::ManagedFileJournal;
+    use journaling::snapshot_set::{SetSnapshot, SnapshotSet};
+    use snap::{snap, SnapOptions};
+    use snap::tests::snap_options;
+    use utils::file_metadata::FileMetadata;
+    use utils::test_dir::TestDir;
+
+    use super::{load, load_set, LiveFilePolicy, SnapshotSelector};
+    use self::chrono::prelude::*;
+
+    fn load_named(journal: &mut Journal, snapshot_name: &str, live_file_policy: LiveFilePolicy, file_path: &PathBuf) -> bool {
+        load(journal, SnapshotSelector::Name(snapshot_name.to_owned()), live_file_policy, file_path.to_owned()).is_ok()
+    }
+
+    /// Names of the snapshots of the file, sorted, and the snapshot it is currently at
+    fn snapshot_names(journal: &mut Journal, file_path: &PathBuf) -> (Vec<String>, Option<String>) {
+        let managed_file_journal = ManagedFileJournal::for_journal(journal);
+        let managed_file = managed_file_journal.get_managed_file(file_path).ok().unwrap().unwrap();
+        let mut snapshot_names: Vec<String> = managed_file.snapshot_entries().iter()
+            .map(|snapshot_entry| snapshot_entry.snapshot_name.to_owned())
+            .collect();
+
+        snapshot_names.sort();
+        (snapshot_names, managed_file.get_current_snapshot_name().map(String::from))
+    }
+
+    #[test]
+    fn load_puts_back_the_contents_of_a_snapshot() {
+        let test_dir = TestDir::new("load");
+        let file_path = test_dir.join("app.conf");
+
+        write(&file_path, "first\n").unwrap();
+        let mut journal = test_dir.open_store();
+        assert!(snap(&mut journal, snap_options("v1"), vec![file_path.to_owned()]).is_ok());
+
+        write(&file_path, "second\n").unwrap();
+        assert!(snap(&mut journal, snap_options("v2"), vec![file_path.to_owned()]).is_ok());
+
+        assert!(load_named(&mut journal, "v1", LiveFilePolicy::Refuse, &file_path));
+        assert_eq!(read_to_string(&file_path).unwrap(), "first\n");
+
+        assert!(load_named(&mut journal, "v2", LiveFilePolicy::Refuse, &file_path));
+        assert_eq!(read_to_string(&file_path).unwrap(), "second\n");
+
+        assert!(!load_named(&mut journal, "v3", LiveFilePolicy::Refuse, &file_path));
+        assert!(!load_named(&mut journal, "v1", LiveFilePolicy::Refuse, &test_dir.join("unmanaged.conf")));
+        assert_eq!(read_to_string(&file_path).unwrap(), "second\n");
+        assert_eq!(snapshot_names(&mut journal, &file_path).0, vec!["v1", "v2"]);
+    }
+
+    #[test]
+    fn current_snapshot_follows_snaps_and_loads() {
+        let test_dir = TestDir::new("load");
+        let file_path = test_dir.join("app.conf");
+
+        write(&file_path, "first\n").unwrap();
+        let mut journal = test_dir.open_store();
+        assert!(snap(&mut journal, snap_options("v1"), vec![file_path.to_owned()]).is_ok());
+        assert_eq!(snapshot_names(&mut journal, &file_path).1.as_deref(), Some("v1"));
+
+        write(&file_path, "second\n").unwrap();
+        assert!(snap(&mut journal, snap_options("v2"), vec![file_path.to_owned()]).is_ok());
+        assert_eq!(snapshot_names(&mut journal, &file_path).1.as_deref(), Some("v2"));
+
+        assert!(load_named(&mut journal, "v1", LiveFilePolicy::Refuse, &file_path));
+        assert_eq!(snapshot_names(&mut journal, &file_path).1.as_deref(), Some("v1"));
+
+        // A load that fails leaves it where it was, and it survives reopening the store
+        assert!(!load_named(&mut journal, "v3", LiveFilePolicy::Refuse, &file_path));
+        drop(journal);
+
+        let mut journal = test_dir.open_store();
+        assert_eq!(snapshot_names(&mut journal, &file_path).1.as_deref(), Some("v1"));
+    }
+
+    #[test]
+    fn unsaved_edits_are_refused_snapped_or_discarded() {
+        let test_dir = TestDir::new("load");
+        let file_path = test_dir.join("app.conf");
+
+        write(&file_path, "first\n").unwrap();
+        let mut journal = test_dir.open_store();
+        assert!(snap(&mut journal, snap_options("v1"), vec![file_path.to_owned()]).is_ok());
+
+        write(&file_path, "second\n").unwrap();
+        assert!(snap(&mut journal, snap_options("v2"), vec![file_path.to_owned()]).is_ok());
+
+        write(&file_path, "edited\n").unwrap();
+        assert!(!load_named(&mut journal, "v1", LiveFilePolicy::Refuse, &file_path));
+        assert_eq!(read_to_string(&file_path).unwrap(), "edited\n");
+
+        assert!(load_named(&mut journal, "v1", LiveFilePolicy::AutoSnap, &file_path));
+        assert_eq!(read_to_string(&file_path).unwrap(), "first\n");
+
+        let (taken_snapshot_names, _) = snapshot_names(&mut journal, &file_path);
+        let auto_snapshot_name = taken_snapshot_names.iter().find(|name| *name != "v1" && *name != "v2").unwrap();
+        assert!(load_named(&mut journal, auto_snapshot_name, LiveFilePolicy::Refuse, &file_path));
+        assert_eq!(read_to_string(&file_path).unwrap(), "edited\n");
+
+        write(&file_path, "edited again\n").unwrap();
+        assert!(load_named(&mut journal, "v2", LiveFilePolicy::Discard, &file_path));
+        assert_eq!(read_to_string(&file_path).unwrap(), "second\n");
+        assert_eq!(taken_snapshot_names.len(), 3);
+        assert_eq!(snapshot_names(&mut journal, &file_path).0, taken_snapshot_names);
+    }
+
+    #[test]
+    fn set_load_with_a_missing_member_snapshot_changes_nothing() {
+        let test_dir = TestDir::new("load");
+        let first_member = test_dir.join("first.conf");
+        let second_member = test_dir.join("second.conf");
+
+        write(&first_member, "v1\n").unwrap();
+        write(&second_member, "v1\n").unwrap();
+
+        let mut journal = test_dir.open_store();
+
+        // Only the first member has the snapshot that the set snapshot refers to
+        assert!(snap(&mut journal, snap_options("v1"), vec![first_member.to_owned()]).is_ok());
+        write(&first_member, "v2\n").unwrap();
+        assert!(snap(&mut journal, snap_options("v2"), vec![first_member.to_owned(), second_member.to_owned()]).is_ok());
+
+        let members: Vec<PathBuf> = vec![first_member.to_owned(), second_member.to_owned()];
+        let mut snapshots = BTreeMap::new();
+        snapshots.insert(String::from("v1"), SetSnapshot {
+            created_time: Utc::now(),
+            members: members.iter().map(|member| (member.to_owned(), String::from("v1"))).collect()
+        });
+        journal.add_set(SnapshotSet { name: String::from("service"), members, snapshots, hooks: BTreeMap::new() });
+
+        assert!(load_set(&mut journal, "service", "v1", LiveFilePolicy::Refuse).is_err());
+        assert_eq!(read_to_string(&first_member).unwrap(), "v2\n");
+        assert_eq!(read_to_string(&second_member).unwrap(), "v1\n");
+    }
+
+    #[test]
+    fn tree_load_restores_filtered_files_and_leaves_excluded_ones() {
+        let test_dir = TestDir::new("load");
+        let tree_path = test_dir.join("sites");
+
+        create_dir_all(tree_path.join("enabled")).unwrap();
+        write(tree_path.join("enabled/app.conf"), "app v1\n").unwrap();
+        write(tree_path.join("base.conf"), "base v1\n").unwrap();
+        write(tree_path.join("access.log"), "first request\n").unwrap();
+
+        let mut journal = test_dir.open_store();
+        let options = SnapOptions { exclude: vec![String::from("*.log")], ..snap_options("v1") };
+        assert!(snap(&mut journal, options, vec![tree_path.to_owned()]).is_ok());
+
+        write(tree_path.join("enabled/app.conf"), "app v2\n").unwrap();
+        write(tree_path.join("enabled/new.conf"), "added\n").unwrap();
+        write(tree_path.join("access.log"), "second request\n").unwrap();
+
+        // The log is excluded, so only the edits to the configs make the tree live
+        assert!(!load_named(&mut journal, "v1", LiveFilePolicy::Refuse, &tree_path));
+        assert!(load_named(&mut journal, "v1", LiveFilePolicy::Discard, &tree_path));
+
+        assert_eq!(read_to_string(tree_path.join("enabled/app.conf")).unwrap(), "app v1\n");
+        assert_eq!(read_to_string(tree_path.join("base.conf")).unwrap(), "base v1\n");
+        assert!(!tree_path.join("enabled/new.conf").exists());
+        assert_eq!(read_to_string(tree_path.join("access.log")).unwrap(), "second request\n");
+
+        write(tree_path.join("access.log"), "third request\n").unwrap();
+        assert!(load_named(&mut journal, "v1", LiveFilePolicy::Refuse, &tree_path));
+    }
+
+    #[test]
+    fn load_restores_mode_and_modification_time() {
+        let test_dir = TestDir::new("load");
+        let file_path = test_dir.join("id_ed25519");
+
+        write(&file_path, "key\n").unwrap();
+        set_permissions(&file_path, Permissions::from_mode(0o600)).unwrap();
+        let snapped_metadata = FileMetadata::read(&file_path).unwrap();
+
+        let mut journal = test_dir.open_store();
+        assert!(snap(&mut journal, snap_options("v1"), vec![file_path.to_owned()]).is_ok());
+
+        // Only the permissions change, which is enough for the file to be live
+        set_permissions(&file_path, Permissions::from_mode(0o644)).unwrap();
+        File::open(&file_path).unwrap().set_modified(SystemTime::now() + Duration::from_secs(60)).unwrap();
+        assert!(!load_named(&mut journal, "v1", LiveFilePolicy::Refuse, &file_path));
+
+        assert!(load_named(&mut journal, "v1", LiveFilePolicy::Discard, &file_path));
+        let loaded_metadata = FileMetadata::read(&file_path).unwrap();
+
+        assert_eq!(loaded_metadata.mode, 0o600);
+        assert_eq!(loaded_metadata.modified_time, snapped_metadata.modified_time);
+        assert_eq!(read_to_string(&file_path).unwrap(), "key\n");
+    }
+
+    #[test]
+    fn absent_snapshots_remove_and_recreate_the_file() {
+        let test_dir = TestDir::new("load");
+        let file_path = test_dir.join("added-by-upgrade.conf");
+
+        // A path that does not exist yet is snapped as absent
+        let mut journal = test_dir.open_store();
+        assert!(snap(&mut journal, snap_options("before"), vec![file_path.to_owned()]).is_ok());
+
+        write(&file_path, "added\n").unwrap();
+        assert!(snap(&mut journal, snap_options("after"), vec![file_path.to_owned()]).is_ok());
+
+        assert!(load_named(&mut journal, "before", LiveFilePolicy::Refuse, &file_path));
+        assert!(!file_path.exists());
+
+        assert!(load_named(&mut journal, "after", LiveFilePolicy::Refuse, &file_path));
+        assert_eq!(read_to_string(&file_path).unwrap(), "added\n");
+
+        // Removing a live file through (null) snapshots it first
+        write(&file_path, "edited\n").unwrap();
+        assert!(load_named(&mut journal, "(null)", LiveFilePolicy::AutoSnap, &file_path));
+        assert!(!file_path.exists());
+
+        let (taken_snapshot_names, current_snapshot_name) = snapshot_names(&mut journal, &file_path);
+        assert_eq!(taken_snapshot_names.len(), 3);
+        assert_eq!(current_snapshot_name, Some(String::from("(null)")));
+    }
+
+    #[test]
+    fn failing_hooks_veto_the_load() {
+        let test_dir = TestDir::new("load");
+        let file_path = test_dir.join("nginx.conf");
+
+        write(&file_path, "good\n").unwrap();
+        let mut journal = test_dir.open_store();
+        assert!(snap(&mut journal, snap_options("v1"), vec![file_path.to_owned()]).is_ok());
+
+        write(&file_path, "broken\n").unwrap();
+        assert!(snap(&mut journal, snap_options("v2"), vec![file_path.to_owned()]).is_ok());
+
+        let validate = (Hook::Validate, Some(String::from("grep -q good \"$HIPPO_CANDIDATE\"")));
+        assert!(hook(&mut journal, HookTarget::File(file_path.to_owned()), vec![validate]).is_ok());
+
+        assert!(load_named(&mut journal, "v1", LiveFilePolicy::Refuse, &file_path));
+        assert_eq!(read_to_string(&file_path).unwrap(), "good\n");
+
+        assert!(!load_named(&mut journal, "v2", LiveFilePolicy::Refuse, &file_path));
+        assert_eq!(read_to_string(&file_path).unwrap(), "good\n");
+        assert_eq!(snapshot_names(&mut journal, &file_path).1, Some(String::from("v1")));
+
+        // A failing pre-load hook stops the load before the live edits are auto-snapped
+        let pre_load = (Hook::PreLoad, Some(String::from("false")));
+        assert!(hook(&mut journal, HookTarget::File(file_path.to_owned()), vec![pre_load]).is_ok());
+
+        write(&file_path, "good, edited\n").unwrap();
+        assert!(!load_named(&mut journal, "v1", LiveFilePolicy::AutoSnap, &file_path));
+        assert_eq!(read_to_string(&file_path).unwrap(), "good, edited\n");
+        assert_eq!(snapshot_names(&mut journal, &file_path).0, vec!["v1", "v2"]);
+    }
+}
@@ -14,19 +14,38 @@ extern crate colored;
 mod journaling;
 mod snap;
 mod list;
+mod load;
+mod diff;
+mod cat;
+mod migrate;
+mod fsck;
+mod recover;
+mod rm;
+mod gc;
+mod prune;
+mod retention;
+mod rename;
+mod annotate;
+mod set;
+mod hook;
 mod utils;
 mod commons;
 
 use std::env;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::fs;
-use journaling::journal::Journal;
+use journaling::journal::{Journal, JournalError};
+use journaling::store_lock::LockMode;
 use clap::{App, SubCommand, Arg};
 use list::list;
+use utils::compression::Codec;
+use utils::paths::absolute_path;
+use journaling::managed_file::{SnapshotLabels, SymlinkPolicy};
+use journaling::hooks::{Hook, HOOKS};
 
 use colored::*;
 
-fn init_hippo() -> Journal {
+fn init_hippo(lock_mode: LockMode, wait_for_locks: bool) -> Result<Journal, JournalError> {
     let mut home_dir = if let Some(path) = env::home_dir() {
         if let Some(path_str) = path.to_str() {
             path_str.to_owned()
@@ -38,7 +57,7 @@ fn init_hippo() -> Journal {
     };
 
     home_dir.push_str("/.hippo");
-    Journal::initialize(home_dir)
+    Journal::initialize(home_dir, lock_mode, wait_for_locks)
 }
 
 struct HippoError;
@@ -49,6 +68,10 @@ impl From<list::ListError> for HippoError {
     }
 }
 
+impl From<JournalError> for HippoError {
+    fn from(_: JournalError) -> Self { HippoError }
+}
+
 impl From<clap::Error> for HippoError {
     fn from(_: clap::Error) -> Self { HippoError {} }
 }
@@ -57,22 +80,89 @@ impl From<snap::SnapError> for HippoError {
     fn from(_: snap::SnapError) -> Self { HippoError }
 }
 
-fn main_func() -> Result<(), HippoError> {
-    let mut journal = init_hippo();
+impl From<load::LoadError> for HippoError {
+    fn from(_: load::LoadError) -> Self { HippoError }
+}
+
+impl From<diff::DiffError> for HippoError {
+    fn from(_: diff::DiffError) -> Self { HippoError }
+}
+
+impl From<cat::CatError> for HippoError {
+    fn from(_: cat::CatError) -> Self { HippoError }
+}
+
+impl From<migrate::MigrateError> for HippoError {
+    fn from(_: migrate::MigrateError) -> Self { HippoError }
+}
+
+impl From<fsck::FsckError> for HippoError {
+    fn from(_: fsck::FsckError) -> Self { HippoError }
+}
+
+impl From<recover::RecoverError> for HippoError {
+    fn from(_: recover::RecoverError) -> Self { HippoError }
+}
+
+impl From<rm::RmError> for HippoError {
+    fn from(_: rm::RmError) -> Self { HippoError }
+}
+
+impl From<gc::GcError> for HippoError {
+    fn from(_: gc::GcError) -> Self { HippoError }
+}
+
+impl From<prune::PruneError> for HippoError {
+    fn from(_: prune::PruneError) -> Self { HippoError }
+}
+
+impl From<retention::RetentionError> for HippoError {
+    fn from(_: retention::RetentionError) -> Self { HippoError }
+}
+
+impl From<rename::RenameError> for HippoError {
+    fn from(_: rename::RenameError) -> Self { HippoError }
+}
+
+impl From<annotate::AnnotateError> for HippoError {
+    fn from(_: annotate::AnnotateError) -> Self { HippoError }
+}
+
+impl From<set::SetError> for HippoError {
+    fn from(_: set::SetError) -> Self { HippoError }
+}
 
+impl From<hook::HookError> for HippoError {
+    fn from(_: hook::HookError) -> Self { HippoError }
+}
+
+fn main_func() -> Result<(), HippoError> {
     let mut clap_app = App::new("hippo".magenta().to_string())
         .version("0.1")
         .author("Rohan Prabhu <rohan@rohanprabhu.com>")
         .about("Variant manager for configuration-like files. Hippo keeps track of everything. EVERYTHING.")
+        .arg(Arg::with_name("wait")
+            .long("wait")
+            .help("If another hippo process is using the store, wait for it to finish instead of \n\
+                   failing right away")
+            .global(true)
+        )
         .subcommand(SubCommand::with_name("snap")
             .version("0.1")
             .about("Create a new snapshot for given files")
             .arg(Arg::with_name("FILE")
                 .help("The files to create a snapshot for, could be absolute or relative to the \
                        current working directory")
-                .required(true)
+                .required_unless("set")
+                .conflicts_with("set")
                 .multiple(true)
             )
+            .arg(Arg::with_name("set")
+                .long("set")
+                .value_name("set")
+                .help("Snapshot every file in this set instead, under the same name. The snapshot \n\
+                       can then be loaded for all of them at once with `hippo load --set`")
+            )
             .arg(Arg::with_name("name")
                 .long("name")
                 .short("n")
@@ -91,45 +181,622 @@ fn main_func() -> Result<(), HippoError> {
                 .required(false)
                 .multiple(false)
             )
+            .arg(Arg::with_name("compress")
+                .long("compress")
+                .help("Compress the stored snapshot. Snapshots are decompressed transparently \n\
+                       when they are loaded")
+            )
+            .arg(Arg::with_name("tag")
+                .long("tag")
+                .short("t")
+                .value_name("tag")
+                .help("A tag for the snapshot, such as `prod` or `known-good`. Can be repeated")
+                .multiple(true)
+                .number_of_values(1)
+            )
+            .arg(Arg::with_name("meta")
+                .long("meta")
+                .short("m")
+                .value_name("key=value")
+                .help("A key/value pair to attach to the snapshot, such as `ticket=OPS-1234`. Can \n\
+                       be repeated")
+                .multiple(true)
+                .number_of_values(1)
+            )
+            .arg(Arg::with_name("force")
+                .long("force")
+                .short("f")
+                .help("Replace an existing snapshot with the same name instead of failing")
+            )
+            .arg(Arg::with_name("prune")
+                .long("prune")
+                .help("Prune the snapshots of the given files according to their retention \n\
+                       policy, once the new snapshots have been created")
+            )
+            .arg(Arg::with_name("symlinks")
+                .long("symlinks")
+                .value_name("policy")
+                .possible_values(&["manage", "follow"])
+                .help("How to treat files that are symlinks: `manage` snapshots the link itself (the \n\
+                       path it points to) and replaces the link on load, `follow` snapshots and \n\
+                       replaces whatever it points to. Remembered for later snapshots of the \n\
+                       same file, and `follow` if never given")
+            )
+            .arg(Arg::with_name("include")
+                .long("include")
+                .value_name("glob")
+                .help("For directories, only snapshot the files matching this pattern, such as \n\
+                       `*.conf` or `sites-enabled/**`. Can be repeated. Remembered for later \n\
+                       snapshots of the same directory")
+                .multiple(true)
+                .number_of_values(1)
+            )
+            .arg(Arg::with_name("exclude")
+                .long("exclude")
+                .value_name("glob")
+                .help("For directories, leave out the files and directories matching this \n\
+                       pattern. Can be repeated. Remembered for later snapshots of the same \n\
+                       directory")
+                .multiple(true)
+                .number_of_values(1)
+            )
         )
         .subcommand(SubCommand::with_name("list")
             .version("0.1")
             .about("List all snapshots for given files")
             .arg(Arg::with_name("FILE")
                 .help("The files to list the snapshot for, could be absolute or relative to the \
+                       current working directory. If not provided, all managed files are listed")
+                .required(false)
+                .index(1)
+                .multiple(true)
+            )
+            .arg(Arg::with_name("summary")
+                .long("summary")
+                .short("s")
+                .help("Only print a single line for each file, with the number of snapshots and the \n\
+                       snapshot it is currently at")
+            )
+            .arg(Arg::with_name("tag")
+                .long("tag")
+                .short("t")
+                .value_name("tag")
+                .help("Only list snapshots with this tag. Can be repeated, in which case snapshots \n\
+                       need to have all of the tags")
+                .multiple(true)
+                .number_of_values(1)
+            )
+        )
+        .subcommand(SubCommand::with_name("load")
+            .version("0.1")
+            .about("Load a snapshot onto the given file, replacing its current contents")
+            .arg(Arg::with_name("FILE")
+                .help("The file to load the snapshot onto, could be absolute or relative to the \
+                       current working directory. With --set, the name of the snapshot of the set \
+                       to load instead")
+                .required(true)
+                .index(1)
+            )
+            .arg(Arg::with_name("SNAPSHOT")
+                .help("Name of the snapshot to load. Could also be (null), which removes the file")
+                .required_unless_one(&["tag", "set"])
+                .conflicts_with_all(&["tag", "set"])
+                .index(2)
+            )
+            .arg(Arg::with_name("set")
+                .long("set")
+                .value_name("set")
+                .help("Load a snapshot of this set, for all of its files. Nothing is loaded unless \n\
+                       every file can be")
+                .conflicts_with("tag")
+            )
+            .arg(Arg::with_name("tag")
+                .long("tag")
+                .short("t")
+                .value_name("tag")
+                .help("Load the snapshot with this tag instead of naming it. Can be repeated, in \n\
+                       which case the snapshot needs to have all of the tags")
+                .multiple(true)
+                .number_of_values(1)
+            )
+            .arg(Arg::with_name("latest")
+                .long("latest")
+                .requires("tag")
+                .help("If more than one snapshot has the tags, load the most recent one")
+            )
+            .arg(Arg::with_name("auto-snap")
+                .long("auto-snap")
+                .help("If the file has been modified since it was last snapped or loaded, create a \n\
+                       snapshot for it before loading")
+                .conflicts_with("discard-live")
+            )
+            .arg(Arg::with_name("discard-live")
+                .long("discard-live")
+                .help("If the file has been modified since it was last snapped or loaded, discard the \n\
+                       changes. The discarded file is kept in the temporary directory")
+            )
+        )
+        .subcommand(SubCommand::with_name("diff")
+            .version("0.1")
+            .about("Show the differences between two snapshots of a file, or between a snapshot and \
+                    the file as it currently exists")
+            .arg(Arg::with_name("FILE")
+                .help("The file to compare snapshots for, could be absolute or relative to the \
                        current working directory")
                 .required(true)
                 .index(1)
+            )
+            .arg(Arg::with_name("FROM")
+                .help("Name of the snapshot to compare from. Could also be (null) or (live)")
+                .required(true)
+                .index(2)
+            )
+            .arg(Arg::with_name("TO")
+                .help("Name of the snapshot to compare to. Could also be (null) or (live); if not \n\
+                       provided, the file as it currently exists is used")
+                .required(false)
+                .index(3)
+            )
+        )
+        .subcommand(SubCommand::with_name("cat")
+            .version("0.1")
+            .about("Print the contents of a snapshot")
+            .arg(Arg::with_name("FILE")
+                .help("The file the snapshot belongs to, could be absolute or relative to the \
+                       current working directory")
+                .required(true)
+                .index(1)
+            )
+            .arg(Arg::with_name("SNAPSHOT")
+                .help("Name of the snapshot to print. Could also be (null) or (live)")
+                .required(true)
+                .index(2)
+            )
+        )
+        .subcommand(SubCommand::with_name("migrate")
+            .version("0.1")
+            .about("Upgrade the store to the current on-disk format. Stores written by older \
+                    versions of hippo are still readable, but should be migrated")
+        )
+        .subcommand(SubCommand::with_name("fsck")
+            .version("0.1")
+            .about("Check the store for unreadable journals, missing or corrupt snapshots and \
+                    orphaned directories")
+            .arg(Arg::with_name("repair")
+                .long("repair")
+                .help("Rebuild broken snapshots where possible, and move everything else that is \n\
+                       broken into a quarantine directory inside the store")
+            )
+        )
+        .subcommand(SubCommand::with_name("recover")
+            .version("0.1")
+            .about("Rebuild the root journal from the managed directories in the store, for when it \
+                    has been lost or damaged")
+        )
+        .subcommand(SubCommand::with_name("rm")
+            .version("0.1")
+            .about("Remove snapshots of a file, along with their contents unless other snapshots \
+                    share them")
+            .arg(Arg::with_name("FILE")
+                .help("The managed file to remove snapshots of")
+                .required(true)
+                .index(1)
+            )
+            .arg(Arg::with_name("SNAPSHOT")
+                .help("Names of the snapshots to remove")
+                .required(true)
                 .multiple(true)
+                .index(2)
+            )
+            .arg(Arg::with_name("force")
+                .long("force")
+                .short("f")
+                .help("Also remove the snapshots of sets that the snapshots are part of, instead of failing")
+            )
+        )
+        .subcommand(SubCommand::with_name("gc")
+            .version("0.1")
+            .about("Remove snapshot contents that are no longer referenced, and the managed \
+                    directories of files without any snapshots")
+            .arg(Arg::with_name("dry-run")
+                .long("dry-run")
+                .help("Only show what would be removed, and how much space it would reclaim")
+            )
+        )
+        .subcommand(SubCommand::with_name("prune")
+            .version("0.1")
+            .about("Remove the snapshots that are not kept by the retention policy of their file")
+            .arg(Arg::with_name("FILE")
+                .help("The files to prune. If not provided, all managed files are pruned")
+                .required(false)
+                .index(1)
+                .multiple(true)
+            )
+            .arg(Arg::with_name("dry-run")
+                .long("dry-run")
+                .help("Only show which snapshots would be removed")
+            )
+        )
+        .subcommand(SubCommand::with_name("retention")
+            .version("0.1")
+            .about("Show or change the retention policy of a file, or the global one which applies to \
+                    files without a policy of their own")
+            .arg(Arg::with_name("FILE")
+                .help("The file to show or change the policy of. If not provided, the global policy is \n\
+                       used instead")
+                .required(false)
+                .index(1)
+            )
+            .arg(Arg::with_name("set")
+                .long("set")
+                .value_name("policy")
+                .help("Rules deciding which snapshots are kept, separated by commas: `last=N` keeps \n\
+                       the N most recent snapshots, `daily=DAYS` and `weekly=WEEKS` keep the most \n\
+                       recent snapshot of each day (or week) for that many days (or weeks). Named \n\
+                       snapshots are never pruned unless `prune-named` is included")
+                .conflicts_with("clear")
+            )
+            .arg(Arg::with_name("clear")
+                .long("clear")
+                .help("Remove the policy")
+            )
+        )
+        .subcommand(SubCommand::with_name("rename")
+            .version("0.1")
+            .about("Give a snapshot a new name")
+            .arg(Arg::with_name("FILE")
+                .help("The managed file the snapshot belongs to")
+                .required(true)
+                .index(1)
+            )
+            .arg(Arg::with_name("OLD")
+                .help("The current name of the snapshot")
+                .required(true)
+                .index(2)
+            )
+            .arg(Arg::with_name("NEW")
+                .help("The new name of the snapshot")
+                .required(true)
+                .index(3)
+            )
+        )
+        .subcommand(SubCommand::with_name("annotate")
+            .version("0.1")
+            .about("Change the comment of a snapshot")
+            .arg(Arg::with_name("FILE")
+                .help("The managed file the snapshot belongs to")
+                .required(true)
+                .index(1)
+            )
+            .arg(Arg::with_name("SNAPSHOT")
+                .help("Name of the snapshot")
+                .required(true)
+                .index(2)
+            )
+            .arg(Arg::with_name("comment")
+                .long("comment")
+                .short("c")
+                .value_name("comment")
+                .help("The new comment for the snapshot")
+            )
+            .arg(Arg::with_name("tag")
+                .long("tag")
+                .short("t")
+                .value_name("tag")
+                .help("A tag to add to the snapshot. Can be repeated")
+                .multiple(true)
+                .number_of_values(1)
+            )
+            .arg(Arg::with_name("untag")
+                .long("untag")
+                .value_name("tag")
+                .help("A tag to remove from the snapshot. Can be repeated")
+                .multiple(true)
+                .number_of_values(1)
+            )
+            .arg(Arg::with_name("meta")
+                .long("meta")
+                .short("m")
+                .value_name("key=value")
+                .help("A key/value pair to add to the snapshot, replacing any existing value for the \n\
+                       key. Can be repeated")
+                .multiple(true)
+                .number_of_values(1)
+            )
+            .arg(Arg::with_name("unmeta")
+                .long("unmeta")
+                .value_name("key")
+                .help("A metadata key to remove from the snapshot. Can be repeated")
+                .multiple(true)
+                .number_of_values(1)
+            )
+        )
+        .subcommand(SubCommand::with_name("set")
+            .version("0.1")
+            .about("Manage sets of files that are snapped and loaded together")
+            .subcommand(SubCommand::with_name("create")
+                .about("Create a set of files")
+                .arg(Arg::with_name("NAME")
+                    .help("Name of the set")
+                    .required(true)
+                    .index(1)
+                )
+                .arg(Arg::with_name("FILE")
+                    .help("The files in the set, could be absolute or relative to the current \
+                           working directory")
+                    .required(true)
+                    .index(2)
+                    .multiple(true)
+                )
+            )
+            .subcommand(SubCommand::with_name("list")
+                .about("List all sets, with their files and snapshots")
+            )
+            .subcommand(SubCommand::with_name("rm")
+                .about("Remove a set. The snapshots of its files are kept")
+                .arg(Arg::with_name("NAME")
+                    .help("Name of the set")
+                    .required(true)
+                    .index(1)
+                )
+            )
+        )
+        .subcommand(SubCommand::with_name("hook")
+            .version("0.1")
+            .about("Show or change the commands run around snapping and loading a file, or a set of \
+                    files. Commands run through `sh -c`, with the path of the file and the name of \
+                    the snapshot in $HIPPO_FILE and $HIPPO_SNAPSHOT (for sets, the name of the set \
+                    in $HIPPO_SET and the paths of its files in $HIPPO_FILES)")
+            .arg(Arg::with_name("FILE")
+                .help("The managed file to show or change the hooks of")
+                .required_unless("set")
+                .conflicts_with("set")
+                .index(1)
+            )
+            .arg(Arg::with_name("set")
+                .long("set")
+                .value_name("set")
+                .help("Show or change the hooks of this set instead. They run once for the whole set, \n\
+                       in addition to the hooks of its files")
+            )
+            .arg(Arg::with_name("pre-snap")
+                .long("pre-snap")
+                .value_name("command")
+                .help("Run before a snapshot is taken. If it fails, the snapshot is not taken")
+            )
+            .arg(Arg::with_name("pre-load")
+                .long("pre-load")
+                .value_name("command")
+                .help("Run before a snapshot is loaded. If it fails, nothing is loaded")
+            )
+            .arg(Arg::with_name("validate")
+                .long("validate")
+                .value_name("command")
+                .help("Run against the contents about to be loaded, before they replace the file, with \n\
+                       their path in $HIPPO_CANDIDATE. If it fails, nothing is loaded")
+            )
+            .arg(Arg::with_name("post-load")
+                .long("post-load")
+                .value_name("command")
+                .help("Run once a snapshot has been loaded, such as `systemctl reload nginx`")
+            )
+            .arg(Arg::with_name("clear")
+                .long("clear")
+                .value_name("hook")
+                .possible_values(&["pre-snap", "pre-load", "validate", "post-load"])
+                .help("Remove a hook. Can be repeated")
+                .multiple(true)
+                .number_of_values(1)
             )
         );
 
     let arg_matches = clap_app.to_owned().get_matches();
 
+    // Commands that only read from the store can share it with each other
+    let lock_mode = match arg_matches.subcommand_name() {
+        Some("list") | Some("diff") | Some("cat") => LockMode::Shared,
+        _ => LockMode::Exclusive
+    };
+    let wait_for_locks = arg_matches.is_present("wait") || arg_matches.subcommand().1
+        .is_some_and(|matches| matches.is_present("wait"));
+
+    let mut journal = init_hippo(lock_mode, wait_for_locks)?;
+
+    match arg_matches.subcommand_name() {
+        Some("fsck") | Some("recover") => {}
+        _ => if journal.rejected_line_count() > 0 {
+            println!("{} {} lines of the root journal, configuration or sets could not be read, run `hippo fsck`",
+                     "WARN".yellow(), journal.rejected_line_count());
+        }
+    }
+
     if let Some(matches) = arg_matches.subcommand_matches("snap") {
-        let raw_file_paths = values_t!(matches.values_of("FILE"), String).unwrap();
-        let absolute_paths = raw_file_paths
-            .iter()
-            .map(|path: &String|
-                fs::canonicalize(Path::new(path).to_path_buf()).unwrap()
-            )
-            .collect::<Vec<_>>();
-
-        snap::snap(&mut journal,
-                   value_t!(matches.value_of("name"), String).ok(),
-                   value_t!(matches.value_of("comment"), String).ok(),
-                   absolute_paths
-        )?;
+        let options = snap::SnapOptions {
+            name: value_t!(matches.value_of("name"), String).ok(),
+            comment: value_t!(matches.value_of("comment"), String).ok(),
+            codec: if matches.is_present("compress") { Codec::Gzip } else { Codec::None },
+            labels: parse_labels(matches, "tag", "meta")?,
+            force: matches.is_present("force"),
+            include: values_t!(matches.values_of("include"), String).unwrap_or_default(),
+            exclude: values_t!(matches.values_of("exclude"), String).unwrap_or_default(),
+            symlink_policy: matches.value_of("symlinks").and_then(SymlinkPolicy::parse)
+        };
+
+        let absolute_paths = if let Ok(set_name) = value_t!(matches.value_of("set"), String) {
+            snap::snap_set(&mut journal, &set_name, options)?;
+            journal.get_set(&set_name).map(|snapshot_set| snapshot_set.members.to_owned()).unwrap_or_default()
+        } else {
+            let raw_file_paths = values_t!(matches.values_of("FILE"), String).unwrap();
+            let absolute_paths = resolve_paths(&journal, &raw_file_paths)?;
+
+            snap::snap(&mut journal, options, absolute_paths.to_owned())?;
+            absolute_paths
+        };
+
+        if matches.is_present("prune") {
+            prune::prune(&mut journal, false, absolute_paths)?;
+        }
 
     } else if let Some(matches) = arg_matches.subcommand_matches("list") {
-        let raw_file_paths = values_t!(matches.values_of("FILE"), String).unwrap();
-        let absolute_paths = raw_file_paths
-            .iter()
-            .map(|path: &String|
-                fs::canonicalize(Path::new(path).to_path_buf()).unwrap())
-            .collect::<Vec<_>>();
-
-        list(&mut journal, absolute_paths)?;
+        let raw_file_paths = values_t!(matches.values_of("FILE"), String).unwrap_or_default();
+        let absolute_paths = resolve_paths(&journal, &raw_file_paths)?;
+
+        list(&mut journal,
+             matches.is_present("summary"),
+             values_t!(matches.values_of("tag"), String).unwrap_or_default(),
+             absolute_paths
+        )?;
+    } else if let Some(matches) = arg_matches.subcommand_matches("load") {
+        let live_file_policy = if matches.is_present("auto-snap") {
+            load::LiveFilePolicy::AutoSnap
+        } else if matches.is_present("discard-live") {
+            load::LiveFilePolicy::Discard
+        } else {
+            load::LiveFilePolicy::Refuse
+        };
+
+        if let Ok(set_name) = value_t!(matches.value_of("set"), String) {
+            load::load_set(&mut journal,
+                           &set_name,
+                           &value_t!(matches.value_of("FILE"), String).unwrap(),
+                           live_file_policy
+            )?;
+
+            return Ok(());
+        }
+
+        let raw_file_path = value_t!(matches.value_of("FILE"), String).unwrap();
+        let absolute_path = resolve_path(&journal, &raw_file_path)?;
+
+        let selector = match value_t!(matches.value_of("SNAPSHOT"), String) {
+            Ok(snapshot_name) => load::SnapshotSelector::Name(snapshot_name),
+            Err(_) => load::SnapshotSelector::Tags {
+                tags: values_t!(matches.values_of("tag"), String).unwrap(),
+                latest: matches.is_present("latest")
+            }
+        };
+
+        load::load(&mut journal,
+                   selector,
+                   live_file_policy,
+                   absolute_path
+        )?;
+    } else if let Some(matches) = arg_matches.subcommand_matches("diff") {
+        let raw_file_path = value_t!(matches.value_of("FILE"), String).unwrap();
+        let absolute_path = resolve_path(&journal, &raw_file_path)?;
+
+        diff::diff(&mut journal,
+                   value_t!(matches.value_of("FROM"), String).unwrap(),
+                   value_t!(matches.value_of("TO"), String).ok(),
+                   absolute_path
+        )?;
+    } else if let Some(matches) = arg_matches.subcommand_matches("cat") {
+        let raw_file_path = value_t!(matches.value_of("FILE"), String).unwrap();
+        let absolute_path = resolve_path(&journal, &raw_file_path)?;
+
+        cat::cat(&mut journal,
+                 value_t!(matches.value_of("SNAPSHOT"), String).unwrap(),
+                 absolute_path
+        )?;
+    } else if arg_matches.subcommand_matches("migrate").is_some() {
+        migrate::migrate(&mut journal)?;
+    } else if let Some(matches) = arg_matches.subcommand_matches("fsck") {
+        fsck::fsck(&mut journal, matches.is_present("repair"))?;
+    } else if arg_matches.subcommand_matches("recover").is_some() {
+        recover::recover(&mut journal)?;
+    } else if let Some(matches) = arg_matches.subcommand_matches("rm") {
+        let raw_file_path = value_t!(matches.value_of("FILE"), String).unwrap();
+        let absolute_path = resolve_path(&journal, &raw_file_path)?;
+
+        rm::rm(&mut journal,
+               values_t!(matches.values_of("SNAPSHOT"), String).unwrap(),
+               absolute_path,
+               matches.is_present("force")
+        )?;
+    } else if let Some(matches) = arg_matches.subcommand_matches("gc") {
+        gc::gc(&mut journal, matches.is_present("dry-run"))?;
+    } else if let Some(matches) = arg_matches.subcommand_matches("prune") {
+        let raw_file_paths = values_t!(matches.values_of("FILE"), String).unwrap_or_default();
+        let absolute_paths = resolve_paths(&journal, &raw_file_paths)?;
+
+        prune::prune(&mut journal, matches.is_present("dry-run"), absolute_paths)?;
+    } else if let Some(matches) = arg_matches.subcommand_matches("retention") {
+        let absolute_path = value_t!(matches.value_of("FILE"), String).ok()
+            .map(|raw_file_path| resolve_path(&journal, &raw_file_path))
+            .transpose()?;
+
+        let change = if let Ok(spec) = value_t!(matches.value_of("set"), String) {
+            retention::RetentionChange::Set(spec)
+        } else if matches.is_present("clear") {
+            retention::RetentionChange::Clear
+        } else {
+            retention::RetentionChange::Show
+        };
+
+        retention::retention(&mut journal, change, absolute_path)?;
+    } else if let Some(matches) = arg_matches.subcommand_matches("rename") {
+        let raw_file_path = value_t!(matches.value_of("FILE"), String).unwrap();
+        let absolute_path = resolve_path(&journal, &raw_file_path)?;
+
+        rename::rename(&mut journal,
+                       value_t!(matches.value_of("OLD"), String).unwrap(),
+                       value_t!(matches.value_of("NEW"), String).unwrap(),
+                       absolute_path
+        )?;
+    } else if let Some(matches) = arg_matches.subcommand_matches("annotate") {
+        let raw_file_path = value_t!(matches.value_of("FILE"), String).unwrap();
+        let absolute_path = resolve_path(&journal, &raw_file_path)?;
+
+        let annotation = annotate::Annotation {
+            comment: value_t!(matches.value_of("comment"), String).ok(),
+            added_labels: parse_labels(matches, "tag", "meta")?,
+            removed_tags: values_t!(matches.values_of("untag"), String).unwrap_or_default(),
+            removed_meta_keys: values_t!(matches.values_of("unmeta"), String).unwrap_or_default()
+        };
+
+        annotate::annotate(&mut journal,
+                           value_t!(matches.value_of("SNAPSHOT"), String).unwrap(),
+                           annotation,
+                           absolute_path
+        )?;
+    } else if let Some(matches) = arg_matches.subcommand_matches("set") {
+        let command = if let Some(matches) = matches.subcommand_matches("create") {
+            let raw_file_paths = values_t!(matches.values_of("FILE"), String).unwrap();
+
+            set::SetCommand::Create {
+                set_name: value_t!(matches.value_of("NAME"), String).unwrap(),
+                members: resolve_paths(&journal, &raw_file_paths)?
+            }
+        } else if matches.subcommand_matches("list").is_some() {
+            set::SetCommand::List
+        } else if let Some(matches) = matches.subcommand_matches("rm") {
+            set::SetCommand::Remove(value_t!(matches.value_of("NAME"), String).unwrap())
+        } else {
+            println!("{}", matches.usage());
+            return Err(HippoError);
+        };
+
+        set::set(&mut journal, command)?;
+    } else if let Some(matches) = arg_matches.subcommand_matches("hook") {
+        let target = match value_t!(matches.value_of("set"), String) {
+            Ok(set_name) => hook::HookTarget::Set(set_name),
+            Err(_) => {
+                let raw_file_path = value_t!(matches.value_of("FILE"), String).unwrap();
+                hook::HookTarget::File(resolve_path(&journal, &raw_file_path)?)
+            }
+        };
+
+        let mut changes: Vec<(Hook, Option<String>)> = matches.values_of("clear").into_iter()
+            .flatten()
+            .filter_map(Hook::parse)
+            .map(|hook| (hook, None))
+            .collect();
+
+        changes.extend(HOOKS.iter().filter_map(|&hook| {
+            value_t!(matches.value_of(hook.name()), String).ok().map(|command| (hook, Some(command)))
+        }));
+
+        hook::hook(&mut journal, target, changes)?;
     } else {
         clap_app.print_help()?;
     }
@@ -137,6 +804,45 @@ fn main_func() -> Result<(), HippoError> {
     Ok(())
 }
 
+/// Resolves a path given on the command line into the absolute path it is managed under. The
+/// path does not need to exist, so that files can be managed before they are created (or after
+/// they have been removed). Symlinks are managed under their own path, except for those that
+/// older versions of hippo already manage under the path they point to.
+fn resolve_path(journal: &Journal, raw_file_path: &str) -> Result<PathBuf, HippoError> {
+    let absolute_path = absolute_path(Path::new(raw_file_path)).map_err(|e| {
+        println!("{} {}, could not resolve the path: {}", "ERROR".red(), raw_file_path, e);
+        HippoError
+    })?;
+
+    let is_managed = |path: &Path| path.to_str()
+        .is_some_and(|path| journal.root_journal_config.records.contains_key(path));
+
+    if !is_managed(&absolute_path) {
+        if let Ok(resolved_path) = fs::canonicalize(&absolute_path) {
+            if resolved_path != absolute_path && is_managed(&resolved_path) {
+                info!("{:?} is managed under the path it points to, {:?}", absolute_path, resolved_path);
+                return Ok(resolved_path);
+            }
+        }
+    }
+
+    Ok(absolute_path)
+}
+
+fn resolve_paths(journal: &Journal, raw_file_paths: &[String]) -> Result<Vec<PathBuf>, HippoError> {
+    raw_file_paths.iter().map(|raw_file_path| resolve_path(journal, raw_file_path)).collect()
+}
+
+fn parse_labels(matches: &clap::ArgMatches, tag_arg: &str, meta_arg: &str) -> Result<SnapshotLabels, HippoError> {
+    SnapshotLabels::parse(
+        values_t!(matches.values_of(tag_arg), String).unwrap_or_default(),
+        values_t!(matches.values_of(meta_arg), String).unwrap_or_default()
+    ).map_err(|e| {
+        println!("{} {}", "ERROR".red(), e);
+        HippoError
+    })
+}
+
 fn main() {
     env_logger::init().unwrap();
     ::std::process::exit(match main_func() {
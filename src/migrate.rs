@@ -0,0 +1,70 @@
+extern crate colored;
+
+use std::path::PathBuf;
+use std::io;
+
+use journaling::journal::Journal;
+use journaling::managed_file_journal::{ManagedFileJournal, ManagedFileJournalError};
+use journaling::managed_file::ManagedFileError;
+
+use self::colored::*;
+
+pub struct MigrateError;
+
+impl From<ManagedFileJournalError> for MigrateError {
+    fn from(_: ManagedFileJournalError) -> Self { MigrateError }
+}
+
+impl From<ManagedFileError> for MigrateError {
+    fn from(_: ManagedFileError) -> Self { MigrateError }
+}
+
+impl From<io::Error> for MigrateError {
+    fn from(_: io::Error) -> Self { MigrateError }
+}
+
+/// Upgrades every journal in the store to the current on-disk format, and moves snapshot
+/// payloads stored in the older per-file layout into the object store.
+pub fn migrate(journal: &mut Journal) -> Result<(), MigrateError> {
+    let mut upgraded_journals = 0;
+    let mut moved_payloads = 0;
+
+    if journal.upgrade()? {
+        println!("{} Upgraded the root journal", "OK".green());
+        upgraded_journals += 1;
+    }
+
+    let mut managed_paths: Vec<PathBuf> = journal.root_journal_config.records
+        .keys()
+        .map(PathBuf::from)
+        .collect();
+    managed_paths.sort();
+
+    let managed_file_journal = ManagedFileJournal::for_journal(journal);
+
+    for file_path in managed_paths {
+        let mut managed_file = match managed_file_journal.get_managed_file(&file_path)? {
+            Some(managed_file) => managed_file,
+            None => continue
+        };
+
+        let (file_upgraded_journals, file_moved_payloads) = managed_file.migrate()?;
+
+        if file_upgraded_journals + file_moved_payloads > 0 {
+            println!("{} {}, upgraded {} journals and moved {} snapshots into the object store", "OK".green(),
+                     file_path.to_string_lossy(), file_upgraded_journals, file_moved_payloads);
+        }
+
+        upgraded_journals += file_upgraded_journals;
+        moved_payloads += file_moved_payloads;
+    }
+
+    if upgraded_journals + moved_payloads == 0 {
+        println!("{} The store is already up to date", "OK".green());
+    } else {
+        println!("{} Migrated the store: upgraded {} journals and moved {} snapshots into the object store",
+                 "OK".green().bold(), upgraded_journals, moved_payloads);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,144 @@
+extern crate chrono;
+extern crate colored;
+extern crate pretty_bytes;
+
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+
+use journaling::journal::Journal;
+use journaling::managed_file_journal::{ManagedFileJournal, ManagedFileJournalError};
+use journaling::managed_file::{ManagedFile, ManagedFileError};
+use journaling::retention::{RetentionPolicy, RETENTION_POLICY_KEY};
+
+use self::chrono::prelude::*;
+use self::colored::*;
+use self::pretty_bytes::converter::convert;
+
+static DEFAULT_SNAPSHOT_TIME_FORMAT: &str = "%a %b %e %T %Y";
+
+pub struct PruneError;
+
+impl From<ManagedFileJournalError> for PruneError {
+    fn from(_: ManagedFileJournalError) -> Self { PruneError }
+}
+
+impl From<ManagedFileError> for PruneError {
+    fn from(_: ManagedFileError) -> Self { PruneError }
+}
+
+/// Removes the snapshots that the retention policy of each file does not keep. A file's own
+/// policy takes precedence over the global one, and files without either are left alone.
+/// Snapshots that are part of a snapshot of a set are always kept. If no files are given, every
+/// managed file is pruned. With `dry_run`, only reports what would be removed.
+pub fn prune(journal: &mut Journal, dry_run: bool, file_paths: Vec<PathBuf>) -> Result<(), PruneError> {
+    let explicit_file_paths = !file_paths.is_empty();
+    let file_paths = if explicit_file_paths {
+        file_paths
+    } else {
+        let mut managed_paths: Vec<PathBuf> = journal.root_journal_config.records
+            .keys()
+            .map(PathBuf::from)
+            .collect();
+
+        managed_paths.sort();
+        managed_paths
+    };
+
+    let global_policy = match journal.get_config(RETENTION_POLICY_KEY).map(str::parse::<RetentionPolicy>) {
+        Some(Ok(policy)) => Some(policy),
+        Some(Err(e)) => {
+            println!("{} The global retention policy could not be read: {}", "ERROR".red(), e);
+            return Err(PruneError);
+        }
+        None => None
+    };
+
+    let set_snapshot_names: HashMap<PathBuf, HashSet<String>> = file_paths.iter()
+        .map(|file_path| {
+            let snapshot_names = journal.set_references(file_path).into_iter()
+                .map(|set_reference| set_reference.snapshot_name)
+                .collect();
+
+            (file_path.to_owned(), snapshot_names)
+        })
+        .collect();
+
+    let managed_file_journal = ManagedFileJournal::for_journal(journal);
+    let mut pruned_payloads = Vec::new();
+    let mut pruned_count = 0;
+    let mut has_errors = false;
+
+    for file_path in file_paths {
+        let file_path_string = file_path.to_string_lossy().into_owned();
+
+        let mut managed_file = match managed_file_journal.get_managed_file(&file_path)? {
+            Some(managed_file) => managed_file,
+            None => {
+                println!("{} {}, file not managed by {}", "WARN".yellow(), file_path_string, "hippo".magenta());
+                continue;
+            }
+        };
+
+        let policy = match effective_policy(&managed_file, &global_policy) {
+            Ok(Some(policy)) => policy,
+            Ok(None) => {
+                if explicit_file_paths {
+                    println!("{} {} has no retention policy, nothing to prune", "WARN".yellow(), file_path_string);
+                }
+
+                continue;
+            }
+            Err(e) => {
+                println!("{} {}, the retention policy could not be read: {}", "ERROR".red(), file_path_string, e);
+                has_errors = true;
+                continue;
+            }
+        };
+
+        let pruned_entries: Vec<_> = {
+            let current_snapshot_name = managed_file.get_current_snapshot().map(|entry| entry.snapshot_name.as_str());
+
+            policy.select_for_pruning(&managed_file.snapshot_entries(), current_snapshot_name, Utc::now())
+                .into_iter()
+                .filter(|snapshot_entry| !set_snapshot_names[&file_path].contains(&snapshot_entry.snapshot_name))
+                .cloned()
+                .collect()
+        };
+
+        for snapshot_entry in &pruned_entries {
+            let created_time = snapshot_entry.created_time.with_timezone(&Local).format(DEFAULT_SNAPSHOT_TIME_FORMAT);
+
+            if dry_run {
+                println!("{} {}, snapshot {} from {}", "WOULD PRUNE".yellow(), file_path_string,
+                         snapshot_entry.snapshot_name.cyan(), created_time);
+            } else {
+                managed_file.remove_snapshot(&snapshot_entry.snapshot_name);
+                pruned_payloads.extend(managed_file.payload_paths(snapshot_entry));
+
+                println!("{} {}, snapshot {} from {}", "PRUNED".green(), file_path_string,
+                         snapshot_entry.snapshot_name.cyan(), created_time);
+            }
+        }
+
+        managed_file.commit()?;
+        pruned_count += pruned_entries.len();
+    }
+
+    if dry_run {
+        println!("{} Would prune {} snapshots (dry run, nothing was removed)", "OK".green().bold(), pruned_count);
+    } else {
+        let reclaimed = managed_file_journal.remove_unreferenced_payloads(pruned_payloads)?;
+
+        println!("{} Pruned {} snapshots (Reclaimed: {})", "OK".green().bold(), pruned_count,
+                 convert(reclaimed as f64));
+    }
+
+    if has_errors { Err(PruneError) } else { Ok(()) }
+}
+
+fn effective_policy(managed_file: &ManagedFile, global_policy: &Option<RetentionPolicy>) -> Result<Option<RetentionPolicy>, String> {
+    match managed_file.get_meta(RETENTION_POLICY_KEY) {
+        Some(spec) => spec.parse().map(Some),
+        None => Ok(global_policy.clone())
+    }
+}
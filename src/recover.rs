@@ -0,0 +1,161 @@
+extern crate colored;
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs::read_dir;
+use std::io;
+use std::path::PathBuf;
+
+use journaling::journal::Journal;
+use journaling::managed_file_journal::ManagedFileJournal;
+
+use self::colored::*;
+
+pub struct RecoverError;
+
+impl From<io::Error> for RecoverError {
+    fn from(_: io::Error) -> Self { RecoverError }
+}
+
+/// Rebuilds the root journal from the managed directories in the store, each of which records
+/// the path of the file it belongs to. Entries already in the root journal are kept; directories
+/// that claim a path which is already managed elsewhere (or which is claimed by more than one
+/// directory) are reported as conflicts and left out.
+pub fn recover(journal: &mut Journal) -> Result<(), RecoverError> {
+    // Unreadable records are dropped, since the whole point is to rebuild them
+    for line in journal.root_journal_config.discard_rejected_lines() {
+        println!("{} Discarding unreadable line {} of the root journal: {}", "WARN".yellow(),
+                 line.line_number, line.content);
+    }
+
+    let managed_dirs: HashMap<PathBuf, String> = journal.root_journal_config.records.values()
+        .map(|entry| (entry.root.to_owned(), entry.key.to_owned()))
+        .collect();
+
+    let mut claims: BTreeMap<PathBuf, Vec<PathBuf>> = BTreeMap::new();
+    let mut unknown = 0;
+
+    let mut dirs: Vec<PathBuf> = read_dir(&journal.root)?
+        .filter_map(|dir_entry| dir_entry.ok().map(|dir_entry| dir_entry.path()))
+        .filter(|path| path.is_dir() && !path.file_name().unwrap().to_string_lossy().starts_with("__"))
+        .collect();
+    dirs.sort();
+
+    for dir in dirs {
+        match ManagedFileJournal::recorded_managed_path(&dir) {
+            Some(managed_path) => claims.entry(managed_path).or_default().push(dir),
+            None => {
+                unknown += 1;
+                println!("{} {} does not record which file it belongs to, it was created by an older version \
+                          of hippo and never opened since", "UNKNOWN".yellow(), dir.to_string_lossy());
+            }
+        }
+    }
+
+    let (mut recovered, mut conflicts) = (0, 0);
+
+    for (managed_path, claimants) in claims {
+        let key = managed_path.to_string_lossy().into_owned();
+
+        let existing_root = journal.root_journal_config.records.get(&key)
+            .map(|entry| entry.root.to_owned())
+            .filter(|root| root.is_dir());
+
+        // Directories that the root journal already assigns to a different file
+        let (claimants, reassigned): (Vec<PathBuf>, Vec<PathBuf>) = claimants.into_iter()
+            .partition(|dir| managed_dirs.get(dir).is_none_or(|owner| *owner == key));
+
+        for dir in reassigned {
+            conflicts += 1;
+            println!("{} {} claims {}, but the root journal assigns it to {}", "CONFLICT".red(),
+                     dir.to_string_lossy(), key, managed_dirs[&dir]);
+        }
+
+        match existing_root {
+            Some(root) => {
+                for dir in claimants.iter().filter(|dir| **dir != root) {
+                    conflicts += 1;
+                    println!("{} {} claims {}, which is already managed by {}", "CONFLICT".red(),
+                             dir.to_string_lossy(), key, root.to_string_lossy());
+                }
+            }
+
+            None if claimants.len() == 1 => {
+                recovered += 1;
+                println!("{} {}, recovered from {}", "OK".green(), key, claimants[0].to_string_lossy());
+                journal.add_record(managed_path.to_owned(), claimants[0].to_owned());
+            }
+
+            None => {
+                for dir in &claimants {
+                    conflicts += 1;
+                    println!("{} {} claims {}, along with {} other directories", "CONFLICT".red(),
+                             dir.to_string_lossy(), key, claimants.len() - 1);
+                }
+            }
+        }
+    }
+
+    journal.commit().map_err(|e| {
+        println!("{} Could not write the root journal: {}", "ERROR".red(), e);
+        e
+    })?;
+
+    println!();
+
+    if conflicts == 0 {
+        println!("{} Recovered {} files into the root journal{}", "OK".green().bold(), recovered,
+                 if unknown > 0 { format!(", {} directories could not be identified", unknown) } else { String::new() });
+        Ok(())
+    } else {
+        println!("{} Recovered {} files into the root journal, {} directories were left out because of \
+                  conflicts", "ERROR".red().bold(), recovered, conflicts);
+        Err(RecoverError)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{read_to_string, remove_file, write};
+
+    use journaling::managed_file_journal::ManagedFileJournal;
+    use snap::snap;
+    use snap::tests::snap_options;
+    use utils::test_dir::TestDir;
+
+    use super::recover;
+
+    #[test]
+    fn lost_root_journal_is_rebuilt_from_the_managed_directories() {
+        let test_dir = TestDir::new("recover");
+        let file_paths = vec![test_dir.join("first.conf"), test_dir.join("second.conf")];
+
+        for file_path in &file_paths {
+            write(file_path, file_path.to_string_lossy().as_bytes()).unwrap();
+        }
+
+        let root_journal_path = {
+            let mut journal = test_dir.open_store();
+            assert!(snap(&mut journal, snap_options("v1"), file_paths.to_owned()).is_ok());
+            journal.root.join("__hippo_journal")
+        };
+
+        remove_file(&root_journal_path).unwrap();
+
+        let mut journal = test_dir.open_store();
+        assert!(journal.root_journal_config.records.is_empty());
+        assert!(recover(&mut journal).is_ok());
+
+        // Recovering again finds nothing new
+        assert!(recover(&mut journal).is_ok());
+        assert_eq!(journal.root_journal_config.records.len(), 2);
+
+        let managed_file_journal = ManagedFileJournal::for_journal(&mut journal);
+
+        for file_path in &file_paths {
+            let managed_file = managed_file_journal.get_managed_file(file_path).ok().unwrap().unwrap();
+            let files = managed_file.read_snapshot_files_by_name("v1").ok().unwrap().unwrap();
+
+            assert_eq!(files[""], read_to_string(file_path).unwrap().into_bytes());
+        }
+    }
+}
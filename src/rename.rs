@@ -0,0 +1,141 @@
+extern crate colored;
+
+use std::path::PathBuf;
+
+use journaling::journal::Journal;
+use journaling::managed_file_journal::{ManagedFileJournal, ManagedFileJournalError};
+use journaling::managed_file::{validate_snapshot_name, ManagedFileError, LIVE_SNAPSHOT_NAME, NULL_SNAPSHOT_NAME};
+
+use self::colored::*;
+
+pub struct RenameError;
+
+impl From<ManagedFileJournalError> for RenameError {
+    fn from(_: ManagedFileJournalError) -> Self { RenameError }
+}
+
+impl From<ManagedFileError> for RenameError {
+    fn from(_: ManagedFileError) -> Self { RenameError }
+}
+
+/// Renames a snapshot of a file. Snapshots of sets that the snapshot is part of are made to
+/// refer to the new name.
+pub fn rename(
+    journal: &mut Journal, snapshot_name: String, new_snapshot_name: String, file_path: PathBuf
+) -> Result<(), RenameError> {
+    let file_path_string = file_path.to_owned().into_os_string().into_string()
+        .expect("Could not read the file path, it might contain invalid characters");
+
+    {
+        let managed_file_journal = ManagedFileJournal::for_journal(journal);
+        rename_snapshot(&managed_file_journal, &snapshot_name, &new_snapshot_name, &file_path, &file_path_string)?;
+    }
+
+    journal.rename_set_references(&file_path, &snapshot_name, &new_snapshot_name);
+    journal.commit().map_err(|e| {
+        println!("{} Could not write the sets: {}", "ERROR".red(), e);
+        RenameError
+    })?;
+
+    println!("{} Renamed snapshot {} of {} to {}", "OK".green().bold(), snapshot_name.cyan(),
+             file_path_string.bold(), new_snapshot_name.cyan());
+
+    Ok(())
+}
+
+fn rename_snapshot(
+    managed_file_journal: &ManagedFileJournal, snapshot_name: &str, new_snapshot_name: &str, file_path: &PathBuf,
+    file_path_string: &str
+) -> Result<(), RenameError> {
+    let mut managed_file = match managed_file_journal.get_managed_file(file_path)? {
+        Some(managed_file) => managed_file,
+        None => {
+            println!("{} {}, file not managed by {}", "ERROR".red(), file_path_string, "hippo".magenta());
+            return Err(RenameError);
+        }
+    };
+
+    if snapshot_name == NULL_SNAPSHOT_NAME || snapshot_name == LIVE_SNAPSHOT_NAME {
+        println!("{} {}, {} is a synthetic snapshot and cannot be renamed", "ERROR".red(), file_path_string,
+                 snapshot_name.cyan());
+        return Err(RenameError);
+    }
+
+    if managed_file.get_snapshot(snapshot_name).is_none() {
+        println!("{} {}, no snapshot named {}", "ERROR".red(), file_path_string, snapshot_name.cyan());
+        return Err(RenameError);
+    }
+
+    if let Err(e) = validate_snapshot_name(new_snapshot_name) {
+        println!("{} {}", "ERROR".red(), e);
+        return Err(RenameError);
+    }
+
+    if managed_file.get_snapshot(new_snapshot_name).is_some() {
+        println!("{} {} already has a snapshot named {}", "ERROR".red(), file_path_string, new_snapshot_name.cyan());
+        return Err(RenameError);
+    }
+
+    managed_file.rename_snapshot(snapshot_name, new_snapshot_name)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate chrono;
+
+    use std::collections::BTreeMap;
+    use std::fs::write;
+
+    use journaling::managed_file_journal::ManagedFileJournal;
+    use journaling::snapshot_set::{SetSnapshot, SnapshotSet};
+    use snap::snap;
+    use snap::tests::snap_options;
+    use utils::test_dir::TestDir;
+
+    use super::rename;
+    use self::chrono::prelude::*;
+
+    #[test]
+    fn renamed_snapshot_keeps_its_contents_and_references() {
+        let test_dir = TestDir::new("rename");
+        let file_path = test_dir.join("app.conf");
+
+        write(&file_path, "first\n").unwrap();
+        let mut journal = test_dir.open_store();
+        assert!(snap(&mut journal, snap_options("v1"), vec![file_path.to_owned()]).is_ok());
+        write(&file_path, "second\n").unwrap();
+        assert!(snap(&mut journal, snap_options("v2"), vec![file_path.to_owned()]).is_ok());
+
+        let mut snapshots = BTreeMap::new();
+        snapshots.insert(String::from("release"), SetSnapshot {
+            created_time: Utc::now(),
+            members: vec![(file_path.to_owned(), String::from("v2"))].into_iter().collect()
+        });
+        journal.add_set(SnapshotSet {
+            name: String::from("service"), members: vec![file_path.to_owned()], snapshots, hooks: BTreeMap::new()
+        });
+
+        let renamed = |journal: &mut _, from: &str, to: &str| {
+            rename(journal, from.to_owned(), to.to_owned(), file_path.to_owned()).is_ok()
+        };
+
+        assert!(renamed(&mut journal, "v2", "stable"));
+        assert!(!renamed(&mut journal, "v1", "stable"));
+        assert!(!renamed(&mut journal, "v3", "other"));
+        assert!(!renamed(&mut journal, "(null)", "other"));
+        assert!(!renamed(&mut journal, "v1", "(live)"));
+
+        assert_eq!(journal.get_set("service").unwrap().snapshots["release"].members[&file_path], "stable");
+
+        let managed_file_journal = ManagedFileJournal::for_journal(&mut journal);
+        let managed_file = managed_file_journal.get_managed_file(&file_path).ok().unwrap().unwrap();
+        let files = managed_file.read_snapshot_files_by_name("stable").ok().unwrap().unwrap();
+
+        assert_eq!(files[""], b"second\n".to_vec());
+        assert!(managed_file.get_snapshot("v2").is_none());
+        assert!(managed_file.get_snapshot("v1").is_some());
+        assert_eq!(managed_file.get_current_snapshot_name(), Some("stable"));
+    }
+}
@@ -0,0 +1,94 @@
+extern crate colored;
+
+use std::path::PathBuf;
+
+use journaling::journal::Journal;
+use journaling::managed_file_journal::{ManagedFileJournal, ManagedFileJournalError};
+use journaling::managed_file::ManagedFileError;
+use journaling::retention::{RetentionPolicy, RETENTION_POLICY_KEY};
+
+use self::colored::*;
+
+pub struct RetentionError;
+
+impl From<ManagedFileJournalError> for RetentionError {
+    fn from(_: ManagedFileJournalError) -> Self { RetentionError }
+}
+
+impl From<ManagedFileError> for RetentionError {
+    fn from(_: ManagedFileError) -> Self { RetentionError }
+}
+
+pub enum RetentionChange {
+    Show,
+    Set(String),
+    Clear
+}
+
+/// Shows or changes the retention policy of a file, or the global one (which applies to every
+/// file without a policy of its own) if no file is given.
+pub fn retention(journal: &mut Journal, change: RetentionChange, file_path: Option<PathBuf>) -> Result<(), RetentionError> {
+    let policy = match change {
+        RetentionChange::Set(ref spec) => match spec.parse::<RetentionPolicy>() {
+            Ok(policy) => Some(policy),
+            Err(e) => {
+                println!("{} {}", "ERROR".red(), e);
+                return Err(RetentionError);
+            }
+        },
+        _ => None
+    };
+
+    let file_path = match file_path {
+        Some(file_path) => file_path,
+        None => {
+            match (change, policy) {
+                (RetentionChange::Set(_), Some(policy)) => journal.set_config(RETENTION_POLICY_KEY, &policy.to_string()),
+                (RetentionChange::Clear, _) => journal.remove_config(RETENTION_POLICY_KEY),
+                _ => {}
+            }
+
+            journal.commit().map_err(|e| {
+                println!("{} Could not write the configuration: {}", "ERROR".red(), e);
+                RetentionError
+            })?;
+
+            match journal.get_config(RETENTION_POLICY_KEY) {
+                Some(spec) => println!("{} Global retention policy: {}", "OK".green(), spec.cyan()),
+                None => println!("{} No global retention policy, files are only pruned if they have one of \
+                                  their own", "OK".green())
+            }
+
+            return Ok(());
+        }
+    };
+
+    let global_spec = journal.get_config(RETENTION_POLICY_KEY).map(String::from);
+    let managed_file_journal = ManagedFileJournal::for_journal(journal);
+    let file_path_string = file_path.to_string_lossy().into_owned();
+
+    let mut managed_file = match managed_file_journal.get_managed_file(&file_path)? {
+        Some(managed_file) => managed_file,
+        None => {
+            println!("{} {}, file not managed by {}", "ERROR".red(), file_path_string, "hippo".magenta());
+            return Err(RetentionError);
+        }
+    };
+
+    match (change, policy) {
+        (RetentionChange::Set(_), Some(policy)) => managed_file.set_meta(RETENTION_POLICY_KEY, &policy.to_string()),
+        (RetentionChange::Clear, _) => managed_file.remove_meta(RETENTION_POLICY_KEY),
+        _ => {}
+    }
+
+    managed_file.commit()?;
+
+    match (managed_file.get_meta(RETENTION_POLICY_KEY), global_spec) {
+        (Some(spec), _) => println!("{} {}, retention policy: {}", "OK".green(), file_path_string, spec.cyan()),
+        (None, Some(spec)) => println!("{} {}, retention policy: {} (the global policy)", "OK".green(),
+                                       file_path_string, spec.cyan()),
+        (None, None) => println!("{} {} has no retention policy, and is never pruned", "OK".green(), file_path_string)
+    }
+
+    Ok(())
+}
@@ -0,0 +1,147 @@
+extern crate colored;
+extern crate pretty_bytes;
+
+use std::io;
+use std::path::PathBuf;
+
+use journaling::journal::Journal;
+use journaling::managed_file_journal::{ManagedFileJournal, ManagedFileJournalError};
+use journaling::managed_file::{ManagedFileError, LIVE_SNAPSHOT_NAME, NULL_SNAPSHOT_NAME};
+use journaling::snapshot_set::SetReference;
+
+use self::colored::*;
+use self::pretty_bytes::converter::convert;
+
+pub struct RmError;
+
+impl From<ManagedFileJournalError> for RmError {
+    fn from(_: ManagedFileJournalError) -> Self { RmError }
+}
+
+impl From<ManagedFileError> for RmError {
+    fn from(_: ManagedFileError) -> Self { RmError }
+}
+
+impl From<io::Error> for RmError {
+    fn from(_: io::Error) -> Self { RmError }
+}
+
+/// Removes snapshots of a file from its journal, along with their payloads unless some other
+/// snapshot (of this or any other file) has the same content. Nothing is removed unless every
+/// one of the given snapshots exists. Snapshots that are part of a snapshot of a set are only
+/// removed with `force`, which removes the snapshots of the sets along with them.
+pub fn rm(journal: &mut Journal, snapshot_names: Vec<String>, file_path: PathBuf, force: bool) -> Result<(), RmError> {
+    let file_path_string = file_path.to_owned().into_os_string().into_string()
+        .expect("Could not read the file path, it might contain invalid characters");
+
+    let set_references: Vec<SetReference> = journal.set_references(&file_path).into_iter()
+        .filter(|set_reference| snapshot_names.contains(&set_reference.snapshot_name))
+        .collect();
+
+    if !force && !set_references.is_empty() {
+        for set_reference in &set_references {
+            println!("{} {}, snapshot {} is part of snapshot {} of set {}", "ERROR".red(), file_path_string,
+                     set_reference.snapshot_name.cyan(), set_reference.set_snapshot_name.cyan(),
+                     set_reference.set_name.cyan());
+        }
+
+        println!("{} Use --force to remove the snapshots of the sets along with them", "ERROR".red());
+        return Err(RmError);
+    }
+
+    let mut removed_payloads = Vec::new();
+
+    {
+        let managed_file_journal = ManagedFileJournal::for_journal(journal);
+        let mut managed_file = match managed_file_journal.get_managed_file(&file_path)? {
+            Some(managed_file) => managed_file,
+            None => {
+                println!("{} {}, file not managed by {}", "ERROR".red(), file_path_string, "hippo".magenta());
+                return Err(RmError);
+            }
+        };
+
+        for snapshot_name in &snapshot_names {
+            if snapshot_name == NULL_SNAPSHOT_NAME || snapshot_name == LIVE_SNAPSHOT_NAME {
+                println!("{} {}, {} is a synthetic snapshot and cannot be removed", "ERROR".red(),
+                         file_path_string, snapshot_name.cyan());
+                return Err(RmError);
+            }
+
+            if managed_file.get_snapshot(snapshot_name).is_none() {
+                println!("{} {}, no snapshot named {}", "ERROR".red(), file_path_string, snapshot_name.cyan());
+                return Err(RmError);
+            }
+        }
+
+        for snapshot_name in &snapshot_names {
+            if let Some(snapshot_entry) = managed_file.remove_snapshot(snapshot_name) {
+                removed_payloads.extend(managed_file.payload_paths(&snapshot_entry));
+            }
+        }
+
+        managed_file.commit()?;
+    }
+
+    for set_reference in &set_references {
+        journal.remove_set_snapshot(&set_reference.set_name, &set_reference.set_snapshot_name);
+    }
+
+    journal.commit().map_err(|e| {
+        println!("{} Could not write the sets: {}", "ERROR".red(), e);
+        e
+    })?;
+
+    // Payloads are shared between snapshots with identical content, so they can only go once
+    // nothing refers to them anymore
+    let reclaimed = ManagedFileJournal::for_journal(journal).remove_unreferenced_payloads(removed_payloads)?;
+
+    for snapshot_name in &snapshot_names {
+        println!("{} Removed snapshot {} of {}", "OK".green(), snapshot_name.cyan(), file_path_string.bold());
+    }
+
+    for set_reference in &set_references {
+        println!("{} Removed snapshot {} of set {}", "OK".green(), set_reference.set_snapshot_name.cyan(),
+                 set_reference.set_name.cyan());
+    }
+
+    println!("{} Removed {} snapshots (Reclaimed: {})", "OK".green().bold(), snapshot_names.len(),
+             convert(reclaimed as f64));
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::write;
+
+    use snap::snap;
+    use snap::tests::snap_options;
+    use utils::test_dir::TestDir;
+
+    use super::rm;
+
+    #[test]
+    fn payloads_go_once_no_snapshot_refers_to_them() {
+        let test_dir = TestDir::new("rm");
+        let file_path = test_dir.join("app.conf");
+        let mut journal = test_dir.open_store();
+
+        for &(snapshot_name, content) in &[("v1", "first\n"), ("v2", "second\n"), ("v3", "first\n")] {
+            write(&file_path, content).unwrap();
+            assert!(snap(&mut journal, snap_options(snapshot_name), vec![file_path.to_owned()]).is_ok());
+        }
+
+        assert_eq!(journal.object_store().files().unwrap().len(), 2);
+
+        assert!(rm(&mut journal, vec![String::from("v1")], file_path.to_owned(), false).is_ok());
+        assert_eq!(journal.object_store().files().unwrap().len(), 2);
+
+        assert!(rm(&mut journal, vec![String::from("v2"), String::from("v3")], file_path.to_owned(), false).is_ok());
+        assert!(journal.object_store().files().unwrap().is_empty());
+
+        for snapshot_name in &["v1", "(null)", "(live)"] {
+            assert!(rm(&mut journal, vec![snapshot_name.to_string()], file_path.to_owned(), false).is_err());
+        }
+    }
+}
@@ -0,0 +1,109 @@
+extern crate chrono;
+extern crate colored;
+
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+use journaling::journal::Journal;
+use journaling::snapshot_set::{validate_set_name, SnapshotSet};
+
+use self::chrono::prelude::*;
+use self::colored::*;
+
+static SET_SNAPSHOT_TIME_FORMAT: &str = "%a %b %e %T %Y";
+
+pub struct SetError;
+
+pub enum SetCommand {
+    Create { set_name: String, members: Vec<PathBuf> },
+    List,
+    Remove(String)
+}
+
+/// Creates, lists or removes sets of files that are snapped and loaded together. Removing a set
+/// leaves the snapshots of its members alone.
+pub fn set(journal: &mut Journal, command: SetCommand) -> Result<(), SetError> {
+    match command {
+        SetCommand::Create { set_name, members } => {
+            if let Err(e) = validate_set_name(&set_name) {
+                println!("{} {}", "ERROR".red(), e);
+                return Err(SetError);
+            }
+
+            if journal.get_set(&set_name).is_some() {
+                println!("{} A set named {} already exists", "ERROR".red(), set_name.cyan());
+                return Err(SetError);
+            }
+
+            let mut unique_members: Vec<PathBuf> = Vec::new();
+
+            for member in members {
+                if !unique_members.contains(&member) {
+                    unique_members.push(member);
+                }
+            }
+
+            let member_count = unique_members.len();
+
+            journal.add_set(SnapshotSet {
+                name: set_name.to_owned(),
+                members: unique_members,
+                snapshots: BTreeMap::new(),
+                hooks: BTreeMap::new()
+            });
+            commit(journal)?;
+
+            println!("{} Created set {} with {} members", "OK".green().bold(), set_name.cyan(), member_count);
+        }
+
+        SetCommand::List => {
+            let mut snapshot_sets: Vec<&SnapshotSet> = journal.sets.records.values().collect();
+            snapshot_sets.sort_by_key(|snapshot_set| snapshot_set.name.to_owned());
+
+            if snapshot_sets.is_empty() {
+                println!("{} No sets have been created", "OK".green());
+            }
+
+            for (index, snapshot_set) in snapshot_sets.into_iter().enumerate() {
+                if index > 0 { println!(); }
+
+                println!("{} {}, {} members, {} snapshots", "OK".green(), snapshot_set.name.cyan(),
+                         snapshot_set.members.len(), snapshot_set.snapshots.len());
+
+                for member in &snapshot_set.members {
+                    println!("  {}", member.to_string_lossy());
+                }
+
+                let mut set_snapshots: Vec<(&String, &DateTime<Utc>)> = snapshot_set.snapshots.iter()
+                    .map(|(snapshot_name, set_snapshot)| (snapshot_name, &set_snapshot.created_time))
+                    .collect();
+                set_snapshots.sort_by_key(|&(_, created_time)| *created_time);
+
+                for (snapshot_name, created_time) in set_snapshots {
+                    println!("  {} {}", snapshot_name.cyan(),
+                             created_time.with_timezone(&Local).format(SET_SNAPSHOT_TIME_FORMAT));
+                }
+            }
+        }
+
+        SetCommand::Remove(set_name) => {
+            if journal.remove_set(&set_name).is_none() {
+                println!("{} No set named {}", "ERROR".red(), set_name.cyan());
+                return Err(SetError);
+            }
+
+            commit(journal)?;
+
+            println!("{} Removed set {}, the snapshots of its members were kept", "OK".green().bold(), set_name.cyan());
+        }
+    }
+
+    Ok(())
+}
+
+fn commit(journal: &mut Journal) -> Result<(), SetError> {
+    journal.commit().map_err(|e| {
+        println!("{} Could not write the sets: {}", "ERROR".red(), e);
+        SetError
+    })
+}
@@ -1,7 +1,19 @@
+extern crate chrono;
+extern crate colored;
+
+use std::iter;
 use std::path::PathBuf;
+use journaling::hooks::Hook;
 use journaling::journal::Journal;
 use journaling::managed_file_journal::{ManagedFileJournal, ManagedFileJournalError};
-use journaling::managed_file::ManagedFileError;
+use journaling::managed_file::{validate_snapshot_name, ManagedFileError, SnapshotEntry, SnapshotLabels, SymlinkPolicy,
+                               AUTO_SNAPSHOT_NAME_FORMAT};
+use journaling::snapshot_set::{SetSnapshot, SnapshotSet};
+use journaling::tree::TreeFilter;
+use utils::compression::Codec;
+
+use self::chrono::prelude::*;
+use self::colored::*;
 
 pub struct SnapError;
 
@@ -13,17 +25,342 @@ impl From<ManagedFileJournalError> for SnapError {
     fn from(_: ManagedFileJournalError) -> Self { SnapError }
 }
 
-pub fn snap(
-    journal: &mut Journal, name: Option<String>, comment: Option<String>, file_paths: Vec<PathBuf>
+/// How new snapshots are taken, shared by all the files being snapped
+pub struct SnapOptions {
+    pub name: Option<String>,
+    pub comment: Option<String>,
+    pub codec: Codec,
+    pub labels: SnapshotLabels,
+    pub force: bool,
+    /// Glob patterns for the files of a directory to snapshot. If neither is given, directories
+    /// keep the patterns they were last snapped with.
+    pub include: Vec<String>,
+    pub exclude: Vec<String>,
+    /// How paths that are symlinks are treated. If not given, files keep the policy they were
+    /// last snapped with.
+    pub symlink_policy: Option<SymlinkPolicy>
+}
+
+/// A snapshot that was taken of one of the files, along with what it takes to undo it
+struct TakenSnapshot {
+    file_path: PathBuf,
+    snapshot_name: String,
+    replaced_entry: Option<SnapshotEntry>,
+    previous_snapshot_name: Option<String>
+}
+
+pub fn snap(journal: &mut Journal, options: SnapOptions, file_paths: Vec<PathBuf>) -> Result<(), SnapError> {
+    snap_files(journal, options, file_paths, false)
+}
+
+/// Snapshots each of the files in turn. With `all_or_nothing`, the snapshots already taken are
+/// undone if snapping any of the files fails.
+fn snap_files(
+    journal: &mut Journal, options: SnapOptions, file_paths: Vec<PathBuf>, all_or_nothing: bool
 ) -> Result<(), SnapError> {
+    let SnapOptions { name, comment, codec, labels, force, include, exclude, symlink_policy } = options;
     let mut managed_file_journal = ManagedFileJournal::for_journal(journal);
+    let tree_filter_given = !include.is_empty() || !exclude.is_empty();
+
+    if tree_filter_given {
+        if let Err(e) = TreeFilter::new(&include, &exclude) {
+            println!("{} {}", "ERROR".red(), e);
+            return Err(SnapError);
+        }
+
+        if let Some(file_path) = file_paths.iter().find(|file_path| !file_path.is_dir()) {
+            println!("{} {} is not a directory, --include and --exclude only apply to directories", "ERROR".red(),
+                     file_path.to_string_lossy());
+            return Err(SnapError);
+        }
+    }
 
-    for ref file_path in file_paths {
+    if let Some(ref name) = name {
+        if let Err(e) = validate_snapshot_name(name) {
+            println!("{} {}", "ERROR".red(), e);
+            return Err(SnapError);
+        }
+
+        // Checked for all files upfront, so that a collision does not leave only some of them snapped
+        if !force {
+            for file_path in file_paths.iter() {
+                if let Some(managed_file) = managed_file_journal.get_managed_file(file_path)? {
+                    if managed_file.get_snapshot(name).is_some() {
+                        println!("{} {} already has a snapshot named {}, use --force to replace it", "ERROR".red(),
+                                 file_path.to_string_lossy(), name.cyan());
+                        return Err(SnapError);
+                    }
+                }
+            }
+        }
+    }
+
+    let mut taken_snapshots = Vec::new();
+
+    for file_path in file_paths.iter() {
         println!("Creating snapshot for {}", file_path.to_owned().into_os_string().into_string().unwrap());
 
-        let mut managed_file = managed_file_journal.create_or_get_managed_file(file_path)?;
-        managed_file.snap_current_state(name.to_owned(), comment.to_owned(), None)?;
+        let taken_snapshot = (|| {
+            let mut managed_file = managed_file_journal.create_or_get_managed_file(file_path)?;
+
+            if tree_filter_given {
+                managed_file.set_tree_filter(&include, &exclude).map_err(|_| SnapError)?;
+            }
+
+            // Recorded for every symlink, so that the policy in effect is never implicit
+            let is_symlink = file_path.symlink_metadata().map(|m| m.file_type().is_symlink()).unwrap_or(false);
+
+            if symlink_policy.is_some() || is_symlink {
+                let symlink_policy = symlink_policy.unwrap_or_else(|| managed_file.symlink_policy());
+                managed_file.set_symlink_policy(symlink_policy);
+            }
+
+            let previous_snapshot_name = managed_file.get_current_snapshot_name().map(String::from);
+            let replaced_entry = managed_file.snap_current_state(
+                name.to_owned(), comment.to_owned(), None, codec, &labels, force
+            )?;
+
+            Ok(TakenSnapshot {
+                file_path: file_path.to_owned(),
+                snapshot_name: managed_file.get_current_snapshot_name().unwrap_or_default().to_owned(),
+                replaced_entry,
+                previous_snapshot_name
+            })
+        })();
+
+        match taken_snapshot {
+            Ok(taken_snapshot) => taken_snapshots.push(taken_snapshot),
+
+            Err(e) => {
+                if all_or_nothing {
+                    undo_snapshots(&managed_file_journal, taken_snapshots)?;
+                }
+
+                return Err(e);
+            }
+        }
+    }
+
+    let replaced_payloads: Vec<PathBuf> = {
+        let mut replaced_payloads = Vec::new();
+
+        for taken_snapshot in &taken_snapshots {
+            if let Some(ref replaced_entry) = taken_snapshot.replaced_entry {
+                if let Some(managed_file) = managed_file_journal.get_managed_file(&taken_snapshot.file_path)? {
+                    replaced_payloads.extend(managed_file.payload_paths(replaced_entry));
+                }
+            }
+        }
+
+        replaced_payloads
+    };
+
+    if !replaced_payloads.is_empty() {
+        managed_file_journal.remove_unreferenced_payloads(replaced_payloads)?;
+    }
+
+    Ok(())
+}
+
+/// Undoes snapshots that were taken, newest first, putting back any snapshots they replaced
+fn undo_snapshots(managed_file_journal: &ManagedFileJournal, taken_snapshots: Vec<TakenSnapshot>) -> Result<(), SnapError> {
+    let mut undone_payloads = Vec::new();
+
+    for taken_snapshot in taken_snapshots.into_iter().rev() {
+        let file_path_string = taken_snapshot.file_path.to_string_lossy().into_owned();
+        let mut managed_file = match managed_file_journal.get_managed_file(&taken_snapshot.file_path)? {
+            Some(managed_file) => managed_file,
+            None => continue
+        };
+
+        let undone_entry = managed_file.undo_snap(&taken_snapshot.snapshot_name, taken_snapshot.replaced_entry,
+                                                  taken_snapshot.previous_snapshot_name.as_deref())?;
+
+        if let Some(undone_entry) = undone_entry {
+            undone_payloads.extend(managed_file.payload_paths(&undone_entry));
+        }
+
+        println!("{} Took back snapshot {} of {}", "WARN".yellow(), taken_snapshot.snapshot_name.cyan(),
+                 file_path_string);
+    }
+
+    managed_file_journal.remove_unreferenced_payloads(undone_payloads)?;
+
+    Ok(())
+}
+
+/// Snapshots every member of a set under the same name, and records the set snapshot referring
+/// to them. Names are generated once for the whole set, so the snapshots of all members are
+/// named alike (and, since the name is not generated per file, retention treats them as named).
+/// If snapping any of the members fails, the members that were already snapped are put back
+/// the way they were.
+pub fn snap_set(journal: &mut Journal, set_name: &str, options: SnapOptions) -> Result<(), SnapError> {
+    let mut snapshot_set = match journal.get_set(set_name) {
+        Some(snapshot_set) => snapshot_set.clone(),
+        None => {
+            println!("{} No set named {}", "ERROR".red(), set_name.cyan());
+            return Err(SnapError);
+        }
+    };
+
+    let created_time = Utc::now();
+    let snapshot_name = match options.name {
+        Some(ref snapshot_name) => {
+            if !options.force && snapshot_set.snapshots.contains_key(snapshot_name) {
+                println!("{} Set {} already has a snapshot named {}, use --force to replace it", "ERROR".red(),
+                         set_name.cyan(), snapshot_name.cyan());
+                return Err(SnapError);
+            }
+
+            snapshot_name.to_owned()
+        }
+
+        None => unique_set_snapshot_name(journal, &snapshot_set,
+                                         created_time.with_timezone(&Local).format(AUTO_SNAPSHOT_NAME_FORMAT).to_string())?
+    };
+
+    if snapshot_set.run_hook(Hook::PreSnap, &snapshot_name, &[]).is_err() {
+        return Err(SnapError);
     }
 
+    let options = SnapOptions { name: Some(snapshot_name.to_owned()), ..options };
+
+    if snap_files(journal, options, snapshot_set.members.to_owned(), true).is_err() {
+        println!("{} Could not snap set {}", "ERROR".red(), set_name.cyan());
+        return Err(SnapError);
+    }
+
+    let member_snapshots = snapshot_set.members.iter()
+        .map(|member| (member.to_owned(), snapshot_name.to_owned()))
+        .collect();
+
+    snapshot_set.snapshots.insert(snapshot_name.to_owned(), SetSnapshot { created_time, members: member_snapshots });
+    journal.add_set(snapshot_set);
+
+    journal.commit().map_err(|e| {
+        println!("{} Could not write the sets: {}", "ERROR".red(), e);
+        SnapError
+    })?;
+
+    println!("{} Created snapshot {} of set {}", "OK".green().bold(), snapshot_name.cyan(), set_name.cyan());
+
     Ok(())
 }
+
+/// Adds a counter to the given name if the set, or any of its members, already has a snapshot
+/// by that name
+fn unique_set_snapshot_name(
+    journal: &mut Journal, snapshot_set: &SnapshotSet, snapshot_name: String
+) -> Result<String, SnapError> {
+    let managed_file_journal = ManagedFileJournal::for_journal(journal);
+    let mut taken_names: Vec<String> = snapshot_set.snapshots.keys().cloned().collect();
+
+    for member in &snapshot_set.members {
+        if let Some(managed_file) = managed_file_journal.get_managed_file(member)? {
+            taken_names.extend(managed_file.snapshot_entries().iter().map(|entry| entry.snapshot_name.to_owned()));
+        }
+    }
+
+    Ok(iter::once(snapshot_name.to_owned())
+        .chain((1..).map(|counter| format!("{}.{}", snapshot_name, counter)))
+        .find(|candidate| !taken_names.contains(candidate))
+        .unwrap())
+}
+
+#[cfg(test)]
+pub mod tests {
+    use std::collections::BTreeMap;
+    use std::fs::write;
+    use std::path::PathBuf;
+
+    use journaling::hooks::Hook;
+    use journaling::journal::Journal;
+    use journaling::managed_file_journal::ManagedFileJournal;
+    use journaling::snapshot_set::SnapshotSet;
+    use utils::compression::Codec;
+    use utils::test_dir::TestDir;
+
+    use super::{snap, snap_set, SnapOptions};
+
+    /// Options for an uncompressed snapshot with the given name and nothing else set
+    pub fn snap_options(name: &str) -> SnapOptions {
+        SnapOptions {
+            name: Some(String::from(name)),
+            comment: None,
+            codec: Codec::None,
+            labels: Default::default(),
+            force: false,
+            include: Vec::new(),
+            exclude: Vec::new(),
+            symlink_policy: None
+        }
+    }
+
+    /// Number of snapshots of the file, and the contents of its snapshot `v1`
+    fn snapshot_content(journal: &mut Journal, file_path: &PathBuf) -> (usize, Vec<u8>) {
+        let managed_file_journal = ManagedFileJournal::for_journal(journal);
+        let managed_file = managed_file_journal.get_managed_file(file_path).ok().unwrap().unwrap();
+        let files = managed_file.read_snapshot_files_by_name("v1").ok().unwrap().unwrap();
+
+        (managed_file.snapshot_entries().len(), files[""].to_owned())
+    }
+
+    #[test]
+    fn snapping_under_a_taken_name_needs_force() {
+        let test_dir = TestDir::new("snap");
+        let file_path = test_dir.join("app.conf");
+        write(&file_path, "first\n").unwrap();
+        let mut journal = test_dir.open_store();
+        assert!(snap(&mut journal, snap_options("v1"), vec![file_path.to_owned()]).is_ok());
+
+        write(&file_path, "second\n").unwrap();
+        assert!(snap(&mut journal, snap_options("v1"), vec![file_path.to_owned()]).is_err());
+        assert_eq!(snapshot_content(&mut journal, &file_path), (1, b"first\n".to_vec()));
+
+        let options = SnapOptions { force: true, ..snap_options("v1") };
+        assert!(snap(&mut journal, options, vec![file_path.to_owned()]).is_ok());
+        assert_eq!(snapshot_content(&mut journal, &file_path), (1, b"second\n".to_vec()));
+
+        // Names reserved for synthetic snapshots are refused before anything is snapped
+        assert!(snap(&mut journal, snap_options("(live)"), vec![file_path.to_owned()]).is_err());
+        assert_eq!(snapshot_content(&mut journal, &file_path).0, 1);
+    }
+
+    #[test]
+    fn failed_set_snap_takes_back_the_members_already_snapped() {
+        let test_dir = TestDir::new("snap");
+        let first_member = test_dir.join("first.conf");
+        let second_member = test_dir.join("second.conf");
+
+        write(&first_member, "first\n").unwrap();
+        write(&second_member, "second\n").unwrap();
+
+        let mut journal = test_dir.open_store();
+
+        {
+            let mut managed_file_journal = ManagedFileJournal::for_journal(&mut journal);
+            let mut managed_file = managed_file_journal.create_or_get_managed_file(&second_member).ok().unwrap();
+            managed_file.set_hook(Hook::PreSnap, Some("false"));
+            assert!(managed_file.commit().is_ok());
+        }
+
+        journal.add_set(SnapshotSet {
+            name: String::from("service"),
+            members: vec![first_member.to_owned(), second_member.to_owned()],
+            snapshots: BTreeMap::new(),
+            hooks: BTreeMap::new()
+        });
+
+        assert!(snap_set(&mut journal, "service", snap_options("v1")).is_err());
+        assert!(journal.get_set("service").unwrap().snapshots.is_empty());
+
+        {
+            let managed_file_journal = ManagedFileJournal::for_journal(&mut journal);
+            let managed_file = managed_file_journal.get_managed_file(&first_member).ok().unwrap().unwrap();
+            assert!(managed_file.snapshot_entries().is_empty());
+            assert_eq!(managed_file.get_current_snapshot_name(), None);
+        }
+
+        assert!(journal.object_store().files().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,78 @@
+extern crate flate2;
+
+use std::io;
+use std::io::{Read, Write};
+
+use self::flate2::Compression;
+use self::flate2::read::GzDecoder;
+use self::flate2::write::GzEncoder;
+
+/// The codec a stored payload is encoded with. Payloads stored before compression was
+/// supported carry no codec and are read as `None`.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug, Default)]
+pub enum Codec {
+    #[default]
+    None,
+    Gzip
+}
+
+impl Codec {
+    pub fn all() -> Vec<Codec> {
+        vec![Codec::None, Codec::Gzip]
+    }
+
+    /// Suffix appended to the name of payloads stored with this codec
+    pub fn extension(&self) -> &'static str {
+        match *self {
+            Codec::None => "",
+            Codec::Gzip => ".gz"
+        }
+    }
+
+    pub fn encode(&self, content: &[u8]) -> io::Result<Vec<u8>> {
+        match *self {
+            Codec::None => Ok(content.to_vec()),
+            Codec::Gzip => {
+                let mut encoder = GzEncoder::new(Vec::new(), Compression::best());
+                encoder.write_all(content)?;
+                encoder.finish()
+            }
+        }
+    }
+
+    pub fn decode(&self, payload: &[u8]) -> io::Result<Vec<u8>> {
+        match *self {
+            Codec::None => Ok(payload.to_vec()),
+            Codec::Gzip => {
+                let mut content = Vec::new();
+                GzDecoder::new(payload).read_to_end(&mut content)?;
+                Ok(content)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Codec;
+
+    #[test]
+    fn every_codec_round_trips() {
+        let repetitive: Vec<u8> = b"key = value\n".iter().cycle().take(64 * 1024).cloned().collect();
+        let binary: Vec<u8> = (0..4096).map(|i| (i * 31 % 256) as u8).collect();
+
+        for codec in Codec::all() {
+            for content in &[Vec::new(), b"x".to_vec(), repetitive.to_owned(), binary.to_owned()] {
+                assert_eq!(&codec.decode(&codec.encode(content).unwrap()).unwrap(), content);
+            }
+        }
+    }
+
+    #[test]
+    fn gzip_compresses_and_rejects_garbage() {
+        let repetitive: Vec<u8> = b"key = value\n".iter().cycle().take(64 * 1024).cloned().collect();
+
+        assert!(Codec::Gzip.encode(&repetitive).unwrap().len() < repetitive.len() / 10);
+        assert!(Codec::Gzip.decode(b"not gzip at all").is_err());
+    }
+}
@@ -0,0 +1,89 @@
+extern crate chrono;
+extern crate users;
+
+use std::fs::{metadata, set_permissions, File, Permissions};
+use std::io;
+use std::os::unix::fs::{chown, MetadataExt, PermissionsExt};
+use std::path::Path;
+use std::time::SystemTime;
+
+use self::chrono::prelude::*;
+use self::users::get_effective_uid;
+
+/// Permission bits, ownership and modification time of a file (or directory), as recorded along
+/// with its snapshot
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub struct FileMetadata {
+    pub mode: u32,
+    pub uid: u32,
+    pub gid: u32,
+    pub modified_time: DateTime<Utc>
+}
+
+impl FileMetadata {
+    pub fn read(path: &Path) -> io::Result<FileMetadata> {
+        let file_metadata = metadata(path)?;
+
+        Ok(FileMetadata {
+            mode: file_metadata.mode() & 0o7777,
+            uid: file_metadata.uid(),
+            gid: file_metadata.gid(),
+            modified_time: DateTime::<Utc>::from(file_metadata.modified()?)
+        })
+    }
+
+    /// Whether the permissions and ownership are the same. The modification time is left out,
+    /// since it changes with every edit of the contents.
+    pub fn same_attributes(&self, other: &FileMetadata) -> bool {
+        self.mode == other.mode && self.uid == other.uid && self.gid == other.gid
+    }
+
+    /// Describes what changed from this metadata to the other, one entry per attribute
+    pub fn changes_to(&self, other: &FileMetadata, include_modified_time: bool) -> Vec<String> {
+        let mut changes = Vec::new();
+
+        if self.mode != other.mode {
+            changes.push(format!("mode {:04o} -> {:04o}", self.mode, other.mode));
+        }
+
+        if self.uid != other.uid || self.gid != other.gid {
+            changes.push(format!("owner {}:{} -> {}:{}", self.uid, self.gid, other.uid, other.gid));
+        }
+
+        if include_modified_time && self.modified_time != other.modified_time {
+            changes.push(format!("modified {} -> {}", self.modified_time.to_rfc3339(), other.modified_time.to_rfc3339()));
+        }
+
+        changes
+    }
+
+    /// Applies the recorded metadata to a file. The modification time is set first and the
+    /// permissions last, so that restrictive permissions do not get in the way of the rest.
+    /// Returns `false` if the ownership could not be restored because the process is not
+    /// allowed to change it (usually because it is not running as root).
+    pub fn apply(&self, path: &Path) -> io::Result<bool> {
+        File::open(path)?.set_modified(SystemTime::from(self.modified_time))?;
+
+        let current_metadata = metadata(path)?;
+        let mut ownership_restored = true;
+
+        if current_metadata.uid() != self.uid || current_metadata.gid() != self.gid {
+            match chown(path, Some(self.uid), Some(self.gid)) {
+                Ok(_) => {}
+                Err(ref e) if e.kind() == io::ErrorKind::PermissionDenied => {
+                    info!("Could not change the ownership of {:?} to {}:{}", path, self.uid, self.gid);
+                    ownership_restored = false;
+                }
+                Err(e) => return Err(e)
+            }
+        }
+
+        set_permissions(path, Permissions::from_mode(self.mode))?;
+
+        Ok(ownership_restored)
+    }
+}
+
+pub fn running_as_root() -> bool {
+    get_effective_uid() == 0
+}
@@ -0,0 +1,302 @@
+use std::cmp::min;
+
+#[derive(Debug, PartialEq)]
+pub enum DiffLine<'a> {
+    Context(&'a str),
+    Removed(&'a str),
+    Added(&'a str)
+}
+
+impl<'a> DiffLine<'a> {
+    pub fn line(&self) -> &'a str {
+        match *self {
+            DiffLine::Context(line) | DiffLine::Removed(line) | DiffLine::Added(line) => line
+        }
+    }
+}
+
+/// Splits content into lines that keep their line endings, so that a `\r` before the `\n` or a
+/// missing newline at the end of the content count as changes like any other
+pub fn split_lines(content: &str) -> Vec<&str> {
+    content.split_inclusive('\n').collect()
+}
+
+/// A contiguous group of changes along with the lines of context surrounding them. Line numbers
+/// are 1-based, as in the unified diff format.
+pub struct Hunk<'a> {
+    pub old_start: usize,
+    pub old_len: usize,
+    pub new_start: usize,
+    pub new_len: usize,
+    pub lines: Vec<DiffLine<'a>>
+}
+
+/// Computes a shortest edit script between two sequences of lines using Myers' algorithm,
+/// returned as the full sequence of lines (unchanged ones included) in order. This is the linear
+/// space variant, which splits the sequences where the searches from both ends meet and diffs the
+/// halves on their own, so that memory stays proportional to the length of the sequences.
+pub fn diff_lines<'a>(old: &[&'a str], new: &[&'a str]) -> Vec<DiffLine<'a>> {
+    let mut lines = Vec::with_capacity(old.len() + new.len());
+    diff_range(old, new, &mut lines);
+    lines
+}
+
+fn diff_range<'a>(old: &[&'a str], new: &[&'a str], lines: &mut Vec<DiffLine<'a>>) {
+    let prefix_len = old.iter().zip(new.iter()).take_while(|&(old_line, new_line)| old_line == new_line).count();
+    let suffix_len = old[prefix_len..].iter().rev()
+        .zip(new[prefix_len..].iter().rev())
+        .take_while(|&(old_line, new_line)| old_line == new_line)
+        .count();
+
+    let old_middle = &old[prefix_len..old.len() - suffix_len];
+    let new_middle = &new[prefix_len..new.len() - suffix_len];
+
+    lines.extend(old[..prefix_len].iter().map(|&line| DiffLine::Context(line)));
+
+    if old_middle.is_empty() {
+        lines.extend(new_middle.iter().map(|&line| DiffLine::Added(line)));
+    } else if new_middle.is_empty() {
+        lines.extend(old_middle.iter().map(|&line| DiffLine::Removed(line)));
+    } else {
+        match split_point(old_middle, new_middle) {
+            Some((x, y)) => {
+                diff_range(&old_middle[..x], &new_middle[..y], lines);
+                diff_range(&old_middle[x..], &new_middle[y..], lines);
+            }
+
+            None => {
+                lines.extend(old_middle.iter().map(|&line| DiffLine::Removed(line)));
+                lines.extend(new_middle.iter().map(|&line| DiffLine::Added(line)));
+            }
+        }
+    }
+
+    lines.extend(old[old.len() - suffix_len..].iter().map(|&line| DiffLine::Context(line)));
+}
+
+/// Searches for a shortest edit script from both ends at once, keeping only the furthest point
+/// reached on every diagonal. Returns the point at which the two searches meet, which lies on a
+/// shortest edit script and is neither the start nor the end of it.
+fn split_point(old: &[&str], new: &[&str]) -> Option<(usize, usize)> {
+    let n = old.len() as isize;
+    let m = new.len() as isize;
+    let max_d = (n + m + 1) / 2;
+    let offset = max_d;
+    let v_len = 2 * max_d + 2;
+    let delta = n - m;
+    // Which of the searches can be the first to meet the other depends on the parity of delta
+    let forward_meets = delta % 2 != 0;
+
+    let mut forward = vec![-1isize; v_len as usize];
+    let mut backward = vec![-1isize; v_len as usize];
+    forward[(offset + 1) as usize] = 0;
+    backward[(offset + 1) as usize] = 0;
+
+    // Diagonals that have run off the edges are skipped from then on
+    let (mut forward_start, mut forward_end) = (0, 0);
+    let (mut backward_start, mut backward_end) = (0, 0);
+
+    for d in 0..max_d {
+        let mut k = -d + forward_start;
+        while k <= d - forward_end {
+            let index = (offset + k) as usize;
+            let mut x = if k == -d || (k != d && forward[index - 1] < forward[index + 1]) {
+                forward[index + 1]
+            } else {
+                forward[index - 1] + 1
+            };
+            let mut y = x - k;
+
+            while x < n && y < m && old[x as usize] == new[y as usize] {
+                x += 1;
+                y += 1;
+            }
+
+            forward[index] = x;
+
+            if x > n {
+                forward_end += 2;
+            } else if y > m {
+                forward_start += 2;
+            } else if forward_meets {
+                let backward_index = offset + delta - k;
+
+                if backward_index >= 0 && backward_index < v_len && backward[backward_index as usize] != -1
+                    && x >= n - backward[backward_index as usize] {
+                    return Some((x as usize, y as usize));
+                }
+            }
+
+            k += 2;
+        }
+
+        let mut k = -d + backward_start;
+        while k <= d - backward_end {
+            let index = (offset + k) as usize;
+            let mut x = if k == -d || (k != d && backward[index - 1] < backward[index + 1]) {
+                backward[index + 1]
+            } else {
+                backward[index - 1] + 1
+            };
+            let mut y = x - k;
+
+            while x < n && y < m && old[(n - x - 1) as usize] == new[(m - y - 1) as usize] {
+                x += 1;
+                y += 1;
+            }
+
+            backward[index] = x;
+
+            if x > n {
+                backward_end += 2;
+            } else if y > m {
+                backward_start += 2;
+            } else if !forward_meets {
+                let forward_index = offset + delta - k;
+
+                if forward_index >= 0 && forward_index < v_len && forward[forward_index as usize] != -1 {
+                    let forward_x = forward[forward_index as usize];
+                    let forward_y = forward_x - (delta - k);
+
+                    if forward_x >= n - x {
+                        return Some((forward_x as usize, forward_y as usize));
+                    }
+                }
+            }
+
+            k += 2;
+        }
+    }
+
+    None
+}
+
+/// Groups the output of `diff_lines` into hunks, keeping `context` unchanged lines around every
+/// change. Changes closer than twice the context are merged into the same hunk.
+pub fn hunks<'a>(lines: Vec<DiffLine<'a>>, context: usize) -> Vec<Hunk<'a>> {
+    let mut positions = Vec::with_capacity(lines.len());
+    let (mut old_position, mut new_position) = (0, 0);
+
+    for line in &lines {
+        positions.push((old_position, new_position));
+
+        match *line {
+            DiffLine::Context(_) => { old_position += 1; new_position += 1; }
+            DiffLine::Removed(_) => { old_position += 1; }
+            DiffLine::Added(_) => { new_position += 1; }
+        }
+    }
+
+    let changes: Vec<usize> = lines.iter()
+        .enumerate()
+        .filter(|&(_, line)| !matches!(*line, DiffLine::Context(_)))
+        .map(|(index, _)| index)
+        .collect();
+
+    let mut ranges = Vec::new();
+    let mut change_index = 0;
+
+    while change_index < changes.len() {
+        let start = changes[change_index].saturating_sub(context);
+        let mut last_change = changes[change_index];
+
+        while change_index + 1 < changes.len() && changes[change_index + 1] - last_change <= 2 * context + 1 {
+            change_index += 1;
+            last_change = changes[change_index];
+        }
+
+        ranges.push((start, min(lines.len(), last_change + context + 1)));
+        change_index += 1;
+    }
+
+    let mut hunks = Vec::new();
+    let mut lines = lines.into_iter().enumerate().peekable();
+
+    for (start, end) in ranges {
+        let mut hunk_lines = Vec::new();
+
+        while let Some(&(index, _)) = lines.peek() {
+            if index >= end { break; }
+
+            let (index, line) = lines.next().unwrap();
+            if index >= start { hunk_lines.push(line); }
+        }
+
+        let old_len = hunk_lines.iter().filter(|line| !matches!(**line, DiffLine::Added(_))).count();
+        let new_len = hunk_lines.iter().filter(|line| !matches!(**line, DiffLine::Removed(_))).count();
+        let (old_position, new_position) = positions[start];
+
+        hunks.push(Hunk {
+            old_start: if old_len == 0 { old_position } else { old_position + 1 },
+            old_len,
+            new_start: if new_len == 0 { new_position } else { new_position + 1 },
+            new_len,
+            lines: hunk_lines
+        });
+    }
+
+    hunks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{diff_lines, DiffLine};
+
+    fn sides<'a>(lines: &[DiffLine<'a>]) -> (Vec<&'a str>, Vec<&'a str>) {
+        let mut old = Vec::new();
+        let mut new = Vec::new();
+
+        for line in lines {
+            match *line {
+                DiffLine::Context(line) => { old.push(line); new.push(line); }
+                DiffLine::Removed(line) => old.push(line),
+                DiffLine::Added(line) => new.push(line)
+            }
+        }
+
+        (old, new)
+    }
+
+    fn edit_distance(old: &[&str], new: &[&str]) -> usize {
+        let mut lcs = vec![vec![0; new.len() + 1]; old.len() + 1];
+
+        for i in 0..old.len() {
+            for j in 0..new.len() {
+                lcs[i + 1][j + 1] = if old[i] == new[j] { lcs[i][j] + 1 } else { lcs[i][j + 1].max(lcs[i + 1][j]) };
+            }
+        }
+
+        old.len() + new.len() - 2 * lcs[old.len()][new.len()]
+    }
+
+    #[test]
+    fn finds_a_shortest_edit_script() {
+        let alphabet = ["a", "b", "c", "d"];
+        let mut seed: u64 = 42;
+        let mut next = || {
+            seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            (seed >> 33) as usize
+        };
+
+        for _ in 0..500 {
+            let old: Vec<&str> = (0..next() % 12).map(|_| alphabet[next() % alphabet.len()]).collect();
+            let new: Vec<&str> = (0..next() % 12).map(|_| alphabet[next() % alphabet.len()]).collect();
+            let lines = diff_lines(&old, &new);
+            let changes = lines.iter().filter(|line| !matches!(**line, DiffLine::Context(_))).count();
+
+            assert_eq!(sides(&lines), (old.to_owned(), new.to_owned()));
+            assert_eq!(changes, edit_distance(&old, &new), "{:?} -> {:?}", old, new);
+        }
+    }
+
+    #[test]
+    fn diffs_long_inputs_against_nothing() {
+        let numbers: Vec<String> = (1..20001).map(|number| number.to_string()).collect();
+        let old: Vec<&str> = numbers.iter().map(|number| number.as_str()).collect();
+
+        let lines = diff_lines(&old, &[]);
+
+        assert_eq!(lines.len(), old.len());
+        assert!(lines.iter().all(|line| matches!(*line, DiffLine::Removed(_))));
+    }
+}
@@ -1 +1,8 @@
+pub mod line_diff;
+pub mod compression;
 pub mod simple_file_records;
+pub mod staged_file;
+pub mod file_metadata;
+pub mod paths;
+#[cfg(test)]
+pub mod test_dir;
@@ -0,0 +1,126 @@
+use std::env;
+use std::fs::{canonicalize, read_link};
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+/// Symlinks are followed at most this many times, as in most kernels
+static MAX_SYMLINK_HOPS: usize = 40;
+
+/// Makes a path absolute, resolving symlinks in its parents like `fs::canonicalize` does. The
+/// last component is not resolved, so that a symlink is named by its own path rather than by
+/// the path it points to. The path (or any of its parents) does not have to exist: the longest
+/// existing prefix of it is canonicalized, and the rest of it is appended as is.
+pub fn absolute_path(path: &Path) -> io::Result<PathBuf> {
+    let path = if path.is_absolute() { path.to_owned() } else { env::current_dir()?.join(path) };
+
+    match (path.parent(), path.components().next_back()) {
+        (Some(parent), Some(Component::Normal(name))) => Ok(canonicalize_existing(parent)?.join(name)),
+        _ => canonicalize_existing(&path)
+    }
+}
+
+fn canonicalize_existing(path: &Path) -> io::Result<PathBuf> {
+    let mut existing_prefix = path;
+    let mut missing_components = Vec::new();
+
+    loop {
+        match canonicalize(existing_prefix) {
+            Ok(mut resolved_path) => {
+                for component in missing_components.into_iter().rev() {
+                    match component {
+                        Component::ParentDir => { resolved_path.pop(); }
+                        Component::Normal(name) => resolved_path.push(name),
+                        _ => {}
+                    }
+                }
+
+                return Ok(resolved_path);
+            }
+
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => {
+                missing_components.push(existing_prefix.components().next_back().unwrap());
+                existing_prefix = existing_prefix.parent().ok_or_else(|| io::Error::new(
+                    io::ErrorKind::NotFound, format!("None of {:?} exists", path)
+                ))?;
+            }
+
+            Err(e) => return Err(e)
+        }
+    }
+}
+
+/// Follows a path through any symlinks until it reaches something that is not a symlink, which
+/// does not need to exist. Paths that are not symlinks are returned as they are.
+pub fn follow_symlinks(path: &Path) -> io::Result<PathBuf> {
+    let mut path = path.to_owned();
+
+    for _ in 0..MAX_SYMLINK_HOPS {
+        let link_target = match read_link(&path) {
+            Ok(link_target) => link_target,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound || e.kind() == io::ErrorKind::InvalidInput => {
+                return Ok(path);
+            }
+            Err(e) => return Err(e)
+        };
+
+        let link_target = match path.parent() {
+            Some(parent) => parent.join(link_target),
+            None => link_target
+        };
+
+        path = absolute_path(&link_target)?;
+    }
+
+    Err(io::Error::other(format!("Too many levels of symbolic links at {:?}", path)))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs::{canonicalize, create_dir, write};
+    use std::os::unix::fs::symlink;
+    use std::path::Path;
+
+    use utils::test_dir::TestDir;
+
+    use super::{absolute_path, follow_symlinks};
+
+    #[test]
+    fn absolute_path_resolves_parents_but_not_the_last_component() {
+        let test_dir = TestDir::new("paths");
+        let root = canonicalize(&*test_dir).unwrap();
+
+        create_dir(root.join("real")).unwrap();
+        write(root.join("real/file"), "").unwrap();
+        symlink(root.join("real"), root.join("dir-link")).unwrap();
+        symlink(root.join("real/file"), root.join("real/file-link")).unwrap();
+
+        assert_eq!(absolute_path(&test_dir.join("dir-link/file")).unwrap(), root.join("real/file"));
+        assert_eq!(absolute_path(&test_dir.join("dir-link/file-link")).unwrap(), root.join("real/file-link"));
+        assert_eq!(absolute_path(&test_dir.join("dir-link/./file")).unwrap(), root.join("real/file"));
+        assert_eq!(absolute_path(&test_dir.join("dir-link/missing/deeper")).unwrap(), root.join("real/missing/deeper"));
+
+        let relative = Path::new("hippo-paths-test-not-there");
+        assert_eq!(absolute_path(relative).unwrap(), canonicalize(env::current_dir().unwrap()).unwrap().join(relative));
+    }
+
+    #[test]
+    fn follow_symlinks_reaches_the_final_target() {
+        let test_dir = TestDir::new("paths");
+        let root = canonicalize(&*test_dir).unwrap();
+
+        create_dir(root.join("real")).unwrap();
+        write(root.join("real/file"), "").unwrap();
+        symlink("real/file", root.join("relative-link")).unwrap();
+        symlink(root.join("relative-link"), root.join("chained-link")).unwrap();
+        symlink("real/missing", root.join("dangling-link")).unwrap();
+        symlink("loop-b", root.join("loop-a")).unwrap();
+        symlink("loop-a", root.join("loop-b")).unwrap();
+
+        assert_eq!(follow_symlinks(&root.join("real/file")).unwrap(), root.join("real/file"));
+        assert_eq!(follow_symlinks(&root.join("relative-link")).unwrap(), root.join("real/file"));
+        assert_eq!(follow_symlinks(&root.join("chained-link")).unwrap(), root.join("real/file"));
+        assert_eq!(follow_symlinks(&root.join("dangling-link")).unwrap(), root.join("real/missing"));
+        assert!(follow_symlinks(&root.join("loop-a")).is_err());
+    }
+}
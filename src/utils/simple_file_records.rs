@@ -1,73 +1,222 @@
+extern crate sha2;
+
 use std::path::PathBuf;
-use std::fs::{File, OpenOptions, create_dir_all};
-use std::io::{BufReader, BufRead, Write};
+use std::fs::{File, OpenOptions, create_dir_all, rename, remove_file};
+use std::io::{Read, Write};
 use std::collections::HashMap;
+use std::{io, process};
+
+use self::sha2::{Digest, Sha256};
+
+/// Every record file starts with this prefix followed by the version of the format it is in
+static FORMAT_HEADER_PREFIX: &str = "#hippo-records v";
+static CURRENT_FORMAT_VERSION: u32 = 2;
+static RECORD_CHECKSUM_LENGTH: usize = 8;
 
 pub struct SimpleRecord {
     pub key: String,
     pub value: String
 }
 
-pub trait MapsToSimpleRecord {
-    fn from(simple_record: SimpleRecord) -> Self;
+pub trait MapsToSimpleRecord: Sized {
+    /// Returns `None` if the record could not be interpreted
+    fn from(simple_record: SimpleRecord) -> Option<Self>;
     fn to(&self) -> SimpleRecord;
 }
 
+#[derive(Debug, PartialEq)]
+pub enum RejectionReason {
+    /// The line could not be parsed, failed its checksum, or held a value that could not be read
+    Corrupt,
+    /// The line was superseded by a later line with the same key
+    DuplicateKey(String),
+    /// The file is in a format this version of hippo cannot read (given as the reason), so none
+    /// of its lines could be
+    UnsupportedFormat(String)
+}
+
+/// A line from the backing file that did not make it into the records.
+#[derive(Debug)]
+pub struct RejectedLine {
+    pub line_number: usize,
+    pub content: String,
+    pub reason: RejectionReason
+}
+
 #[derive(Debug)]
 pub struct SimpleFileRecords<T: MapsToSimpleRecord> {
     dirty: bool,
+    format_version: u32,
     file_path: PathBuf,
     pub name: String,
-    pub records: HashMap<String, T>
+    pub records: HashMap<String, T>,
+    /// Lines that were skipped while reading. As long as there are any, the records are not
+    /// written back, since that would silently drop these lines.
+    pub rejected_lines: Vec<RejectedLine>
 }
 
 impl<T: MapsToSimpleRecord> Drop for SimpleFileRecords<T> {
     fn drop(&mut self) {
-        self.write()
+        // Owners are expected to write (and handle failures) explicitly; this is only a last
+        // resort so that changes are not silently lost.
+        if let Err(e) = self.write() {
+            error!("Could not write record set `{}` to {:?}: {}", self.name, self.file_path, e);
+        }
     }
 }
 
 impl<T: MapsToSimpleRecord> SimpleFileRecords<T> {
-    pub fn new(record_set_name: String, file_path: PathBuf) -> Self {
-        match file_path.parent() {
-            Some(prefix) => {
-                info!("Ensuring that a directory exists for {} (probing: {:?})", record_set_name, prefix);
-
-                create_dir_all(prefix)
-                    .expect(format!("Could not probe directory (or create) for journal {}", record_set_name).as_str());
-            }
+    /// Opens (or creates) the record file. A file in a format that cannot be read, such as one
+    /// written by a newer version of hippo, is opened with every line rejected: nothing can be
+    /// written back to it, but it can still be inspected and repaired by `hippo fsck`.
+    pub fn new(record_set_name: String, file_path: PathBuf) -> io::Result<Self> {
+        if let Some(prefix) = file_path.parent() {
+            info!("Ensuring that a directory exists for {} (probing: {:?})", record_set_name, prefix);
 
-            None => {}
+            create_dir_all(prefix)?;
         }
 
         info!("Creating (or opening if already exists) for {} (probing: {:?})", record_set_name, file_path);
 
-        let f = OpenOptions::new()
+        let mut f = OpenOptions::new()
             .read(true)
             .write(true)
             .create(true)
-            .open(&file_path).expect("Error opening/creating journal file");
+            .truncate(false)
+            .open(&file_path)?;
+
+        let mut contents = Vec::new();
+        f.read_to_end(&mut contents)?;
+
+        // Lines are decoded one by one, so that invalid UTF-8 only affects the line it is in
+        let mut lines = contents.split(|byte| *byte == b'\n')
+            .map(|line| String::from_utf8(line.to_vec()).map_err(|e| String::from_utf8_lossy(e.as_bytes()).into_owned()))
+            .enumerate()
+            .map(|(index, line)| (index + 1, line))
+            .filter(|(_, line)| !matches!(line, Ok(line) if line.is_empty()))
+            .peekable();
+
+        // Files without a header were written before the format was versioned. Empty files
+        // have not been written yet, and are as good as being in the current format.
+        let format_version = match lines.peek() {
+            Some(&(_, Ok(ref header))) if header.starts_with(FORMAT_HEADER_PREFIX) => {
+                match header[FORMAT_HEADER_PREFIX.len()..].trim().parse::<u32>() {
+                    Ok(version) if version <= CURRENT_FORMAT_VERSION => Ok(version),
+                    Ok(version) => Err(format!("format v{} is newer than what this version of hippo \
+                                                understands (v{})", version, CURRENT_FORMAT_VERSION)),
+                    Err(_) => Err(format!("unrecognized header `{}`", header))
+                }
+            }
+
+            Some(_) => Ok(1),
+            None => Ok(CURRENT_FORMAT_VERSION)
+        };
+
+        let format_version = match format_version {
+            Ok(format_version) => format_version,
+            Err(reason) => {
+                warn!("Cannot read journal {}: {}", record_set_name, reason);
+
+                let rejected_lines = lines
+                    .map(|(line_number, line)| RejectedLine {
+                        line_number,
+                        content: match line { Ok(content) => content, Err(content) => content },
+                        reason: RejectionReason::UnsupportedFormat(reason.to_owned())
+                    })
+                    .collect();
+
+                return Ok(SimpleFileRecords {
+                    dirty: false,
+                    format_version: CURRENT_FORMAT_VERSION,
+                    name: record_set_name,
+                    file_path,
+                    records: HashMap::new(),
+                    rejected_lines
+                });
+            }
+        };
+
+        if format_version != 1 {
+            lines.next();
+        }
 
-        let file = BufReader::new(&f);
         let mut records = HashMap::new();
+        let mut record_origins: HashMap<String, (usize, String)> = HashMap::new();
+        let mut rejected_lines = Vec::new();
+
+        for (line_number, line) in lines {
+            let record = line.as_ref().ok()
+                .and_then(|line| match format_version {
+                    1 => parse_v1_record(line),
+                    _ => parse_v2_record(line)
+                })
+                .and_then(|simple_record| {
+                    let key = simple_record.key.to_owned();
+                    T::from(simple_record).map(|record| (key, record))
+                });
+
+            let content = match line { Ok(content) => content, Err(content) => content };
 
-        for line in file.lines() {
-            let line_string = line.unwrap();
+            match record {
+                Some((key, record)) => {
+                    if let Some((superseded_line_number, superseded_content)) = record_origins.remove(&key) {
+                        rejected_lines.push(RejectedLine {
+                            line_number: superseded_line_number,
+                            content: superseded_content,
+                            reason: RejectionReason::DuplicateKey(key.to_owned())
+                        });
+                    }
 
-            // This should be checked for an overall format, if the number could be processed,
-            // because as of now this would totally work even for six spaces.
-            if line_string.len() > 6 {
-                let key_len = line_string[..6].parse::<usize>().unwrap();
-                let key : String = line_string.chars().skip(6).take(key_len).collect();
-                let value : String = line_string.chars().skip(6 + key_len).collect();
+                    record_origins.insert(key.to_owned(), (line_number, content));
+                    records.insert(key, record);
+                }
 
-                records.insert(key.to_owned(), T::from(SimpleRecord {
-                    key, value
-                }));
+                None => {
+                    warn!("Rejecting corrupt record on line {} of journal {}", line_number, record_set_name);
+                    rejected_lines.push(RejectedLine { line_number, content, reason: RejectionReason::Corrupt });
+                }
             }
         }
 
-        SimpleFileRecords { dirty: false, name: record_set_name, file_path, records }
+        Ok(SimpleFileRecords { dirty: false, format_version, name: record_set_name, file_path, records, rejected_lines })
+    }
+
+    /// Drops the rejected lines, allowing the records to be written again. The file is
+    /// rewritten on the next write even if no records changed, so that the lines are removed
+    /// from it.
+    pub fn discard_rejected_lines(&mut self) -> Vec<RejectedLine> {
+        if !self.rejected_lines.is_empty() {
+            self.dirty = true;
+        }
+
+        self.rejected_lines.drain(..).collect()
+    }
+
+    /// Rewrites the backing file in the current format if it was written in an older one.
+    /// Returns whether anything had to be rewritten.
+    pub fn upgrade(&mut self) -> io::Result<bool> {
+        if self.format_version >= CURRENT_FORMAT_VERSION {
+            return Ok(false);
+        }
+
+        info!("Upgrading record set `{}` from format v{} to v{}", self.name, self.format_version, CURRENT_FORMAT_VERSION);
+
+        self.dirty = true;
+        self.write()?;
+        self.format_version = CURRENT_FORMAT_VERSION;
+
+        Ok(true)
+    }
+
+    /// Whether anything has changed since the records were read or last written
+    pub fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    /// Keeps the changes made so far from being written, for records that are only read, such
+    /// as under a shared lock. They are still written along with any changes made later.
+    pub fn keep_changes_in_memory(&mut self) {
+        self.dirty = false;
     }
 
     #[allow(dead_code)]
@@ -80,36 +229,291 @@ impl<T: MapsToSimpleRecord> SimpleFileRecords<T> {
         self.records.get(key).unwrap()
     }
 
-    /*
-    pub fn remove(&mut self, entry: T) {
-        self.dirty = true;
-        self.records.remove(entry)
+    pub fn remove(&mut self, key: &str) -> Option<T> {
+        let removed = self.records.remove(key);
+
+        if removed.is_some() {
+            self.dirty = true;
+        }
+
+        removed
     }
-    */
 
-    pub fn write(&self) {
+    /// Writes all records to the backing file if anything has changed since it was read or last
+    /// written. The records are written to a temporary file next to it, which is synced and then
+    /// renamed over the original, so a crash midway leaves either the old or the new file intact.
+    pub fn write(&mut self) -> io::Result<()> {
         if !self.dirty {
             info!("No changes were made to record set `{}`, not writing anything to file", self.name);
+            return Ok(());
+        }
+
+        if !self.rejected_lines.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!(
+                "{} has {} corrupt or duplicate records, run `hippo fsck --repair` before making changes",
+                self.file_path.to_string_lossy(), self.rejected_lines.len()
+            )));
+        }
+
+        let staging_file_path = self.file_path.with_file_name(format!(
+            ".{}.{}", self.file_path.file_name().unwrap().to_string_lossy(), process::id()
+        ));
+
+        info!("Writing record set `{}` to {:?} (staging at {:?})", self.name, self.file_path, staging_file_path);
+
+        let written = self.write_records_to(&staging_file_path)
+            .and_then(|_| rename(&staging_file_path, &self.file_path));
+
+        if let Err(e) = written {
+            let _ = remove_file(&staging_file_path);
+            return Err(e);
+        }
+
+        if let Some(parent) = self.file_path.parent() {
+            File::open(parent)?.sync_all()?;
         }
 
-        let file_name_as_str = self.file_path.to_str().unwrap();
+        self.dirty = false;
+        Ok(())
+    }
 
-        let mut f = File::create(&self.file_path)
-            .expect(format!("Could not open file to write {}", file_name_as_str).as_str());
+    fn write_records_to(&self, file_path: &PathBuf) -> io::Result<()> {
+        let mut f = File::create(file_path)?;
+        let mut keys: Vec<&String> = self.records.keys().collect();
+        keys.sort();
 
-        for (_, record) in &self.records {
-            let simple_record : SimpleRecord = record.to();
-            let mut writable_record = String::new();
+        f.write_all(format!("{}{}\n", FORMAT_HEADER_PREFIX, CURRENT_FORMAT_VERSION).as_bytes())?;
 
-            writable_record.push_str(format!("{:06}", simple_record.key.len()).as_str());
-            writable_record.push_str(simple_record.key.as_str());
-            writable_record.push_str(simple_record.value.as_str());
-            writable_record.push_str("\n");
+        for key in keys {
+            let simple_record : SimpleRecord = self.records[key].to();
+            let body = format!("{}\t{}", escape(&simple_record.key), escape(&simple_record.value));
 
-            f.write_all(writable_record.into_bytes().as_slice())
-                .expect(format!("Could not write to file {}", file_name_as_str).as_str());
+            f.write_all(format!("{}\t{}\n", checksum(&body), body).as_bytes())?;
         }
 
-        f.write_all(b"\n").expect(format!("Could not write to file {}", file_name_as_str).as_str());
+        f.sync_all()
+    }
+}
+
+/// Records written before the format was versioned: a six digit key length (in bytes), the key
+/// and then the value, with no escaping.
+fn parse_v1_record(line: &str) -> Option<SimpleRecord> {
+    let key_len = line.get(..6)?.parse::<usize>().ok()?;
+    let key = line.get(6..6 + key_len)?;
+    let value = line.get(6 + key_len..)?;
+
+    Some(SimpleRecord { key: key.to_owned(), value: value.to_owned() })
+}
+
+/// A checksum of the rest of the record, followed by the escaped key and value, all separated
+/// by tabs.
+fn parse_v2_record(line: &str) -> Option<SimpleRecord> {
+    let (record_checksum, body) = line.split_once('\t')?;
+
+    if checksum(body) != record_checksum {
+        return None;
+    }
+
+    let (key, value) = body.split_once('\t')?;
+    let (key, value) = (unescape(key)?, unescape(value)?);
+
+    Some(SimpleRecord { key, value })
+}
+
+fn checksum(body: &str) -> String {
+    format!("{:x}", Sha256::digest(body.as_bytes()))[..RECORD_CHECKSUM_LENGTH].to_owned()
+}
+
+fn escape(field: &str) -> String {
+    let mut escaped = String::with_capacity(field.len());
+
+    for c in field.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\t' => escaped.push_str("\\t"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            c => escaped.push(c)
+        }
+    }
+
+    escaped
+}
+
+fn unescape(field: &str) -> Option<String> {
+    let mut unescaped = String::with_capacity(field.len());
+    let mut chars = field.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+
+        match chars.next()? {
+            '\\' => unescaped.push('\\'),
+            't' => unescaped.push('\t'),
+            'n' => unescaped.push('\n'),
+            'r' => unescaped.push('\r'),
+            _ => return None
+        }
+    }
+
+    Some(unescaped)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{create_dir, read_dir, read_to_string, remove_dir_all, remove_file, write};
+
+    use utils::test_dir::TestDir;
+
+    use super::{checksum, MapsToSimpleRecord, RejectionReason, SimpleFileRecords, SimpleRecord};
+
+    struct Entry {
+        key: String,
+        value: String
+    }
+
+    impl MapsToSimpleRecord for Entry {
+        fn from(simple_record: SimpleRecord) -> Option<Self> {
+            Some(Entry { key: simple_record.key, value: simple_record.value })
+        }
+
+        fn to(&self) -> SimpleRecord {
+            SimpleRecord { key: self.key.to_owned(), value: self.value.to_owned() }
+        }
+    }
+
+    fn v2_line(key: &str, value: &str) -> String {
+        let body = format!("{}\t{}", key, value);
+        format!("{}\t{}", checksum(&body), body)
+    }
+
+    fn open(test_dir: &TestDir, contents: &str) -> SimpleFileRecords<Entry> {
+        let file_path = test_dir.join("records");
+        write(&file_path, contents).unwrap();
+
+        SimpleFileRecords::new(String::from("records"), file_path).unwrap()
+    }
+
+    #[test]
+    fn keys_and_values_with_special_characters_round_trip() {
+        let test_dir = TestDir::new("records");
+        let file_path = test_dir.join("records");
+        let key = "a\tkey\\with\nall\rof them";
+        let value = "\\t is not a tab\t\r\n\\\\";
+
+        {
+            let mut records = SimpleFileRecords::new(String::from("records"), file_path.to_owned()).unwrap();
+            records.add(Entry { key: key.to_owned(), value: value.to_owned() });
+            records.write().unwrap();
+        }
+
+        assert_eq!(read_to_string(&file_path).unwrap().lines().count(), 2);
+
+        let records = SimpleFileRecords::<Entry>::new(String::from("records"), file_path).unwrap();
+        assert!(records.rejected_lines.is_empty());
+        assert_eq!(records.records[key].value, value);
+    }
+
+    #[test]
+    fn writes_replace_the_file_without_leaving_anything_behind() {
+        let test_dir = TestDir::new("records");
+        let file_path = test_dir.join("records");
+        let mut records = open(&test_dir, &format!("#hippo-records v2\n{}\n", v2_line("key", "old")));
+
+        records.add(Entry { key: String::from("key"), value: String::from("new") });
+        records.write().unwrap();
+
+        assert_eq!(read_to_string(&file_path).unwrap(), format!("#hippo-records v2\n{}\n", v2_line("key", "new")));
+        assert_eq!(read_dir(&*test_dir).unwrap().count(), 1);
+
+        // If the file cannot be replaced, the staged copy is cleaned up
+        remove_file(&file_path).unwrap();
+        create_dir(&file_path).unwrap();
+        write(file_path.join("in the way"), "").unwrap();
+
+        records.add(Entry { key: String::from("key"), value: String::from("newer") });
+        assert!(records.write().is_err());
+        assert_eq!(read_dir(&*test_dir).unwrap().count(), 1);
+
+        remove_dir_all(&file_path).unwrap();
+        records.write().unwrap();
+        assert_eq!(read_to_string(&file_path).unwrap(), format!("#hippo-records v2\n{}\n", v2_line("key", "newer")));
+    }
+
+    #[test]
+    fn line_with_a_wrong_checksum_is_rejected_as_corrupt() {
+        let test_dir = TestDir::new("records");
+        let records = open(&test_dir, &format!("#hippo-records v2\n{}\n00000000\tkey\tvalue\n", v2_line("good", "1")));
+
+        assert_eq!(records.records.len(), 1);
+        assert_eq!(records.rejected_lines.len(), 1);
+        assert_eq!(records.rejected_lines[0].line_number, 3);
+        assert_eq!(records.rejected_lines[0].reason, RejectionReason::Corrupt);
+    }
+
+    #[test]
+    fn line_with_a_bad_escape_is_rejected_as_corrupt() {
+        let test_dir = TestDir::new("records");
+        let records = open(&test_dir, &format!("#hippo-records v2\n{}\n", v2_line("key", "bad \\x escape")));
+
+        assert!(records.records.is_empty());
+        assert_eq!(records.rejected_lines.len(), 1);
+        assert_eq!(records.rejected_lines[0].reason, RejectionReason::Corrupt);
+    }
+
+    #[test]
+    fn duplicate_key_keeps_the_later_line() {
+        let test_dir = TestDir::new("records");
+        let records = open(&test_dir, &format!("#hippo-records v2\n{}\n{}\n", v2_line("key", "earlier"),
+                                               v2_line("key", "later")));
+
+        assert_eq!(records.records["key"].value, "later");
+        assert_eq!(records.rejected_lines.len(), 1);
+        assert_eq!(records.rejected_lines[0].line_number, 2);
+        assert_eq!(records.rejected_lines[0].reason, RejectionReason::DuplicateKey(String::from("key")));
+    }
+
+    #[test]
+    fn headerless_v1_file_is_read_and_upgraded() {
+        let test_dir = TestDir::new("records");
+        let file_path = test_dir.join("records");
+
+        {
+            let mut records = open(&test_dir, "000003keyvalue\twith a tab\n000005otherx\n");
+            assert!(records.rejected_lines.is_empty());
+            assert_eq!(records.records["key"].value, "value\twith a tab");
+            assert_eq!(records.records["other"].value, "x");
+
+            assert!(records.upgrade().unwrap());
+            assert!(!records.upgrade().unwrap());
+        }
+
+        let contents = read_to_string(&file_path).unwrap();
+        assert!(contents.starts_with("#hippo-records v2\n"));
+        assert!(contents.contains(&v2_line("key", "value\\twith a tab")));
+
+        let mut records = SimpleFileRecords::<Entry>::new(String::from("records"), file_path).unwrap();
+        assert!(records.rejected_lines.is_empty());
+        assert_eq!(records.records["key"].value, "value\twith a tab");
+        assert!(!records.upgrade().unwrap());
+    }
+
+    #[test]
+    fn newer_format_rejects_every_line_and_refuses_writes() {
+        let test_dir = TestDir::new("records");
+        let contents = format!("#hippo-records v3\n{}\n", v2_line("key", "value"));
+        let mut records = open(&test_dir, &contents);
+
+        assert!(records.records.is_empty());
+        assert_eq!(records.rejected_lines.len(), 2);
+        assert!(records.rejected_lines.iter()
+            .all(|rejected_line| matches!(rejected_line.reason, RejectionReason::UnsupportedFormat(_))));
+
+        records.add(Entry { key: String::from("new"), value: String::from("record") });
+        assert!(records.write().is_err());
+        assert_eq!(read_to_string(test_dir.join("records")).unwrap(), contents);
     }
 }
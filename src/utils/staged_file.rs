@@ -0,0 +1,324 @@
+extern crate uuid;
+
+use std::fs::{copy, create_dir, hard_link, metadata, read_link, remove_dir, remove_file, rename, set_permissions,
+              symlink_metadata, File};
+use std::io;
+use std::io::Write;
+use std::mem;
+use std::os::unix::fs::symlink;
+use std::path::{Path, PathBuf};
+
+use self::uuid::Uuid;
+
+use super::file_metadata::FileMetadata;
+
+/// Changes to a number of files that are applied together, or not at all. New contents are first
+/// written to staging files next to the files they replace, so that nothing on disk changes until
+/// everything has been staged, and applying the changes is then just renaming each staging file
+/// over its target.
+///
+/// Before a file is replaced (or removed), a safety copy of it is kept next to it, as a hard link
+/// wherever possible so that it takes up no space. Together these make up the safety snapshot of
+/// the change: if applying any of the changes fails, every file that was already replaced can be
+/// restored from it with `roll_back`.
+pub struct StagedChanges {
+    changes: Vec<StagedChange>,
+    /// Number of changes (from the start) that have been applied
+    applied: usize,
+    /// Directories created while staging, so that they can be removed again on rollback
+    created_dirs: Vec<PathBuf>
+}
+
+struct StagedChange {
+    target_file: PathBuf,
+    /// The file that is renamed over the target, or `None` if the target is removed
+    staging_file: Option<PathBuf>,
+    /// The copy of the target as it was before the change was applied, if it existed
+    safety_copy: Option<PathBuf>
+}
+
+/// A change that could not be applied
+pub struct FailedChange {
+    pub target_file: PathBuf,
+    pub error: io::Error
+}
+
+/// What happened to a file that had already been changed when the changes were rolled back
+pub enum RollbackOutcome {
+    /// The file was restored from its safety copy
+    Restored,
+    /// The file did not exist before, and was removed again
+    Removed,
+    /// The file could not be restored. If it existed before, its safety copy is left in place.
+    Failed { error: io::Error, safety_copy: Option<PathBuf> }
+}
+
+impl StagedChanges {
+    pub fn new() -> StagedChanges {
+        StagedChanges { changes: Vec::new(), applied: 0, created_dirs: Vec::new() }
+    }
+
+    /// Stages new contents for a file. If metadata is given it is applied to the staged file right
+    /// away, otherwise the staged file takes over the permissions of the file it replaces (if that
+    /// exists). Returns `false` if the recorded ownership could not be applied because the process
+    /// is not allowed to change it.
+    pub fn stage_file(&mut self, target_file: &Path, content: &[u8], file_metadata: Option<&FileMetadata>) -> io::Result<bool> {
+        let staging_file = sibling_path(target_file, "staged")?;
+
+        info!("Staging new contents of {:?} at {:?}", target_file, staging_file);
+
+        let staged = File::create(&staging_file)
+            .and_then(|mut f| {
+                f.write_all(content)?;
+                f.sync_all()
+            })
+            .and_then(|_| match file_metadata {
+                Some(file_metadata) => file_metadata.apply(&staging_file),
+                None => match metadata(target_file) {
+                    Ok(target_metadata) => set_permissions(&staging_file, target_metadata.permissions()).map(|_| true),
+                    Err(_) => Ok(true)
+                }
+            });
+
+        match staged {
+            Ok(ownership_applied) => {
+                self.push(target_file, Some(staging_file));
+                Ok(ownership_applied)
+            }
+
+            Err(e) => {
+                let _ = remove_file(&staging_file);
+                Err(e)
+            }
+        }
+    }
+
+    /// Stages a symlink pointing at the given path, which replaces the target file (or symlink)
+    pub fn stage_symlink(&mut self, target_file: &Path, link_target: &Path) -> io::Result<()> {
+        let staging_file = sibling_path(target_file, "staged")?;
+
+        info!("Staging symlink to {:?} for {:?} at {:?}", link_target, target_file, staging_file);
+
+        symlink(link_target, &staging_file)?;
+        self.push(target_file, Some(staging_file));
+
+        Ok(())
+    }
+
+    /// Stages the removal of a file (or symlink)
+    pub fn stage_removal(&mut self, target_file: &Path) -> io::Result<()> {
+        info!("Staging removal of {:?}", target_file);
+
+        // Fail now rather than halfway through applying, if no safety copy could be named
+        sibling_path(target_file, "safety")?;
+        self.push(target_file, None);
+
+        Ok(())
+    }
+
+    /// Creates a directory along with any missing parents, which staging files inside it needs.
+    /// Directories created this way are removed again if the changes are rolled back.
+    pub fn create_dir_all(&mut self, dir: &Path) -> io::Result<()> {
+        let mut missing_dirs = Vec::new();
+        let mut existing_prefix = Some(dir);
+
+        while let Some(path) = existing_prefix {
+            if symlink_metadata(path).is_ok() {
+                break;
+            }
+
+            missing_dirs.push(path.to_owned());
+            existing_prefix = path.parent();
+        }
+
+        for missing_dir in missing_dirs.into_iter().rev() {
+            create_dir(&missing_dir)?;
+            self.created_dirs.push(missing_dir);
+        }
+
+        Ok(())
+    }
+
+    /// The staging file that is to replace the given target, if any
+    pub fn staging_file(&self, target_file: &Path) -> Option<&Path> {
+        self.changes.iter().rev()
+            .find(|change| change.target_file == target_file)
+            .and_then(|change| change.staging_file.as_deref())
+    }
+
+    /// Applies the staged changes in the order they were staged, keeping a safety copy of every
+    /// file before it is changed. Stops at the first change that fails, leaving the ones applied
+    /// so far in place; use `roll_back` to undo them.
+    pub fn apply(&mut self) -> Result<(), FailedChange> {
+        while self.applied < self.changes.len() {
+            let change = &mut self.changes[self.applied];
+
+            apply_change(change).map_err(|error| FailedChange {
+                target_file: change.target_file.to_owned(),
+                error
+            })?;
+
+            self.applied += 1;
+        }
+
+        Ok(())
+    }
+
+    /// Undoes the changes that were applied, newest first, and throws away the ones that were
+    /// not. Returns what happened to each of the files that had been changed.
+    pub fn roll_back(mut self) -> Vec<(PathBuf, RollbackOutcome)> {
+        let changes = mem::take(&mut self.changes);
+        let (applied_changes, unapplied_changes) = changes.split_at(self.applied);
+        let mut outcomes = Vec::new();
+
+        for change in applied_changes.iter().rev() {
+            info!("Rolling back the change to {:?}", change.target_file);
+
+            let outcome = match change.safety_copy {
+                Some(ref safety_copy) => match rename(safety_copy, &change.target_file) {
+                    Ok(_) => RollbackOutcome::Restored,
+                    Err(error) => RollbackOutcome::Failed { error, safety_copy: Some(safety_copy.to_owned()) }
+                },
+
+                None => match remove_file(&change.target_file) {
+                    Ok(_) => RollbackOutcome::Removed,
+                    Err(ref e) if e.kind() == io::ErrorKind::NotFound => RollbackOutcome::Removed,
+                    Err(error) => RollbackOutcome::Failed { error, safety_copy: None }
+                }
+            };
+
+            outcomes.push((change.target_file.to_owned(), outcome));
+        }
+
+        discard_staging_files(unapplied_changes);
+
+        for created_dir in self.created_dirs.iter().rev() {
+            let _ = remove_dir(created_dir);
+        }
+
+        outcomes
+    }
+
+    /// Removes the safety copies once the changes no longer need to be rolled back
+    pub fn finish(mut self) {
+        for change in mem::take(&mut self.changes) {
+            if let Some(safety_copy) = change.safety_copy {
+                if let Err(e) = remove_file(&safety_copy) {
+                    info!("Could not remove safety copy {:?}: {}", safety_copy, e);
+                }
+            }
+        }
+    }
+
+    fn push(&mut self, target_file: &Path, staging_file: Option<PathBuf>) {
+        self.changes.push(StagedChange { target_file: target_file.to_owned(), staging_file, safety_copy: None });
+    }
+}
+
+impl Default for StagedChanges {
+    fn default() -> Self { StagedChanges::new() }
+}
+
+/// Staging files are never left behind, even if the changes are dropped without being applied
+impl Drop for StagedChanges {
+    fn drop(&mut self) {
+        discard_staging_files(&self.changes[self.applied.min(self.changes.len())..]);
+    }
+}
+
+fn apply_change(change: &mut StagedChange) -> io::Result<()> {
+    if symlink_metadata(&change.target_file).is_ok() {
+        change.safety_copy = Some(keep_safety_copy(&change.target_file)?);
+    }
+
+    let applied = match change.staging_file {
+        Some(ref staging_file) => rename(staging_file, &change.target_file),
+        None => remove_file(&change.target_file)
+    };
+
+    // The target is untouched, so its safety copy is not needed
+    if applied.is_err() {
+        if let Some(safety_copy) = change.safety_copy.take() {
+            if let Err(e) = remove_file(&safety_copy) {
+                info!("Could not remove safety copy {:?}: {}", safety_copy, e);
+            }
+        }
+    }
+
+    applied
+}
+
+/// Keeps a copy of a file next to it, as a hard link if the filesystem supports them
+fn keep_safety_copy(target_file: &Path) -> io::Result<PathBuf> {
+    let safety_copy = sibling_path(target_file, "safety")?;
+
+    info!("Keeping a safety copy of {:?} at {:?}", target_file, safety_copy);
+
+    if let Err(e) = hard_link(target_file, &safety_copy) {
+        info!("Could not hard link {:?} ({}), copying it instead", target_file, e);
+
+        if symlink_metadata(target_file)?.file_type().is_symlink() {
+            symlink(read_link(target_file)?, &safety_copy)?;
+        } else {
+            copy(target_file, &safety_copy)?;
+        }
+    }
+
+    Ok(safety_copy)
+}
+
+fn discard_staging_files(changes: &[StagedChange]) {
+    for staging_file in changes.iter().filter_map(|change| change.staging_file.as_ref()) {
+        let _ = remove_file(staging_file);
+    }
+}
+
+fn sibling_path(target_file: &Path, purpose: &str) -> io::Result<PathBuf> {
+    let target_file_name = target_file.file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Cannot replace a path without a file name"))?
+        .to_string_lossy()
+        .into_owned();
+
+    Ok(target_file.with_file_name(format!(".{}.hippo-{}-{}", target_file_name, purpose, Uuid::new_v4())))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{read_dir, read_to_string, remove_file, write};
+
+    use utils::test_dir::TestDir;
+
+    use super::{RollbackOutcome, StagedChanges};
+
+    #[test]
+    fn failing_second_change_rolls_back_the_first() {
+        let test_dir = TestDir::new("staged-file");
+        let first_file = test_dir.join("first.conf");
+        let second_file = test_dir.join("second.conf");
+
+        write(&first_file, "first\n").unwrap();
+        write(&second_file, "second\n").unwrap();
+
+        let mut staged_changes = StagedChanges::new();
+        assert!(staged_changes.stage_file(&first_file, b"first, changed\n", None).is_ok());
+        assert!(staged_changes.stage_file(&second_file, b"second, changed\n", None).is_ok());
+
+        // Renaming the second staging file over its target fails once it is gone
+        remove_file(staged_changes.staging_file(&second_file).unwrap()).unwrap();
+
+        let failed_change = staged_changes.apply().err().unwrap();
+        assert_eq!(failed_change.target_file, second_file);
+        assert_eq!(read_to_string(&first_file).unwrap(), "first, changed\n");
+
+        let outcomes = staged_changes.roll_back();
+        assert_eq!(outcomes.len(), 1);
+        assert_eq!(outcomes[0].0, first_file);
+        assert!(matches!(outcomes[0].1, RollbackOutcome::Restored));
+
+        assert_eq!(read_to_string(&first_file).unwrap(), "first\n");
+        assert_eq!(read_to_string(&second_file).unwrap(), "second\n");
+
+        // Neither safety copies nor staging files are left behind
+        assert_eq!(read_dir(&*test_dir).unwrap().count(), 2);
+    }
+}
@@ -0,0 +1,49 @@
+extern crate uuid;
+
+use std::env;
+use std::fs::{create_dir_all, remove_dir_all};
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+
+use journaling::journal::Journal;
+use journaling::store_lock::LockMode;
+
+use self::uuid::Uuid;
+
+/// A scratch directory for a test, removed along with everything in it when dropped, so that
+/// nothing is left behind even when an assertion fails. Declare it before any journal opened in
+/// it, so that the journal (and its locks) is dropped first.
+pub struct TestDir {
+    path: PathBuf
+}
+
+impl TestDir {
+    pub fn new(name: &str) -> TestDir {
+        let path = env::temp_dir().join(format!("hippo-{}-test-{}", name, Uuid::new_v4()));
+        create_dir_all(&path).unwrap();
+
+        TestDir { path }
+    }
+
+    /// Opens (or initializes) the store inside the directory, locked exclusively
+    pub fn open_store(&self) -> Journal {
+        self.open_store_locked(LockMode::Exclusive)
+    }
+
+    pub fn open_store_locked(&self, lock_mode: LockMode) -> Journal {
+        Journal::initialize(self.path.join("store").to_string_lossy().into_owned(), lock_mode, false)
+            .ok().unwrap()
+    }
+}
+
+impl Deref for TestDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path { &self.path }
+}
+
+impl Drop for TestDir {
+    fn drop(&mut self) {
+        let _ = remove_dir_all(&self.path);
+    }
+}